@@ -35,8 +35,8 @@
 //! | Advanced transformations                              | ✅       | ✅         | ❌             |
 //! | [Space-aware interpolation](#-output)                 | ✅       | ❌         | ❌             |
 //! | Can define [fn-like macros][fn_like_macros]           | ✅       | ✅         | ✅             |
-//! | Can define [derive macros][derive_macros]             | 🚧       | ✅         | ❌             |
-//! | Can define [attribute macros][attribute_macros]       | 🚧       | ✅         | ❌             |
+//! | Can define [derive macros][derive_macros]             | ⚠️       | ✅         | ❌             |
+//! | Can define [attribute macros][attribute_macros]       | ⚠️       | ✅         | ❌             |
 //! | Reusable across modules and crates                    | ✅       | ✅         | ✅             |
 //!
 //! <h5><b>Comfort of life</b></h5>
@@ -66,6 +66,27 @@
 //! # fn main() {}
 //! ```
 //!
+//! Numeric results, both signed and unsigned, are pasted as a single literal expression. This lets
+//! you use `eval!` wherever a const expression is expected, including array lengths and const
+//! generic arguments. Note that Rust requires the latter to be wrapped in braces:
+//!
+//! ```
+//! struct Buffer<const N: usize>([u8; N]);
+//! let bytes = [0_u8; crabtime::eval! { 2 + 3 }];
+//! let buffer = Buffer::<{ crabtime::eval! { 4 * 2 } }>([0; 8]);
+//! # assert_eq!((bytes.len(), buffer.0.len()), (5, 8));
+//! ```
+//!
+//! If the code to evaluate is built by another tool, for example, a templating system, you can pass
+//! it as a string literal to `crabtime::eval_str!`. The string is parsed as Rust code before the
+//! evaluation, and a parse error is reported at the literal. The argument has to be a literal,
+//! macros like `concat!` and constants are not expanded.
+//!
+//! ```
+//! const MY_NUM2: usize = crabtime::eval_str!("(2..=5).product::<usize>()");
+//! # fn main() {}
+//! ```
+//!
 //! <br/>
 //! <br/>
 //!
@@ -106,6 +127,48 @@
 //! # fn main() {}
 //! ```
 //!
+//! To make the macro usable by path within the crate, set its `visibility`. Crabtime emits the
+//! `pub(crate) use gen_positions1;` re-export after the macro definition for you. The `export`
+//! option sets the module path the macro should be reachable at, like `crate::macros`, and
+//! verifies it at compile time. It defaults the visibility to `pub(crate)`, so defining the macro
+//! in that module is enough. Macros defined elsewhere need a `use` in that module. The
+//! `#[macro_export]` attribute wins over both options, as it already makes the macro visible at
+//! the crate root. In such a case, the options are ignored with a warning.
+//!
+//! ```
+//! mod macros {
+//!     #[crabtime::function(export = "crate::macros")]
+//!     fn gen_unit() -> &str {
+//!         "pub struct Unit;"
+//!     }
+//! }
+//!
+//! crate::macros::gen_unit!();
+//! # fn main() {}
+//! ```
+//!
+//! Exported macros are often used in other crates, so the generated code can't refer to items
+//! of the defining crate with `crate::`. Use `$crate` instead, just like in `macro_rules!`.
+//! Crabtime maps it to the `$crate` of the generated macro:
+//!
+//! ```
+//! pub struct Color(pub u8, pub u8, pub u8);
+//!
+//! #[crabtime::function]
+//! #[macro_export]
+//! fn gen_colors(names: Vec<String>) {
+//!     for (ix, name) in names.iter().enumerate() {
+//!         let value = ix * 100;
+//!         crabtime::output! {
+//!             pub const {{name}}: $crate::Color = $crate::Color({{value}}, 0, 0);
+//!         }
+//!     }
+//! }
+//!
+//! gen_colors!(["BLACK", "RED"]);
+//! # fn main() {}
+//! ```
+//!
 //! <br/>
 //!
 //! <div class="warning">
@@ -152,9 +215,62 @@
 //! <br/>
 //!
 //! # 🤩 Attribute and derive macros
-//! Currently, generating [attribute macros][attribute_macros] and [derive macros][derive_macros]
-//! is not supported, but there are several ways to achieve it. If you want to help, ping us on
-//! [GitHub](https://github.com/wdanilo/crabtime).
+//!
+//! Real [derive macros][derive_macros] can only be defined in a separate `proc-macro` crate, so
+//! Crabtime defines derive-like macros instead. A function annotated with `#[crabtime::derive]`
+//! takes the annotated item as `input: TokenStream` and becomes a function-like macro, just like
+//! with `#[crabtime::function]`. Instead of `#[derive(...)]`, apply it with
+//! `#[crabtime::derive_with(...)]`, which keeps the item unchanged and places the output of the
+//! listed macros after it. The usual `#[derive(...)]` attributes can be used next to it.
+//!
+//! ```
+//! trait Describe {
+//!     fn describe() -> &'static str;
+//! }
+//!
+//! #[crabtime::derive]
+//! fn describe(input: TokenStream) -> TokenStream {
+//!     let input = input.to_string();
+//!     let name = input.split_whitespace()
+//!         .skip_while(|t| *t != "struct" && *t != "enum")
+//!         .nth(1)
+//!         .unwrap()
+//!         .to_string();
+//!     format!("
+//!         impl Describe for {name} {{
+//!             fn describe() -> &'static str {{ \"{name}\" }}
+//!         }}
+//!     ").parse().unwrap()
+//! }
+//!
+//! #[crabtime::derive_with(describe)]
+//! #[derive(Debug)]
+//! struct Point { x: f32, y: f32 }
+//! # fn main() {
+//! #     assert_eq!(Point::describe(), "Point");
+//! # }
+//! ```
+//!
+//! Similarly, [attribute macros][attribute_macros] are defined with `#[crabtime::attribute]`. The
+//! function takes two arguments, `attr: TokenStream` with the attribute arguments and
+//! `item: TokenStream` with the annotated item, and its output replaces the item. Apply it with
+//! `#[crabtime::apply(...)]`, giving the macro name and, optionally, its arguments in parentheses.
+//!
+//! ```
+//! #[crabtime::attribute]
+//! fn add_label(attr: TokenStream, item: TokenStream) -> TokenStream {
+//!     let label = attr.to_string();
+//!     format!("
+//!         #[doc = {label}]
+//!         #[allow(dead_code)]
+//!         {item}
+//!     ").parse().unwrap()
+//! }
+//!
+//! #[crabtime::apply(add_label("A point in 2D space."))]
+//! struct Point { x: f32, y: f32 }
+//! # fn main() {}
+//! ```
 //!
 //! <br/>
 //! <br/>
@@ -247,6 +363,62 @@
 //! # fn main() {}
 //! ```
 //!
+//! Both `crabtime::quote!` and `crabtime::output!` interpolate expressions as well, like
+//! `{{fields.len()}}`. Variables are captured by the format string, so they support format specs,
+//! like `{{name:?}}`, while other expressions are evaluated and inserted with their `Display`
+//! implementation. As the interpolated expression can be another `crabtime::quote!`, fragments
+//! can be composed before they are emitted:
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_getters(fields: Vec<String>) {
+//!     let getters = fields.iter().map(|field| crabtime::quote! {
+//!         pub fn {{field}}(&self) -> u32 { self.{{field}} }
+//!     }).collect::<String>();
+//!     let decls = fields.iter().map(|field| crabtime::quote! { {{field}}: u32, });
+//!     crabtime::output! {
+//!         pub struct Point { {{decls.collect::<String>()}} }
+//!         impl Point {
+//!             {{getters}}
+//!             pub const FIELD_COUNT: usize = {{fields.len()}};
+//!             pub fn describe() -> &'static str {
+//!                 {{ crabtime::quote! { {{format!("{:?}", fields.join(" "))}} } }}
+//!             }
+//!         }
+//!     }
+//! }
+//! gen_getters!(["x", "y"]);
+//! # fn main() {
+//! #     assert_eq!(Point { x: 1, y: 2 }.y(), 2);
+//! #     assert_eq!((Point::FIELD_COUNT, Point::describe()), (2, "x y"));
+//! # }
+//! ```
+//!
+//! <br/>
+//!
+//! <h5><b>Generating output with a computed derive list</b></h5>
+//!
+//! The `crabtime::derive_output!` macro works like `crabtime::output!`, but it prepends a
+//! `#[derive(...)]` attribute to the generated item. Its first argument is an expression
+//! evaluating to a slice, an array, or a vector of strings, so the list can be computed at build
+//! time. An empty list generates no attribute.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_point(fields: Vec<String>) {
+//!     let mut derives = vec!["Clone", "Debug"];
+//!     if fields.iter().all(|field| field.ends_with("f32")) {
+//!         derives.push("Default");
+//!     }
+//!     let fields = fields.join(",");
+//!     crabtime::derive_output!(derives, {
+//!         struct Point { {{fields}} }
+//!     });
+//! }
+//! gen_point!(["x: f32", "y: f32"]);
+//! # fn main() {}
+//! ```
+//!
 //! <br/>
 //!
 //! <h5><b>Generating output by returning a string or number</b></h5>
@@ -266,6 +438,38 @@
 //! # fn main() {}
 //! ```
 //!
+//! If your macro computes several independent pieces of code, like a type definition and its
+//! implementation, you can return them as a tuple (up to 8 elements). The elements are joined with
+//! newlines.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_unit_struct(name: String) -> (String, String) {
+//!     let def = format!("struct {name};");
+//!     let imp = format!("impl {name} {{ const NAME: &str = \"{name}\"; }}");
+//!     (def, imp)
+//! }
+//! gen_unit_struct!(Meters);
+//! # fn main() {}
+//! ```
+//!
+//! Iterators and values implementing `Display` can be returned without collecting them, by using
+//! `impl Iterator<Item = T>` or `impl Display` as the return type. The items of an iterator can be
+//! of any supported output type, and their code is joined with newlines.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_flag_consts(names: Vec<String>) -> impl Iterator<Item = String> {
+//!     names.into_iter().enumerate().map(|(index, name)| {
+//!         format!("pub const {name}: u32 = 1 << {index};")
+//!     })
+//! }
+//! gen_flag_consts!([FLAG_A, FLAG_B]);
+//! # fn main() {
+//! #     assert_eq!(FLAG_B, 2);
+//! # }
+//! ```
+//!
 //! <br/>
 //!
 //! <h5><b>Generating output by using <code>crabtime::output_str!</code></b></h5>
@@ -287,6 +491,65 @@
 //!
 //! <br/>
 //!
+//! <h5><b>Generating output into named sections</b></h5>
+//!
+//! Some items belong to a different place than the rest of the output, like tests of the
+//! generated code. `crabtime::output_to(section, code)` writes the code to a named section. Each
+//! section is emitted as a module of the same name, importing everything from the macro call
+//! site with `use super::*;`, so the section name has to be a valid identifier. The module of the
+//! `tests` section is annotated with `#[cfg(test)]`. The rest of the output is emitted as usual,
+//! after the section modules. As the modules are named after the sections, a macro using them can
+//! be called only once per module.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_flags(names: Vec<String>) {
+//!     for (index, name) in names.iter().enumerate() {
+//!         let bit = 1_u32 << index;
+//!         crabtime::output! {
+//!             pub const {{name}}: u32 = {{bit}};
+//!         }
+//!         crabtime::output_to("tests", &format!(
+//!             "#[test] fn {}_is_single_bit() {{ assert_eq!({name}.count_ones(), 1); }}",
+//!             name.to_lowercase()
+//!         ));
+//!     }
+//! }
+//! gen_flags!([READ, WRITE]);
+//! # fn main() {}
+//! ```
+//!
+//! <br/>
+//!
+//! <h5><b>Generating shared items once</b></h5>
+//!
+//! If several invocations need the same helper item, emitting it from each of them results in
+//! "defined multiple times" errors. `crabtime::output_once(key, code)` emits the code only in the
+//! first invocation using the key in the crate being compiled. All invocations must be in the
+//! same module, or refer to the helper by its path. The keys are stored in a directory of the
+//! compilation process, under the directory of the generated projects. It is removed in the next
+//! build, or by `cargo clean`, so the helper is emitted again in every build. Concurrent
+//! compilations of the same crate, like of its library and tests, use separate stores. Two
+//! invocations of the same macro with the same input in the same file are not distinguished.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_unit(name: String) {
+//!     crabtime::output_once("Unit", "pub trait Unit { const SCALE: f64; }");
+//!     crabtime::output! {
+//!         pub struct {{name}};
+//!         impl Unit for {{name}} { const SCALE: f64 = 1.0; }
+//!     }
+//! }
+//! gen_unit!(Meter);
+//! gen_unit!(Second);
+//! # fn main() {
+//! #     assert_eq!(<Second as Unit>::SCALE, 1.0);
+//! # }
+//! ```
+//!
+//! <br/>
+//!
 //! <h5><b>Generating output by returning a <code>TokenStream</code></b></h5>
 //!
 //! Finally, you can output [TokenStream][token_stream] from the macro. Please note that for
@@ -325,6 +588,251 @@
 //! # fn main() {}
 //! ```
 //!
+//! Constants computed by the macro can be emitted with `crabtime::emit_const(name, value)`, which
+//! formats a `pub const` definition with the Rust type of the value. It supports the signed and
+//! unsigned integers, `f32`, and `f64`.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_table_size() -> String {
+//!     let entries = (1..=10_u32).filter(|x| x % 3 == 0).count();
+//!     crabtime::emit_const("TABLE_SIZE", entries)
+//! }
+//! gen_table_size!();
+//! # fn main() {}
+//! ```
+//!
+//! Large lookup tables, like Unicode property tables, are slow to compile when emitted as array
+//! literals, as every element is a separate token. Use `crabtime::emit_table(name, values)`
+//! instead. It emits a `pub static` array encoded as a single byte string literal, converted to
+//! the element type with a `transmute` for the target endianness. It supports the unsigned
+//! integers (except `usize`), `f32`, and `f64`.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_squares_table() -> String {
+//!     let squares = (0..4096_u32).map(|x| x * x).collect::<Vec<_>>();
+//!     crabtime::emit_table("SQUARES", &squares)
+//! }
+//! gen_squares_table!();
+//! # fn main() {
+//! #     assert_eq!(SQUARES[100], 10_000);
+//! # }
+//! ```
+//!
+//! Tables of types other than `u8` are converted with `unsafe` code, allowed with
+//! `#[allow(unsafe_code)]` on the emitted statics. It can't override `#![forbid(unsafe_code)]`, so
+//! crates forbidding unsafe code can use `emit_table` only for `u8` tables.
+//!
+//! Attributes of generated items often depend on build-time analysis. The
+//! `crabtime::with_attrs(attrs, item)` function prepends them to the item, with or without the
+//! `#[...]` brackets. Attributes commonly used in performance-sensitive code are `inline`,
+//! `inline(always)`, and `inline(never)`, which control inlining, `cold`, which marks rarely called
+//! functions, like error handlers, and `track_caller`, which reports panics at the caller's
+//! location.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_accessors() {
+//!     for (name, hot) in [("fast_path", true), ("slow_path", false)] {
+//!         let attrs: &[&str] = if hot { &["inline(always)"] } else { &["cold", "inline(never)"] };
+//!         let item = format!("pub fn {name}(x: u32) -> u32 {{ x + 1 }}");
+//!         crabtime::output_str!("{}", crabtime::with_attrs(attrs, &item));
+//!     }
+//! }
+//! gen_accessors!();
+//! # fn main() {}
+//! ```
+//!
+//! Enums with string representations often need both `Display` and `FromStr`. Given the
+//! `(variant, string)` pairs, `crabtime::emit_display(enum_name, variants)` and
+//! `crabtime::emit_from_str(enum_name, variants)` generate the impls. Parsing an unknown string
+//! fails with a `&'static str` error listing the expected strings.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_color() {
+//!     let variants = [("Red", "red"), ("Green", "green"), ("Blue", "blue")];
+//!     let names = variants.iter().map(|(variant, _)| *variant).collect::<Vec<_>>().join(", ");
+//!     let display = crabtime::emit_display("Color", &variants);
+//!     let from_str = crabtime::emit_from_str("Color", &variants);
+//!     crabtime::output! {
+//!         #[derive(Debug, PartialEq)]
+//!         pub enum Color { {{names}} }
+//!         {{display}}
+//!         {{from_str}}
+//!     }
+//! }
+//! gen_color!();
+//! # fn main() {
+//! #     assert_eq!(Color::Green.to_string(), "green");
+//! #     assert_eq!("blue".parse::<Color>(), Ok(Color::Blue));
+//! # }
+//! ```
+//!
+//! Command and opcode enums often need the names of their variants, for example, for logging.
+//! `crabtime::emit_variant_names(enum_name, variants)` generates the `NAMES` array, indexed by the
+//! discriminants, and the `name()` accessor.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_opcode() {
+//!     let variants = ["Push", "Pop", "Add"];
+//!     let names = crabtime::emit_variant_names("Opcode", &variants);
+//!     let variants = variants.join(", ");
+//!     crabtime::output! {
+//!         #[derive(Clone, Copy)]
+//!         pub enum Opcode { {{variants}} }
+//!         {{names}}
+//!     }
+//! }
+//! gen_opcode!();
+//! # fn main() {
+//! #     assert_eq!(Opcode::Pop.name(), "Pop");
+//! #     assert_eq!(Opcode::NAMES[Opcode::Add as usize], "Add");
+//! # }
+//! ```
+//!
+//! When the same generation logic is used at different module depths, relative `use` paths in the
+//! output need a different number of `super::` segments. If the used items have a fixed location
+//! in the crate, prefer `crate::`-absolute paths, which work at any depth. Otherwise, pass the
+//! depth to the macro and use `crabtime::relative_use(depth, path)`, which formats a `use` of the
+//! path given relative to the anchor module.
+//!
+//! ```
+//! pub struct Config;
+//!
+//! #[crabtime::function]
+//! fn gen_config_getter(depth: usize) {
+//!     let use_config = crabtime::relative_use(depth, "Config");
+//!     crabtime::output! {
+//!         {{use_config}}
+//!         pub fn config() -> Config { Config }
+//!     }
+//! }
+//!
+//! mod outer {
+//!     gen_config_getter!(1);
+//!     mod inner {
+//!         gen_config_getter!(2);
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! Attributes of specific enum variants, like `cfg` gates or `serde` renames, are easy to misplace
+//! when formatting the enum by hand. `crabtime::emit_enum(name, variants)` formats an enum from the
+//! `(variant, attrs)` pairs, attaching each attribute list to its variant.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_backend() {
+//!     let unix: &[&str] = &["cfg(unix)"];
+//!     let windows: &[&str] = &["cfg(windows)"];
+//!     let variants = [("Epoll", unix), ("Iocp", windows), ("Threads", &[])];
+//!     let backend = crabtime::emit_enum("pub Backend", &variants);
+//!     crabtime::output! {
+//!         #[derive(Debug)]
+//!         {{backend}}
+//!     }
+//! }
+//! gen_backend!();
+//! # fn main() {
+//! #     println!("{:?}", Backend::Threads);
+//! # }
+//! ```
+//!
+//! Table-driven tests are easier to read when every case is a separate test.
+//! `crabtime::emit_parameterized_tests(base_name, cases, body_template)` generates a `#[cfg(test)]`
+//! test function for each `(name, value)` case, replacing the `{{name}}` and `{{value}}`
+//! placeholders of the body template. The tests are named after the base name, the index, and the
+//! name of the case, like `parse_sum_1_pair`, so failing cases are easy to spot.
+//!
+//! ```
+//! pub fn parse_sum(input: &str) -> Option<i32> {
+//!     input.split('+').map(|term| term.trim().parse::<i32>().ok()).sum()
+//! }
+//!
+//! #[crabtime::function]
+//! fn gen_parser_tests() {
+//!     let cases = [
+//!         ("single", r#"("7", Some(7))"#),
+//!         ("pair", r#"("1 + 2", Some(3))"#),
+//!         ("empty", r#"("", None)"#),
+//!     ];
+//!     let body = "let (input, expected) = {{value}}; assert_eq!(parse_sum(input), expected);";
+//!     let tests = crabtime::emit_parameterized_tests("parse_sum", &cases, body);
+//!     crabtime::output_str!("{tests}");
+//! }
+//! gen_parser_tests!();
+//! # fn main() {}
+//! ```
+//!
+//! Crabtime macros are not hygienic, so helper items generated by a macro can clash with user
+//! items or with helpers of other macro calls. Use `crabtime::gensym(prefix)` or
+//! `crabtime::gensym!(prefix)` to name them. It returns identifiers like
+//! `__crabtime_tmp_1a2b3c4d_0`, built from the hash of the macro body (including its arguments)
+//! and a counter, so the output is the same in every build. Calls with identical arguments
+//! generate identical names, so invoke them in separate scopes.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_checked_const(name: String, value: u32) {
+//!     let helper = crabtime::gensym!("check");
+//!     crabtime::output! {
+//!         const fn {{helper}}(x: u32) -> u32 { assert!(x < 1000); x }
+//!         pub const {{name}}: u32 = {{helper}}({{value}});
+//!     }
+//! }
+//! gen_checked_const!("A", 10);
+//! gen_checked_const!("B", 20);
+//! # fn main() {}
+//! ```
+//!
+//! If the function returns a `TokenStream` (either bare or as `proc_macro2::TokenStream`) and
+//! `proc-macro2` is not among its dependencies, Crabtime adds it automatically. Renamed
+//! dependencies, like `pm2 = { package = "proc-macro2", version = "1" }`, are recognized as well,
+//! also for `TokenStream` parameters.
+//!
+//! The automatically added dependencies, `proc-macro2`, `serde_json` (used by
+//! `crabtime::include_json`), and `semver` (used by `crabtime::require_dependency`), default to
+//! version `1`, and `toml` (used by `crabtime::require_dependency`) to version `0.8`. On nightly,
+//! you can pin other versions, matching the rest of your project, in the
+//! `[package.metadata.crabtime.default_dep_versions]` table of your crate's `Cargo.toml`. The
+//! values accept the same forms as dependencies:
+//!
+//! ```toml
+//! [package.metadata.crabtime.default_dep_versions]
+//! proc-macro2 = "=1.0.86"
+//! serde_json = { version = "1.0.120", features = ["preserve_order"] }
+//! ```
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_positions6b() -> TokenStream {
+//!     "enum Position1 { X }".parse().unwrap()
+//! }
+//! gen_positions6b!();
+//! # fn main() {}
+//! ```
+//!
+//! To guard against logic changes silently altering the amount of generated code, set the
+//! `expect_items` option. The output is then parsed and the macro fails if the number of
+//! top-level items differs from the expected one.
+//!
+//! ```
+//! #[crabtime::function(expect_items = 4)]
+//! fn gen_positions6c() {
+//!     for dim in 1..=4 {
+//!         crabtime::output! {
+//!             enum Position{{dim}} { X }
+//!         }
+//!     }
+//! }
+//! gen_positions6c!();
+//! # fn main() {}
+//! ```
+//!
 //! <br/>
 //! <br/>
 //!
@@ -339,9 +847,32 @@
 //! <h5><b>Input by using supported arguments</b></h5>
 //!
 //! Currently, you can use any combination of the following types as arguments to your macro and
-//! they will be automatically translated to patterns: `Vec<...>`, `&str`, `String`, and numbers.
-//! If the expected argument is a string, you can pass either a string literal or an identifier,
-//! which will automatically be converted to a string.
+//! they will be automatically translated to patterns: `Vec<...>`, `&str`, `String`, integers,
+//! floats, `bool`, and `char`. If the expected argument is a string, you can pass either a string
+//! literal or an identifier, which will automatically be converted to a string. Tuples of these
+//! types are supported as well, like `pair: (usize, &str)`, called as `gen!((3, "Foo"))`. Each
+//! tuple element is translated to a pattern just like a standalone argument of its type. Vectors
+//! and tuples can be nested, like `grid: Vec<Vec<usize>>`, called as `gen!([[1, 2], [3, 4]])`.
+//!
+//! Maps, `HashMap<K, V>` and `BTreeMap<K, V>`, are given as `key: value` pairs in braces, like
+//! `gen!({ "a": "b", c: "d" })`. The separator is `:`, so keys have to be single tokens, like
+//! string literals, identifiers, or numbers. Trailing commas and empty maps (`{}`) are allowed.
+//! The map type has to be in scope of the macro body, so either use its full path or import it at
+//! the beginning of the body. Maps can't be passed as named arguments, as they are not expressions.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_config(fields: std::collections::BTreeMap<String, String>) {
+//!     for (name, value) in fields {
+//!         let (name, value) = (name.to_uppercase(), format!("{value:?}"));
+//!         crabtime::output! {
+//!             pub const {{name}}: &str = {{value}};
+//!         }
+//!     }
+//! }
+//! gen_config!({ "host": "localhost", port: "8080", });
+//! # fn main() {}
+//! ```
 //!
 //! ```
 //! #[crabtime::function]
@@ -360,6 +891,54 @@
 //! # fn main() {}
 //! ```
 //!
+//! Macros using only these argument types can be also called with named arguments, given in any
+//! order. All arguments have to be named then, as mixing named and positional arguments is not
+//! supported. Missing, unknown, and duplicated arguments are reported as errors.
+//!
+//! ```
+//! # #[crabtime::function]
+//! # fn gen_positions7(name: String, components: Vec<String>) {
+//! #     for dim in 1 ..= components.len() {
+//! #         let cons = components[0..dim].join(",");
+//! #         crabtime::output! {
+//! #             enum {{name}}{{dim}} {
+//! #                 {{cons}}
+//! #             }
+//! #         }
+//! #     }
+//! # }
+//! gen_positions7!(components = ["X", "Y", "Z", "W"], name = Position);
+//! # fn main() {}
+//! ```
+//!
+//! Arguments can have default values, given by the `#[default(...)]` attribute in the same form as
+//! the arguments at the call site. The trailing arguments having defaults can be omitted. For each
+//! of them, the generated `macro_rules!` gets an additional arm, which matches the arguments before
+//! it and binds the omitted ones to their defaults. So, a macro with two trailing defaults has
+//! three positional arms, matching all, all but the last, and all but the last two arguments. In
+//! calls with named arguments, any argument having a default can be omitted.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_items(
+//!     count: usize,
+//!     #[default(Item)] prefix: String,
+//!     #[default([])] derives: Vec<String>,
+//! ) {
+//!     let derives = derives.join(", ");
+//!     for i in 0 .. count {
+//!         crabtime::output! {
+//!             #[derive({{derives}})]
+//!             pub struct {{prefix}}{{i}};
+//!         }
+//!     }
+//! }
+//! gen_items!(2);
+//! gen_items!(2, Node, [Debug, Clone]);
+//! gen_items!(prefix = Leaf, count = 1);
+//! # fn main() {}
+//! ```
+//!
 //! <br/>
 //!
 //! <h5><b>Input by using patterns</b></h5>
@@ -396,6 +975,26 @@
 //! # fn main() {}
 //! ```
 //!
+//! Patterns can be combined with typed parameters. The patterns of all parameters are joined with
+//! commas in order, so the macro below is called like `gen_counters!(Clicks, 3)`. The raw pattern
+//! is matched first, so it should not be greedy. For example, `pattern!($($items:tt)*)` would
+//! consume the whole input, leaving nothing for the following parameters. Macros with pattern
+//! parameters can't be called with named arguments.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_counters(pattern!($name:ident): _, count: usize) {
+//!     let name = stringify!($name);
+//!     for i in 0 .. count {
+//!         crabtime::output! {
+//!             pub const {{name}}{{i}}: usize = {{i}};
+//!         }
+//!     }
+//! }
+//! gen_counters!(Clicks, 3);
+//! # fn main() {}
+//! ```
+//!
 //! <br/>
 //!
 //! <h5><b>Input by using <code>TokenStream</code></b></h5>
@@ -421,13 +1020,215 @@
 //! # fn main() {}
 //! ```
 //!
-//! <br/>
+//! The number of the input token trees is available as the `INPUT_TOKEN_COUNT` constant, where a
+//! group, like `(a, b)`, counts as a single token tree. The `crabtime::input_spans()` function
+//! returns the line and column range of each of them, so the macro can report which part of its
+//! input is invalid. Lines are 1-based and columns are 0-based. If the compiler does not provide
+//! span locations, all ranges are `(0, 0)..(0, 0)`.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_token_positions(input: TokenStream) {
+//!     #![dependency(proc-macro2 = "1")]
+//!     for (token, span) in input.into_iter().zip(crabtime::input_spans()) {
+//!         let (line, column) = span.start;
+//!         crabtime::output! {
+//!             pub const {{token}}: (usize, usize) = ({{line}}, {{column}});
+//!         }
+//!     }
+//!     crabtime::output! {
+//!         pub const TOKEN_COUNT: usize = {{INPUT_TOKEN_COUNT}};
+//!     }
+//! }
+//! gen_token_positions!(FIRST SECOND);
+//! # fn main() {}
+//! ```
+//!
 //! <br/>
 //!
-//! # 🚀 Performance
+//! <h5><b>Input as a string</b></h5>
 //!
-//! The lifecycle of a Crabtime macro is similar to that of a procedural macro. It is compiled as a
-//! separate crate and then invoked to transform input tokens into output tokens. On the unstable
+//! If the macro has a single `&str` parameter annotated with `#[input]`, it is bound to the whole
+//! input, stringified just like by [`stringify!`]. It is useful if you want to parse the input with
+//! your own parser. Unlike the `TokenStream` parameter, it does not require the `proc-macro2`
+//! dependency, but the input is given as a flat string, with the whitespace between tokens
+//! normalized, for example, `a=1;` becomes `a = 1;`. Without the attribute, a `&str` parameter
+//! binds a single argument, like the other typed parameters described above.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_consts(#[input] input: &str) {
+//!     for assignment in input.split(';').filter(|t| !t.trim().is_empty()) {
+//!         let (name, value) = assignment.split_once('=').unwrap_or_default();
+//!         let (name, value) = (name.trim(), value.trim());
+//!         crabtime::output! {
+//!             pub const {{name}}: u32 = {{value}};
+//!         }
+//!     }
+//! }
+//! gen_consts!(WIDTH = 640; HEIGHT = 480;);
+//! # fn main() {}
+//! ```
+//!
+//! <br/>
+//!
+//! <h5><b>Features of the calling crate</b></h5>
+//!
+//! The macro body is evaluated in a separate project, so it can't use `cfg!` to check features of
+//! the crate being compiled. Use `crabtime::has_feature(name)` or the `crabtime::FEATURES` list
+//! instead:
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_sum() {
+//!     let body = if crabtime::has_feature("simd") { "simd_sum(xs)" } else { "xs.iter().sum()" };
+//!     crabtime::output! {
+//!         pub fn sum(xs: &[f32]) -> f32 { {{body}} }
+//!     }
+//! }
+//! gen_sum!();
+//! # fn main() {}
+//! ```
+//!
+//! Similarly, `crabtime::IS_NIGHTLY` is `true` if the toolchain is nightly, as reported by
+//! `rustc -vV`. It lets the macro use unstable library features when they are available and emit a
+//! stable fallback otherwise. Please note that it describes the host toolchain which evaluates the
+//! macro, which is the one compiling your crate, unless it is cross-compiled with a different one.
+//! Features enabled with `#![feature(...)]` have to be enabled in your crate as well, for example,
+//! with `#![cfg_attr(nightly, feature(...))]` and a build script setting the `nightly` cfg.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_first_byte() {
+//!     let body = if crabtime::IS_NIGHTLY {
+//!         "bytes.first().copied().unwrap_or_default()"
+//!     } else {
+//!         "if bytes.is_empty() { 0 } else { bytes[0] }"
+//!     };
+//!     crabtime::output! {
+//!         pub fn first_byte(bytes: &[u8]) -> u8 { {{body}} }
+//!     }
+//! }
+//! gen_first_byte!();
+//! # fn main() {}
+//! ```
+//!
+//! <br/>
+//!
+//! <h5><b>Target of the calling crate</b></h5>
+//!
+//! The generated project is always built for the host, as it has to run on the machine compiling
+//! your crate. Thus, `cfg!(target_os = ...)` or `usize::BITS` in the macro body describe the
+//! machine evaluating the macro, not the target of your crate, and are wrong when
+//! cross-compiling. Use `crabtime::COMPILE_TARGET` instead, which contains the target triple of
+//! the crate being compiled, and `crabtime::TARGET_CFG`, which lists its configuration, as printed
+//! by `rustc --print cfg`. The `crabtime::target_cfg(key)` and `crabtime::target_pointer_width()`
+//! helpers query it. The target is read from the `--target` argument of the compiler, and
+//! defaults to the host if your crate is not cross-compiled.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_word() {
+//!     let bits = crabtime::target_pointer_width();
+//!     let os = format!("{:?}", crabtime::target_cfg("target_os").unwrap_or("unknown"));
+//!     crabtime::output! {
+//!         pub type Word = u{{bits}};
+//!         pub const OS: &str = {{os}};
+//!     }
+//! }
+//! gen_word!();
+//! # fn main() {
+//! #     assert_eq!(Word::BITS, usize::BITS);
+//! # }
+//! ```
+//!
+//! <br/>
+//!
+//! <h5><b>Dependency versions</b></h5>
+//!
+//! To adapt the generated code to the version of a dependency, use `crabtime::DEP_VERSIONS`. It
+//! lists the `(package, version)` pairs of the dependencies of your macro, as resolved in the
+//! `Cargo.lock` file of the generated project. The lockfile is written by Cargo before the project
+//! is compiled, so the versions are available already during the first evaluation. Renamed
+//! dependencies are listed under their package names.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_syn_major() {
+//!     #![dependency(syn = "2")]
+//!     let (_, version) = crabtime::DEP_VERSIONS.iter().find(|(name, _)| *name == "syn").unwrap();
+//!     let major = version.split('.').next().unwrap();
+//!     crabtime::output! {
+//!         const SYN_MAJOR: u32 = {{major}};
+//!     }
+//! }
+//! gen_syn_major!();
+//! # fn main() {
+//! #     assert_eq!(SYN_MAJOR, 2);
+//! # }
+//! ```
+//!
+//! Macros meant to be used by other crates often emit code relying on a runtime library, which
+//! the calling crate has to depend on. `crabtime::require_dependency(name, version_req)` checks
+//! that the `[dependencies]` of the calling crate's `Cargo.toml`, including the target-specific
+//! ones, list the package with a version compatible with the requirement, and fails the macro with
+//! an error otherwise. Requirements accept the Cargo syntax, like `1.2`, `~0.3`, or `>=1.2, <1.5`.
+//! Please note that it inspects the manifest of the calling crate, not its lockfile, so it checks
+//! the lowest version allowed by the requirement in the manifest, not the resolved version.
+//! Dependencies inherited from the workspace are looked up in the workspace `Cargo.toml`.
+//! Dependencies without a version, like path dependencies, can't be checked and are accepted with a
+//! warning. The manifest is tracked, so the macro is evaluated again when it changes. The manifest
+//! is parsed with the `toml` and `semver` crates, which are added automatically if your macro does
+//! not declare them.
+//!
+//! ```ignore
+//! #[crabtime::function]
+//! #[macro_export]
+//! fn gen_serializable(name: String) {
+//!     crabtime::require_dependency("serde", "1.0.100");
+//!     crabtime::output! {
+//!         #[derive(serde::Serialize)]
+//!         pub struct {{name}};
+//!     }
+//! }
+//! ```
+//!
+//! <br/>
+//!
+//! <h5><b>Deterministic seed</b></h5>
+//!
+//! To make pseudo-random choices reproducible, for example, to shuffle generated test cases, use
+//! `crabtime::INVOCATION_SEED`. It is a `u64` derived from the content of the macro body,
+//! including its arguments, so it varies between invocations with different arguments but stays
+//! the same across builds and Rust toolchains, which keeps caching effective.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_shuffled(count: usize) {
+//!     let mut state = crabtime::INVOCATION_SEED | 1;
+//!     let mut cases = (0..count).collect::<Vec<_>>();
+//!     for i in (1..cases.len()).rev() {
+//!         state ^= state << 13;
+//!         state ^= state >> 7;
+//!         state ^= state << 17;
+//!         cases.swap(i, (state % (i as u64 + 1)) as usize);
+//!     }
+//!     let cases = cases.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", ");
+//!     crabtime::output! {
+//!         const CASES: &[usize] = &[{{cases}}];
+//!     }
+//! }
+//! gen_shuffled!(10);
+//! # fn main() {}
+//! ```
+//!
+//! <br/>
+//! <br/>
+//!
+//! # 🚀 Performance
+//!
+//! The lifecycle of a Crabtime macro is similar to that of a procedural macro. It is compiled as a
+//! separate crate and then invoked to transform input tokens into output tokens. On the unstable
 //! Rust channel, Crabtime and procedural macros have the same performance. On the stable channel,
 //! Crabtime requires slightly more time than a procedural macro after you change your macro
 //! definition. In other words, Crabtime’s performance is similar to procedural macros. It has
@@ -448,25 +1249,51 @@
 //! interprets the results as the generated Rust code. When you call the macro again (for example,
 //! after changing the macro’s parameters or calling the same macro in a different place), Crabtime
 //! can reuse the previously generated project. This feature is called “caching.” It is enabled by
-//! default on the nightly channel and can be enabled on the stable channel by providing a `module`
-//! attribute, for example:
+//! default on the nightly channel and can be enabled on the stable channel with the `cache`
+//! option, for example:
 //!
-//! ```ignore
-//! #[crabtime::function(cache_key=my_key)]
-//! #[module(my_crate::my_module)]
-//! fn my_macro() {
-//!     // ...
+//! ```
+//! #[crabtime::function(cache = true)]
+//! fn gen_cached_table() -> String {
+//!     let squares = (0..16_u32).map(|x| (x * x).to_string()).collect::<Vec<_>>().join(", ");
+//!     format!("const CACHED_SQUARES: &[u32] = &[{squares}];")
+//! }
+//! gen_cached_table!();
+//! # fn main() {}
+//! ```
+//!
+//! With the `reuse_binary = true` option, a cached project keeps its compiled binary. If the macro
+//! body, its dependencies, edition, and profile did not change since the last evaluation, Crabtime
+//! runs the binary directly, without invoking Cargo. Each project directory contains a
+//! `crabtime-cache.toml` file mapping the hash of these inputs to the executable path and listing
+//! the inputs used. Files and environment variables read by the body, including with
+//! `include_str!` or `env!`, are not a part of the hash, so don't use this option in such macros,
+//! as they would return a stale output. Macros with `path` dependencies are always run through
+//! Cargo, so changes in the dependency sources are picked up.
+//!
+//! ```
+//! #[crabtime::function(cache = true, reuse_binary = true)]
+//! fn gen_reused_table() -> String {
+//!     let cubes = (0..16_u32).map(|x| (x * x * x).to_string()).collect::<Vec<_>>().join(", ");
+//!     format!("const CUBES: &[u32] = &[{cubes}];")
 //! }
+//! gen_reused_table!();
+//! # fn main() {}
 //! ```
 //!
-//! The cache is always written to
-//! `<project_dir>/target/debug/build/crabtime/<module>/<macro_name>`. The defaults are presented
-//! below:
+//! All generated projects share a single target directory, so dependencies used by many macros,
+//! like `syn`, are compiled only once. The cache is written to
+//! `<project_dir>/target/debug/build/crabtime`. On nightly, projects are named after the call-site
+//! file and the macro name. On stable, they are named after the hash of the macro input, so every
+//! change of the input creates a new project. To store the projects elsewhere, for example, when
+//! the build directory is read-only or discarded on CI, set the `CRABTIME_OUT_DIR` environment
+//! variable. If set, it takes precedence over the discovered build directory. The defaults are
+//! presented below:
 //!
 //! |                      | Rust Unstable           | Rust Stable                               |
 //! | :---                 | :---                    | :---                                      |
-//! | Cache enabled        | ✅                      | ❌ by default, ✅ when `module` used.    |
-//! | `module` default     | path to def-site module | __none__                                 |
+//! | Cache enabled        | ✅                      | ❌ by default, ✅ with `cache = true`.    |
+//! | Project name         | call-site path and name | hash of the macro input                   |
 //!
 //! Please note that caching will be automatically enabled on the stable channel as soon as the
 //! [proc_macro_span][proc_macro_span] feature is stabilized. That feature allows Crabtime to read
@@ -474,6 +1301,37 @@
 //!
 //! <br/>
 //!
+//! <h5><b>Memoization</b></h5>
+//!
+//! If many invocations perform the same expensive computation, like parsing a big grammar, use
+//! `crabtime::memoize(key, || compute())`. The first call computes the value and stores it in
+//! `memo/<crate_name>` under the directory of the generated projects. All later calls with the same
+//! key, in any macro of the crate and in later builds, return the stored value. The value is a
+//! `String`, so other types have to be serialized, for example, with `serde_json`.
+//!
+//! The stored values are never invalidated, other than by `cargo clean`. Fold every input of the
+//! computation into the key, like the content of the file it reads or its version, so a change of
+//! the inputs results in a new key.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_grammar_rules() {
+//!     let grammar = "expr term factor";
+//!     let rules = crabtime::memoize(&format!("grammar-rules:{grammar}"), || {
+//!         grammar.split(' ').count().to_string()
+//!     });
+//!     crabtime::output! {
+//!         pub const GRAMMAR_RULES: usize = {{rules}};
+//!     }
+//! }
+//! gen_grammar_rules!();
+//! # fn main() {
+//! #     assert_eq!(GRAMMAR_RULES, 3);
+//! # }
+//! ```
+//!
+//! <br/>
+//!
 //! <h5><b>Performance Stats</b></h5>
 //!
 //! Crabtime also generates runtime and performance statistics to help you understand how much time
@@ -490,6 +1348,8 @@
 //! Macro Options: MacroOptions {
 //!     cache: true,
 //!     content_base_name: false,
+//!     rust_analyzer: Eval,
+//!     expression: false,
 //! }
 //! ```
 //!
@@ -497,6 +1357,129 @@
 //! macro evaluation if your IDE or build system evaluated it earlier in the background.
 //!
 //! <br/>
+//!
+//! <h5><b>IDEs</b></h5>
+//!
+//! Rust Analyzer evaluates Crabtime macros on its own, independently of `rustc`. Its results are
+//! used for IDE features only, like type hints and completions, so in heavy macros you might want
+//! to skip the expensive work during IDE analysis. The `crabtime::UNDER_RUST_ANALYZER` constant is
+//! set to `true` when the macro is evaluated by Rust Analyzer. Please note that the IDE will then
+//! see a different expansion than the compiler, so keep the generated item signatures the same.
+//!
+//! ```
+//! #[crabtime::function]
+//! fn gen_squares() -> String {
+//!     let squares: Vec<u64> = if crabtime::UNDER_RUST_ANALYZER {
+//!         vec![]
+//!     } else {
+//!         (0..256).map(|x| x * x).collect()
+//!     };
+//!     let squares = squares.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ");
+//!     format!("const SQUARES: &[u64] = &[{squares}];")
+//! }
+//! gen_squares!();
+//! # fn main() {}
+//! ```
+//!
+//! Alternatively, you can skip the evaluation under Rust Analyzer altogether with the
+//! `rust_analyzer` option. It trades the IDE accuracy for responsiveness, which is especially
+//! useful in crates using many heavy macros:
+//!
+//! | Option                        | Behavior under Rust Analyzer                                    |
+//! | :---                          | :---                                                            |
+//! | `rust_analyzer = eval`        | Evaluates the macro, just like the compiler does (default).     |
+//! | `rust_analyzer = cached`      | Reuses the output of the last evaluation of the same input, evaluates the macro otherwise. |
+//! | `rust_analyzer = placeholder` | Reuses the output of the last evaluation of the same input, emits a placeholder otherwise. |
+//!
+//! The placeholder is empty for item macros and `unreachable!()` for expression macros, so the
+//! surrounding code still type-checks. The default mode can be set for all macros with the
+//! `CRABTIME_RUST_ANALYZER` environment variable, for example, in the
+//! `rust-analyzer.cargo.extraEnv` setting of your editor.
+//!
+//! ```
+//! #[crabtime::function(rust_analyzer = cached)]
+//! fn gen_cubes() -> String {
+//!     let cubes = (0..256_u64).map(|x| (x * x * x).to_string()).collect::<Vec<_>>().join(", ");
+//!     format!("const CUBES: &[u64] = &[{cubes}];")
+//! }
+//! gen_cubes!();
+//! # fn main() {}
+//! ```
+//!
+//! To provide type hints and completions inside the macro body, Crabtime also emits a hidden test
+//! module containing the body as a regular function when running under Rust Analyzer. If it causes
+//! spurious IDE errors, for example, type errors of the body reported by Rust Analyzer, you can
+//! disable it with the `ra_hints = false` option, at the cost of losing the type hints in the
+//! macro body.
+//!
+//! ```
+//! #[crabtime::function(ra_hints = false)]
+//! fn gen_halves() -> String {
+//!     let halves = (0..256_u64).map(|x| (x / 2).to_string()).collect::<Vec<_>>().join(", ");
+//!     format!("const HALVES: &[u64] = &[{halves}];")
+//! }
+//! gen_halves!();
+//! # fn main() {}
+//! ```
+//!
+//! The outputs reused by Rust Analyzer are stored as `.rs` files in the `output_cache` directory
+//! next to the generated projects by every compiler evaluation, so the mode can be set in the
+//! environment of Rust Analyzer only. When a new output is stored and there are more than 1024 of
+//! them, the least recently written ones are removed.
+//!
+//! Generated code often has very long lines, like a big `match` in a single line. The
+//! `max_line_width` option breaks lines longer than the given width after the commas at the top
+//! delimiter level of the line. It is a naive, best-effort readability improvement of the stored
+//! output for environments without [rustfmt][rustfmt], and it is no substitute for it. It does not
+//! change the meaning of the code, as lines are broken only between tokens, outside of string
+//! literals and comments.
+//!
+//! ```
+//! #[crabtime::function(rust_analyzer = cached, max_line_width = 100)]
+//! fn gen_quads() -> String {
+//!     let quads = (0..256_u64).map(|x| x.pow(4).to_string()).collect::<Vec<_>>().join(", ");
+//!     format!("const QUADS: &[u64] = &[{quads}];")
+//! }
+//! gen_quads!();
+//! # fn main() {}
+//! ```
+//!
+//! If [rustfmt][rustfmt] is installed, the `rustfmt = true` option formats the output instead.
+//! If it is missing or fails to format the code, for example, because the output is an
+//! expression, the output is used unformatted and the build continues. The output is formatted for
+//! the edition of the calling crate, so identifiers which are keywords in newer editions, like
+//! `gen`, don't make it fail.
+//!
+//! ```
+//! #[crabtime::function(rust_analyzer = cached, rustfmt = true)]
+//! fn gen_cubes() -> String {
+//!     let cubes = (0..256_u64).map(|x| x.pow(3).to_string()).collect::<Vec<_>>().join(", ");
+//!     format!("const CUBES: &[u64] = &[{cubes}];")
+//! }
+//! gen_cubes!();
+//! # fn main() {}
+//! ```
+//!
+//! Clippy skips the code generated by procedural macros, so the output of Crabtime macros is not
+//! linted with the rest of your crate. If you enforce clippy on all the code, the `clippy = true`
+//! option lints the output with `cargo clippy` in a scratch project, using the `[lints]` of your
+//! crate. Clippy warnings are reported as macro warnings, and the denied lints fail the macro. The
+//! scratch project does not depend on your crate, so if the output refers to its items, the code
+//! does not type-check and only the lints not requiring type information are reported. Running
+//! clippy makes every evaluation noticeably slower, so the option is disabled by default, and it is
+//! skipped under Rust Analyzer.
+//!
+//! ```
+//! #[crabtime::function(clippy = true)]
+//! fn gen_sizes() -> String {
+//!     let sizes = (0..8_u64).map(|x| (1 << x).to_string()).collect::<Vec<_>>().join(", ");
+//!     format!("pub const SIZES: [u64; 8] = [{sizes}];")
+//! }
+//! gen_sizes!();
+//! # fn main() {}
+//! ```
+//!
+//! <br/>
 //! <br/>
 //!
 //! # 🪲 Logging & Debugging
@@ -505,8 +1488,10 @@
 //! [proc_macro::Diagnostic](https://doc.rust-lang.org/proc_macro/struct.Diagnostic.html) is
 //! currently a nightly-only feature, Crabtime prints nicer warnings and errors if you are using
 //! nightly Rust channel. They look just like warnings and errors from the Rust compiler.
-//! Otherwise, your warnings and errors will be printed to the console with a `[WARNING]` or
-//! `[ERROR]` prefix.
+//! Otherwise, your warnings and errors will be printed to the console with cargo-style `warning:`
+//! or `error:` labels. Macros run in the compiler process, which can't tell whether Cargo prints
+//! to a terminal, so the labels are colored only if `CARGO_TERM_COLOR` is set to `always` and
+//! `NO_COLOR` is not set to a non-empty value.
 //!
 //! | Method               | Behavior on stable | Behavior on nightly |
 //! | :---                 | :---               | :---                |
@@ -514,6 +1499,70 @@
 //! | `crabtime::warning!` | Debug log in console | Warning in console   |
 //! | `crabtime::error!`   | Debug log in console | Error in console     |
 //!
+//! If the macro body panics, the panic message is reported as a compilation error at the macro
+//! call site, so the rest of the file is still checked.
+//! To include the backtrace of the panic in the error, set the `CRABTIME_BACKTRACE` environment
+//! variable to `1` or `full`. It is passed to the evaluated code as `RUST_BACKTRACE`. If it is not
+//! set, the value of `RUST_BACKTRACE` is used, and `CRABTIME_BACKTRACE=0` disables backtraces
+//! even if `RUST_BACKTRACE` is set. This is useful when the panic originates in a dependency.
+//!
+//! For slow macros, it's useful to see the progress of the generation. If the `CRABTIME_VERBOSE`
+//! environment variable is set to `1`, the lines of `crabtime::output!` are printed to the console
+//! as soon as they are produced, prefixed with the name of the macro, like `[gen_table] ...`.
+//! Otherwise, they are collected and passed to the compiler when the evaluation finishes. Changing
+//! the variable rebuilds the generated projects.
+//!
+//! To inspect the code generated by a macro, use the `dump` option. It writes the final output to
+//! the given file, relative to the directory of your crate's `Cargo.toml`. To dump the outputs of
+//! all macros, set the `CRABTIME_DUMP_DIR` environment variable to a directory. The files are
+//! named after the macro and the hash of its input, so multiple macros and invocations don't
+//! overwrite each other. Unlike debug prints, the dumps contain only the clean generated code.
+//!
+//! ```
+//! #[crabtime::function(dump = "target/generated/gen_dumped.rs")]
+//! fn gen_dumped() -> &str {
+//!     "pub struct Dumped;"
+//! }
+//! gen_dumped!();
+//! # fn main() {}
+//! ```
+//!
+//! When reviewing changes of the dumped code, it's useful to know whether the code itself changed
+//! or only its formatting. With the `hash = true` option, the generated code starts with a
+//! `// crabtime-hash: <hash>` comment. The hash is computed from the tokens of the code, so it
+//! doesn't depend on whitespace and comments. It is also shown in the compilation stats as
+//! `Output Hash`.
+//!
+//! Coverage tools, like `cargo llvm-cov`, attribute the execution of the generated code to the
+//! macro invocation, so it's reported as a single line. With the `coverage = true` option, the
+//! output is written to `coverage/<macro>_<hash>.rs` in the Crabtime output directory (by default
+//! `target/<profile>/build/crabtime`) and included with `include!`, so the generated items get
+//! spans pointing to a real file, and their lines are reported separately. The file is named after
+//! the hash of its content, so it's rewritten only when the output changes, and it's removed only
+//! by `cargo clean`. This option requires the nightly channel. On stable, and for outputs using
+//! `$crate`, it's ignored with a warning.
+//!
+//! ```
+//! #[crabtime::function(coverage = true)]
+//! fn gen_covered() -> &str {
+//!     "pub fn covered() -> u32 { 1 }"
+//! }
+//! gen_covered!();
+//! # fn main() {
+//! #     assert_eq!(covered(), 1);
+//! # }
+//! ```
+//!
+//! If the macro generates code which is not valid Rust, the error shows the lines around the
+//! problem, and the full generated code is written to a file in the temporary directory, whose
+//! path is included in the error.
+//!
+//! Projects which are not cached are removed after the evaluation, even if it failed. To debug a
+//! failing macro, set the `CRABTIME_KEEP_ON_ERROR` environment variable to `1`. Then, projects of
+//! failed evaluations are kept, and their path is printed as a warning, so you can enter the
+//! directory and run `cargo run` manually. Projects of successful evaluations are still removed.
+//! Cached projects are always kept.
+//!
 //! <br/>
 //!
 //! <h5><b>Stdout Protocol</b></h5>
@@ -530,6 +1579,7 @@
 //! | `[OUTPUT]`  | A line of generated Rust code to be included in the final macro output. |
 //! | `[WARNING]` | A compilation warning. |
 //! | `[ERROR]`   | A compilation error. |
+//! | `[TRACK]`   | A path of a file read by the macro. The macro is expanded again when it changes. |
 //!
 //! <br/>
 //!
@@ -575,13 +1625,13 @@
 //!
 //!     macro_rules! warning {
 //!         // On the nightly channel prints a compilation warning.
-//!         // On the stable channel prints a log prefixed with `[WARNING]`.
+//!         // On the stable channel prints a log labeled with `warning:`.
 //!         # () => {};
 //!     }
 //!
 //!     macro_rules! error {
 //!         // On the nightly channel prints a compilation error.
-//!         // On the stable channel prints a log prefixed with `[ERROR]`.
+//!         // On the stable channel prints a log labeled with `error:`.
 //!         # () => {};
 //!     }
 //! }
@@ -594,8 +1644,9 @@
 //!
 //! <div style="background-color:#397be440; padding: 8px; border-radius: 8px; margin-bottom: 8px;">
 //! 💡 On the Rust unstable channel, all configuration is automatically gathered from your
-//! Cargo.toml. It includes build-dependencies and code lints, including those defined in your
-//! workspace.
+//! Cargo.toml. It includes build-dependencies, code lints, and the `rust-version` of your crate,
+//! including those defined in your workspace. With `rust-version` set, Cargo refuses to build the
+//! macro with a too-old toolchain and reports a clear MSRV error.
 //! </div>
 //!
 //! Every Crabtime macro is a separate Cargo project with its own configuration and dependencies.
@@ -612,6 +1663,7 @@
 //!     #![edition(2024)]
 //!     #![resolver(3)]
 //!     #![dependency(anyhow = "1.0")]
+//!     #![dependency(serde = { version = "1", features = ["derive"] })]
 //!
 //!     type Result<T> = anyhow::Result<T>;
 //!     // ...
@@ -619,115 +1671,460 @@
 //! # fn main() {}
 //! ```
 //!
-//! Crabtime recognizes these Cargo configuration attributes. The attributes below override any
-//! configuration discovered in your Cargo.toml, even on nightly:
+//! Dependencies accept the same specifications as the `[dependencies]` section of `Cargo.toml`,
+//! either a version string or an inline table, including `git` and `path` dependencies, such as
+//! `#![dependency(my_lib = { path = "../my_lib" })]`. Relative paths are resolved against the
+//! directory of your crate's `Cargo.toml`. Inline dependencies are reported with a warning
+//! recommending the `[build-dependencies]` section, printed once per crate in a build on stable.
+//! Add `#![allow_inline_dependencies]` to the macro body to silence it. Crabtime recognizes these
+//! Cargo configuration attributes. The attributes below override any configuration discovered in
+//! your Cargo.toml, even on nightly:
 //!
 //! <br/>
 //!
 //! <h5><b>Supported Cargo Configuration Attributes</b></h5>
 //!
-//! | Attribute             | Default |
-//! | :---                  | :---    |
-//! | `#![edition(...)]`    | 2024    |
-//! | `#![resolver(...)]`   | 3       |
-//! | `#![dependency(...)]` | []      |
+//! | Attribute                       | Default |
+//! | :---                            | :---    |
+//! | `#![edition(...)]`              | 2024    |
+//! | `#![resolver(...)]`             | 3       |
+//! | `#![dependency(...)]`           | []      |
+//! | `#![profile(...)]`              | dev     |
+//! | `#![opt_level(...)]`            | 0       |
+//! | `#![eval_profile(...)]`         | []      |
+//! | `#![min_edition(...)]`          | none    |
+//! | `#![timeout(...)]`              | none    |
+//! | `#![registry_index(...)]`       | none    |
+//! | `#![no_std]`                    | none    |
+//! | `#![allow_inline_dependencies]` | none    |
+//!
+//! Except for `#![dependency(...)]` and `#![eval_profile(...)]`, each attribute can be specified
+//! only once. Conflicting occurrences are reported as an error.
 //!
 //! <br/>
-//! <br/>
 //!
-//! # 📚 Attributes
+//! <h5><b>Evaluation Profile</b></h5>
 //!
-//! You can provide any set of global attributes (`#![...]`) on top of your Crabtime macro
-//! definition for them to be applied to the given generated Crabtime crate.
+//! The `#![eval_profile(...)]` attribute sets [profile][cargo_profiles] options used to build the
+//! generated project. It does not affect the profile of your crate, it only tunes the binary that
+//! evaluates the macro. Underscores in keys are replaced with dashes, so `opt_level` becomes
+//! `opt-level`. If a setting is given more than once, for example, by both `#![opt_level(...)]`
+//! and `#![eval_profile(...)]`, the later one wins.
 //!
-//! <br/>
-//! <br/>
+//! ```
+//! #[crabtime::function]
+//! fn gen_heavy_table() -> String {
+//!     #![eval_profile(opt_level = 3, codegen_units = 1)]
+//!     let sum: u64 = (0..1_000_000).sum();
+//!     format!("const SUM: u64 = {sum};")
+//! }
+//! gen_heavy_table!();
+//! # fn main() {}
+//! ```
 //!
-//! # 🗺️ Paths
+//! You can also build the generated project with the release profile by using
+//! `#![profile(release)]`, or only set its optimization level with `#![opt_level(...)]`. The
+//! profile is a part of the project configuration, so switching it never reuses a binary built
+//! with the other profile.
 //!
-//! Crabtime macros provide access to several path variables, allowing you to traverse your
-//! project's folder structure during macro evaluation. All paths are accessible within the
-//! `crabtime::` namespace.
+//! ```
+//! #[crabtime::function]
+//! fn gen_heavy_table2() -> String {
+//!     #![profile(release)]
+//!     let sum: u64 = (0..1_000_000).sum();
+//!     format!("const SUM2: u64 = {sum};")
+//! }
+//! gen_heavy_table2!();
+//! # fn main() {}
+//! ```
 //!
+//! Please note that this is a tradeoff. Optimizations make the evaluation faster, but the
+//! generated project takes longer to compile. It pays off only for macros doing heavy
+//! computations. For most macros, the default (unoptimized) profile results in the shortest total
+//! expansion time.
 //!
-//! | Path                  | Availability     | Description |
-//! | :---                  | :---             | :---        |
-//! | `WORKSPACE_PATH`      | Stable & Nightly | Path to the root of your project. This is where the top-most `Cargo.toml` resides, whether it's a single-crate project or a Cargo workspace. |
-//! | `CRATE_CONFIG_PATH`   | Nightly only     | Path to the `Cargo.toml` file of the current crate. |
-//! | `CALL_SITE_FILE_PATH` | Nightly only     | Path to the file where the macro was invoked. |
+//! <br/>
+//!
+//! <h5><b>Timeout</b></h5>
 //!
+//! A macro body with an infinite loop would stall your build forever. The `#![timeout(...)]`
+//! attribute limits the evaluation time, accepting durations like `500ms`, `30s`, or `2m`. The
+//! default timeout for all macros can be set with the `CRABTIME_TIMEOUT` environment variable,
+//! and the attribute takes precedence over it. By default, there is no timeout. When the time is
+//! up, the evaluation is terminated together with all processes it spawned, and an error is
+//! reported. Compilation of the generated project is not limited.
 //!
 //! ```
 //! #[crabtime::function]
-//! fn check_paths() {
-//!     println!("Workspace path: {}", crabtime::WORKSPACE_PATH);
+//! fn gen_bounded() -> String {
+//!     #![timeout(60s)]
+//!     "const BOUNDED: bool = true;".to_string()
 //! }
-//! check_paths!();
+//! gen_bounded!();
 //! # fn main() {}
 //! ```
 //!
 //! <br/>
-//! <br/>
 //!
-//! # 📖 How It Works Under The Hood
+//! <h5><b>Registry</b></h5>
 //!
-//! The content of a function annotated with `crabtime::function` is pasted into the `main`
-//! function of a temporary Rust project. This project is created, compiled, executed, and (if
-//! caching is disabled) removed at build time, and its `stdout` becomes the generated Rust code.
-//! The generated `main` function looks something like this:
+//! The generated project is built by Cargo in a directory inside your target directory, so it
+//! uses the same Cargo configuration as your crate. The environment variables, like
+//! `CARGO_REGISTRIES_<NAME>_INDEX`, are inherited, and the `.cargo/config.toml` files are found in
+//! the parent directories and in the Cargo home directory. Thus, a registry mirror configured for
+//! your project, or a private registry used by a dependency with the `registry` key, works without
+//! any changes. Please note that the configuration of your workspace is not found if you move the
+//! generated projects outside of it with `CRABTIME_OUT_DIR`.
 //!
-//! ```
-//! const SOURCE_CODE: &str = "..."; // Your code as a string.
+//! To resolve the dependencies of a single macro against a different registry, use
+//! `#![registry_index(...)]`. It replaces crates.io with the given index in a
+//! `.cargo/config.toml` written to the generated project, which takes precedence over the
+//! inherited configuration files. Environment variables still take precedence over it, as they do
+//! over any configuration file.
 //!
-//! # mod phantom_for_crabtime_name_crash_resolution {
-//! mod crabtime {
-//!     // Various utils described in this documentation.
-//!     # pub fn push_as_str(str: &mut String, result: &()) {}
-//!     # pub fn prefix_lines_with_output(input: &str) -> String { String::new() }
+//! ```
+//! #[crabtime::function]
+//! fn gen_from_mirror() {
+//!     #![registry_index("sparse+https://mirror.example.com/index/")]
+//!     crabtime::output! {
+//!         pub struct FromMirror;
+//!     }
 //! }
+//! gen_from_mirror!();
+//! # fn main() {}
+//! ```
 //!
-//! fn main() {
-//!     let mut __output_buffer__ = String::new();
-//!     let result = {
-//!         // Your code.
-//!     };
-//!     crabtime::push_as_str(&mut __output_buffer__, &result);
-//!     println!("{}", crabtime::prefix_lines_with_output(&__output_buffer__));
+//! On air-gapped machines, set the `CRABTIME_OFFLINE` environment variable to `1`, so the
+//! generated projects are built with `cargo --offline`. Dependencies are then resolved only from
+//! the crates already downloaded to the Cargo home directory. Set it to `frozen` to build with
+//! `cargo --frozen` instead, which additionally fails if the lock file of the project has to be
+//! updated. To build from a vendored registry, for example, one created with `cargo vendor`,
+//! configure the [source replacement][cargo_source_replacement] in the `.cargo/config.toml` of your
+//! workspace. As described above, it's inherited by the generated projects. Setting
+//! `CARGO_NET_OFFLINE=true` works too, as it's inherited like other Cargo environment variables.
+//!
+//! Each generated project has its own `Cargo.lock`, created when it's built for the first time.
+//! It's not shared with the `Cargo.lock` of your workspace, as the projects have different
+//! dependencies. The lock file of a cached project is kept between builds, so `frozen` works for
+//! projects built before, but one-shot projects are created from scratch in every build and always
+//! need to resolve their dependencies. Thus, with `frozen`, enable the `cache` option.
+//!
+//! As the lock file of a project is created when it's first built, the versions of its
+//! dependencies can drift between builds, for example, when the target directory is discarded on
+//! CI. With the `lock_file = true` option, the `Cargo.lock` is stored after the build in
+//! `locks/<hash>.lock` under the directory of the generated projects, where the hash is computed
+//! from the dependencies and the registry configuration, and is the same for all toolchains. The
+//! root package is stored under a fixed name and renamed to the name of the next project with the
+//! same dependencies, which starts with this lock file. Thus, it resolves the same versions, and
+//! `frozen` works for one-shot projects too. Set the `CRABTIME_LOCK_DIR` environment variable to an
+//! absolute path to store the lock files elsewhere, for example, in a directory committed to your
+//! repository or cached by your CI, to keep the versions the same across days.
+//!
+//! ```
+//! #[crabtime::function(lock_file = true)]
+//! fn gen_locked() {
+//!     #![dependency(anyhow = "1.0")]
+//!     let answer: anyhow::Result<u32> = Ok(42);
+//!     let answer = answer.unwrap_or_default();
+//!     crabtime::output! {
+//!         pub const ANSWER: u32 = {{answer}};
+//!     }
 //! }
+//! gen_locked!();
+//! # fn main() {
+//! #     assert_eq!(ANSWER, 42);
 //! # }
-//! # fn main() {}
 //! ```
 //!
-//! The `output!` macro is essentially a shortcut for writing to output buffer using `format!`, so
-//! this:
+//! <br/>
+//!
+//! <h5><b>No-std Output</b></h5>
+//!
+//! If the code your macro emits is used in a `no_std` crate, add `#![no_std]` to the macro body.
+//! After the evaluation, the output is checked in a scratch `no_std` library, which has access to
+//! the `alloc` crate, placed next to the generated project. The expansion fails if the output uses
+//! `std` paths, like `std::fmt`, or `std` macros, like `println!`. The library doesn't depend on
+//! your crate, so other errors, like the ones caused by references to your items, are ignored.
 //!
 //! ```
 //! #[crabtime::function]
-//! fn my_macro_expansion1(components: Vec<String>) {
-//!     for dim in 1 ..= components.len() {
-//!         let cons = components[0..dim].join(",");
-//!         crabtime::output! {
-//!             enum Position{{dim}} {
-//!                 {{cons}}
-//!             }
-//!         }
+//! fn gen_no_std_buffer() {
+//!     #![no_std]
+//!     crabtime::output! {
+//!         pub struct Buffer(alloc::vec::Vec<u8>);
 //!     }
 //! }
-//! my_macro_expansion1!(["X", "Y", "Z", "W"]);
+//! # extern crate alloc;
+//! gen_no_std_buffer!();
 //! # fn main() {}
 //! ```
 //!
-//! Is equivalent to:
+//! Please note that the body itself is still evaluated with `std`. It's a program run on your
+//! machine, which prints the generated code to stdout, as described in the "How It Works Under The
+//! Hood" section. Evaluating a `no_std` body would require replacing this protocol, for example,
+//! with an output channel based on panics, and a custom entry point, which is a significant
+//! restructuring of the generated project not supported currently.
+//!
+//! <br/>
+//!
+//! <h5><b>Minimum Edition of the Emitted Code</b></h5>
+//!
+//! The `#![edition(...)]` attribute sets the edition of the generated project only. If the code
+//! your macro emits requires a newer edition than the crate using it, for example, because it
+//! uses `async` blocks, declare it with `#![min_edition(...)]`. On nightly, the edition of the
+//! crate is read from its Cargo.toml, and the expansion fails with a clear error if it is older.
+//! The check is skipped on stable, where the edition of the crate is not known.
 //!
 //! ```
 //! #[crabtime::function]
-//! fn my_macro_expansion2(pattern!([$($components_arg:expr),*$(,)?]): _) {
-//!     let components: Vec<String> = expand!(
-//!         [$(crabtime::stringify_if_needed!($components_arg).to_string()),*]
-//!     ).into_iter().collect();
-//!     for dim in 1 ..= components.len() {
-//!         let cons = components[0..dim].join(",");
-//!         crabtime::output_str! {"
+//! fn gen_async_fn() {
+//!     #![min_edition(2018)]
+//!     crabtime::output! {
+//!         async fn ready() -> u32 { 42 }
+//!     }
+//! }
+//! gen_async_fn!();
+//! # fn main() {}
+//! ```
+//!
+//! Macros are evaluated where they are invoked, not where they are defined. If you reuse a macro
+//! defined in another crate, for example, by exporting the generated `macro_rules!`, the check
+//! uses the edition of the crate invoking it. A macro defined in a 2024 crate with
+//! `#![min_edition(2021)]` can still be used by a 2021 crate, while a 2018 crate gets the error.
+//! Just like Cargo, Crabtime assumes the 2015 edition for crates not specifying it.
+//!
+//! <br/>
+//! <br/>
+//!
+//! # 📚 Attributes
+//!
+//! You can provide any set of global attributes (`#![...]`) on top of your Crabtime macro
+//! definition for them to be applied to the given generated Crabtime crate.
+//!
+//! Unstable features, enabled with `#![feature(...)]`, require a nightly toolchain. If your crate
+//! is compiled with a stable one, the generated project of such a macro is built with the nightly
+//! toolchain installed with rustup, selected with the `RUSTUP_TOOLCHAIN` environment variable. If
+//! it is not installed, the expansion fails with an error pointing to the attribute. Other macros
+//! are still built with the toolchain compiling your crate. The toolchain can't be switched if
+//! the compiler is overridden with `CRABTIME_RUSTC`, which is reported as an error.
+//!
+//! ```ignore
+//! #[crabtime::function]
+//! fn gen_never_type_check() {
+//!     #![feature(never_type)]
+//!     let never: Option<!> = None;
+//!     let is_none = never.is_none();
+//!     crabtime::output! {
+//!         const NEVER_IS_NONE: bool = {{is_none}};
+//!     }
+//! }
+//! gen_never_type_check!();
+//! # fn main() {}
+//! ```
+//!
+//! <br/>
+//! <br/>
+//!
+//! # 🗺️ Paths
+//!
+//! Crabtime macros provide access to several path variables, allowing you to traverse your
+//! project's folder structure during macro evaluation. All paths are accessible within the
+//! `crabtime::` namespace.
+//!
+//!
+//! | Path                  | Availability     | Description |
+//! | :---                  | :---             | :---        |
+//! | `WORKSPACE_PATH`      | Stable & Nightly | Path to the root of your project. This is where the top-most `Cargo.toml` resides, whether it's a single-crate project or a Cargo workspace. |
+//! | `CRATE_CONFIG_PATH`   | Nightly only     | Path to the `Cargo.toml` file of the current crate. |
+//! | `CALL_SITE_FILE_PATH` | Nightly only     | Path to the file where the macro was invoked. |
+//! | `CALL_SITE_FILE`      | Stable & Nightly | Path to the file where the macro was invoked, as returned by `file!()`. Empty if the call site does not come from a real source file, for example, if the invocation was generated from a string by another macro. |
+//! | `MANIFEST_DIR_PATH`   | Stable & Nightly | Path to the directory of the `Cargo.toml` file of the current crate. |
+//!
+//!
+//! ```
+//! #[crabtime::function]
+//! fn check_paths() {
+//!     println!("Workspace path: {}", crabtime::WORKSPACE_PATH);
+//! }
+//! check_paths!();
+//! # fn main() {}
+//! ```
+//!
+//! <br/>
+//!
+//! <h5><b>Generated module files</b></h5>
+//!
+//! Very large generated modules can be written to their own files, so they are visible and
+//! reviewable in your repository. The `crabtime::emit_mod_file(name, path, code)` function writes
+//! `code` to `path`, relative to the directory of your crate's `Cargo.toml`, and returns the
+//! `#[path = "..."] mod name;` declaration to output:
+//!
+//! ```ignore
+//! #[crabtime::function]
+//! fn gen_big_module() {
+//!     let code = (0..1000).map(|i| format!("pub const C{i}: u32 = {i};")).collect::<String>();
+//!     let module = crabtime::emit_mod_file("big", "src/generated/big.rs", &code);
+//!     crabtime::output_str!("{module}");
+//! }
+//! gen_big_module!();
+//! ```
+//!
+//! The file starts with a `// @generated by crabtime` header. Files without this header are never
+//! overwritten, so a hand-written file at the same path results in an error instead of being
+//! lost. The file is rewritten only when its content changes. To regenerate it, rebuild your
+//! crate after changing the macro, or delete the file. Commit the file together with the macro, so
+//! changes of the generated code are visible in code reviews.
+//!
+//! <br/>
+//!
+//! <h5><b>Data files</b></h5>
+//!
+//! The `crabtime::include_str(path)` function reads a file, relative to the directory of your
+//! crate's `Cargo.toml`, as a `String`. Unlike reading the file with `std::fs`, the file is
+//! tracked, so your macro is expanded again when it changes, and the output is never stale.
+//!
+//! ```ignore
+//! #[crabtime::function]
+//! fn gen_opcodes() {
+//!     let opcodes = crabtime::include_str("data/opcodes.txt");
+//!     let variants = opcodes.lines().collect::<Vec<_>>().join(", ");
+//!     crabtime::output! {
+//!         pub enum Opcode { {{variants}} }
+//!     }
+//! }
+//! gen_opcodes!();
+//! ```
+//!
+//! To generate code for every file in a directory, use `crabtime::glob(pattern)`. It returns the
+//! matching paths, relative to the directory of your crate's `Cargo.toml`. In the pattern, `*`
+//! matches any sequence of characters in a name, `?` matches a single character, and `**` matches
+//! any number of directories. Hidden files are matched only if the pattern starts with a dot. The
+//! paths are sorted, so the output is the same on every machine.
+//!
+//! ```ignore
+//! #[crabtime::function]
+//! fn gen_icons() {
+//!     let names = crabtime::glob("assets/*.png").iter()
+//!         .map(|path| path.file_stem().unwrap().to_string_lossy().to_uppercase())
+//!         .collect::<Vec<_>>()
+//!         .join(", ");
+//!     crabtime::output! {
+//!         pub enum Icon { {{names}} }
+//!     }
+//! }
+//! gen_icons!();
+//! ```
+//!
+//! On nightly, the listed directories are tracked, so your macro is expanded again when files are
+//! added or removed. On stable, tracking directories is not possible. Rebuild your crate after
+//! changing the set of files, for example, by touching the file invoking the macro. Files are
+//! tracked on stable by including them in `const _` items appended to the output, so only macros
+//! generating items track them. Other macros print a warning.
+//!
+//! Structured data can be read with `crabtime::include_json(path)`, which parses the file as a
+//! `serde_json::Value`. The `serde_json` dependency is added automatically if your macro does not
+//! declare it. If the file is not valid JSON, the macro fails with an error pointing to the line
+//! and column of the problem.
+//!
+//! ```ignore
+//! #[crabtime::function]
+//! fn gen_config() {
+//!     let config = crabtime::include_json("config.json");
+//!     let fields = config["fields"].as_array().unwrap().iter()
+//!         .map(|field| (field["name"].as_str().unwrap(), field["type"].as_str().unwrap()))
+//!         .map(|(name, tp)| format!("pub {name}: {tp},"))
+//!         .collect::<String>();
+//!     crabtime::output! {
+//!         pub struct Config { {{fields}} }
+//!     }
+//! }
+//! gen_config!();
+//! ```
+//!
+//! Code is often generated from tabular data. The `crabtime::read_csv(path)` function reads the
+//! rows of a file, relative to the directory of your crate's `Cargo.toml`, as `Vec<Vec<String>>`.
+//! The file is tracked, so your macro is expanded again when it changes.
+//!
+//! ```ignore
+//! #[crabtime::function]
+//! fn gen_country_codes() {
+//!     for row in crabtime::read_csv("data/countries.csv").iter().skip(1) {
+//!         let (code, name) = (&row[0], &row[1]);
+//!         crabtime::output! {
+//!             pub const {{code}}: &str = "{{name}}";
+//!         }
+//!     }
+//! }
+//! gen_country_codes!();
+//! ```
+//!
+//! Fields are separated by commas, or by tabs if the file extension is `tsv`. A field can be
+//! enclosed in double quotes to contain separators and line breaks, and a double quote inside of
+//! it is written as `""`. Both `\n` and `\r\n` line endings are supported, and empty lines are
+//! skipped. The header row is returned like any other row.
+//!
+//! <br/>
+//! <br/>
+//!
+//! # 📖 How It Works Under The Hood
+//!
+//! The content of a function annotated with `crabtime::function` is pasted into the `main`
+//! function of a temporary Rust project. This project is created, compiled, executed, and (if
+//! caching is disabled) removed at build time, and its `stdout` becomes the generated Rust code.
+//! The generated `main` function looks something like this:
+//!
+//! ```
+//! const SOURCE_CODE: &str = "..."; // Your code as a string.
+//!
+//! # mod phantom_for_crabtime_name_crash_resolution {
+//! mod crabtime {
+//!     // Various utils described in this documentation.
+//!     # pub fn push_as_str(str: &mut String, result: &()) {}
+//!     # pub fn prefix_lines_with_output(input: &str) -> String { String::new() }
+//! }
+//!
+//! fn main() {
+//!     let mut __output_buffer__ = String::new();
+//!     let result = {
+//!         // Your code.
+//!     };
+//!     crabtime::push_as_str(&mut __output_buffer__, &result);
+//!     println!("{}", crabtime::prefix_lines_with_output(&__output_buffer__));
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+//!
+//! The `output!` macro is essentially a shortcut for writing to output buffer using `format!`, so
+//! this:
+//!
+//! ```
+//! #[crabtime::function]
+//! fn my_macro_expansion1(components: Vec<String>) {
+//!     for dim in 1 ..= components.len() {
+//!         let cons = components[0..dim].join(",");
+//!         crabtime::output! {
+//!             enum Position{{dim}} {
+//!                 {{cons}}
+//!             }
+//!         }
+//!     }
+//! }
+//! my_macro_expansion1!(["X", "Y", "Z", "W"]);
+//! # fn main() {}
+//! ```
+//!
+//! Is equivalent to:
+//!
+//! ```
+//! #[crabtime::function]
+//! fn my_macro_expansion2(pattern!([$($components_arg:expr),*$(,)?]): _) {
+//!     let components: Vec<String> = expand!(
+//!         [$(crabtime::stringify_if_needed!($components_arg).to_string()),*]
+//!     ).into_iter().collect();
+//!     for dim in 1 ..= components.len() {
+//!         let cons = components[0..dim].join(",");
+//!         crabtime::output_str! {"
 //!             enum Position{dim} {{
 //!                 {cons}
 //!             }}
@@ -772,6 +2169,27 @@
 //! # fn main() {}
 //! ```
 //!
+//! If the body prints logs that start with `[OUTPUT]`, they would be mistaken for the generated
+//! code. With the `sentinel_output = true` option, the generated code is printed between two
+//! sentinel lines instead, containing a number randomized for every evaluation. The code between
+//! them is taken verbatim, and all other lines, including the ones starting with `[OUTPUT]`, are
+//! treated as logs. Warnings, errors, and tracked files are printed after the sentinel too, so
+//! logs starting with `[WARNING]`, `[ERROR]`, or `[TRACK]` are not mistaken for them either. The
+//! `output!` and `output_str!` macros, and the `warning!` and `error!` macros, work the same in
+//! both modes.
+//!
+//! ```
+//! #[crabtime::function(sentinel_output = true)]
+//! fn gen_with_protocol_logs() {
+//!     println!("[OUTPUT] is the prefix of the generated code lines.");
+//!     crabtime::output! {
+//!         pub struct Logged;
+//!     }
+//! }
+//! gen_with_protocol_logs!();
+//! # fn main() {}
+//! ```
+//!
 //! <br/>
 //! <br/>
 //!
@@ -785,11 +2203,16 @@
 //!   above. This is because when expanding constants, macros need to produce an additional pair of
 //!   `{` and `}` around the expanded tokens. If anyone knows how to improve this, please contact
 //!   us.
-//! - Error spans from the generated code are not mapped to your source code. It means that you
-//!   will still get nice, colored error messages, but the line/column numbers will be pointing to
-//!   the generated file, not to your source file. This is an area for improvement, and I'd be
-//!   happy to accept a PR that fixes this.
+//! - Compilation errors of the macro body are reported twice: first as the full, colored compiler
+//!   output pointing to the generated file, and then as plain errors spanning the offending tokens
+//!   of your source code. Errors which can't be mapped to your source code, like unresolvable
+//!   dependencies, are included in the macro error message.
+//! - Do not glob-import Crabtime (`use crabtime::*`). The `crabtime::derive` attribute would
+//!   conflict with the built-in `derive` attribute, and every `#[derive(...)]` would be ambiguous.
 //! - `Crabtime::eval!` does not use caching, as there is no name we can associate the cache with.
+//! - Macros are evaluated lazily, per invocation. Invocations disabled by `#[cfg(...)]`, including
+//!   `eval!` calls, are removed by the compiler before expansion, so they never build or run the
+//!   macro project. Runtime conditions, like `if cfg!(...) { ... }`, expand both branches.
 //!
 //! <br/>
 //! <br/>
@@ -803,7 +2226,44 @@
 //! `crabtime::output_str!` syntax described above. If you encounter this, please
 //! [open an issue](https://github.com/wdanilo/eval-macro/issues) to let us know!
 //!
+//! The generated projects are stored next to the build directory of the crabtime crate, which is
+//! found by looking for the `build` ancestor of its `OUT_DIR`. Build systems other than Cargo
+//! (e.g. Bazel) may use a different layout. In such a case, set the `CRABTIME_OUT_DIR` environment
+//! variable to a writable directory where the generated projects should be stored.
+//!
+//! Macros are evaluated by building and running a separate Cargo project, so `cargo` and `rustc`
+//! have to be available on `PATH` at build time. Restricted build environments, like some
+//! containers, may provide only the compiler driver. If either tool is missing, the error names it
+//! explicitly, which distinguishes it from a failed compilation of the macro body.
+//!
+//! To use specific toolchain binaries instead of the ones found on `PATH`, for example, in
+//! hermetic builds or to pin a toolchain with a wrapper, set the `CRABTIME_CARGO` and
+//! `CRABTIME_RUSTC` environment variables to their paths. The `rustc` override is used to detect
+//! the host target and is passed to Cargo as `RUSTC`, so the generated projects are built by the
+//! same compiler.
+//!
+//! The generated project is a binary crate with the code placed in `src/main.rs`. Some build
+//! sandboxes and coverage tools treat binary targets specially, for example, by instrumenting
+//! them or refusing to run them. In such environments, use the `lib_target = true` option. The
+//! code is then placed in `src/lib.rs` with a `#![no_main]` harness, which calls the macro body
+//! from the C `main` symbol. Please note that it is still a binary target, declared as
+//! `[[bin]]` with `path = "src/lib.rs"`, and the library target is disabled with
+//! `autolib = false`. Thus, it only helps with tools recognizing binaries by the `src/main.rs`
+//! file. Use it only when the default layout causes problems.
+//!
+//! ```
+//! #[crabtime::function(lib_target = true)]
+//! fn gen_lib_target_struct() {
+//!     crabtime::output! {
+//!         struct LibTargetStruct;
+//!     }
+//! }
+//! gen_lib_target_struct!();
+//! # fn main() {}
+//! ```
+//!
 //! [zigs_comptime]: https://zig.guide/language-basics/comptime
+//! [cargo_source_replacement]: https://doc.rust-lang.org/cargo/reference/source-replacement.html
 //! [token_stream]: https://doc.rust-lang.org/proc_macro/struct.TokenStream.html
 //! [macro_fragments]: https://doc.rust-lang.org/reference/macros-by-example.html#metavariables
 //! [macro_rules]: https://doc.rust-lang.org/rust-by-example/macros.html
@@ -813,6 +2273,7 @@
 //! [proc_macro_span]: https://github.com/rust-lang/rust/issues/54725
 //! [rustfmt]: https://github.com/rust-lang/rustfmt
 //! [macro_hygiene]: https://doc.rust-lang.org/reference/macros-by-example.html#hygiene
+//! [cargo_profiles]: https://doc.rust-lang.org/cargo/reference/profiles.html
 //!
 //! [^supported_ides]: This code was thoroughly tested in `rustc`, the IntelliJ/RustRover Rust expansion engine, and Rust Analyzer (VS Code, etc.).
 //!
@@ -822,6 +2283,7 @@
 
 extern crate self as crabtime;
 pub use crabtime_internal::*;
+pub use crabtime_internal::derive_macro as derive;
 
 // =====================
 // === Macro Helpers ===
@@ -831,7 +2293,7 @@ pub use crabtime_internal::*;
 macro_rules! eval {
     ($($ts:tt)*) => {
         {
-            #[crabtime::eval_function(cache=true, content_base_name=true)]
+            #[crabtime::eval_function(content_base_name=true)]
             fn run() -> _ {
                 $($ts)*
             }
@@ -857,12 +2319,24 @@ macro_rules! quote {
     ($($ts:tt)*) => { String::new() };
 }
 
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+#[macro_export]
+macro_rules! derive_output {
+    ($($ts:tt)*) => {};
+}
+
 /// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
 #[macro_export]
 macro_rules! write_ln {
     ($($ts:tt)*) => {};
 }
 
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+#[macro_export]
+macro_rules! gensym {
+    ($($ts:tt)*) => { String::new() };
+}
+
 /// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
 ///
 /// Returns all ordered combinations of positive integers that sum to `n` (with at least two
@@ -883,43 +2357,868 @@ pub fn sum_combinations(_n: usize) -> Vec<Vec<usize>> {
     panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
 }
 
-pub const WORKSPACE_PATH: &str = "AVAILABLE ONLY WITHIN THE CRABTIME MACRO.";
-pub const CRATE_CONFIG_PATH: &str = "AVAILABLE ONLY WITHIN THE CRABTIME MACRO.";
-pub const CALL_SITE_FILE_PATH: &str = "AVAILABLE ONLY WITHIN THE CRABTIME MACRO.";
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Generates methods forwarding to the `inner` field. Each method is given by its name, optionally
+/// preceded by a visibility, and its signature. For example,
+/// `forward_methods("items", &[("pub len", "(&self) -> usize")])` returns
+///
+/// ```text
+/// pub fn len(&self) -> usize { self.items.len() }
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn forward_methods(_inner: &str, _methods: &[(&str, &str)]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
 
-// =============
-// === Tests ===
-// =============
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats a where-clause from `(type, traits)` pairs. Pairs without traits are skipped, and an
+/// empty string is returned if there are no bounds. For example,
+/// `emit_where(&[("T", &["Clone", "Debug"]), ("U", &["Default"])])` returns
+///
+/// ```text
+/// where T: Clone + Debug, U: Default
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_where(_bounds: &[(&str, &[&str])]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
 
-/// Most of the tests are included in the documentation above. These tests cover corner cases to
-/// ensure that the macro works as expected.
-#[cfg(all(test, feature = "std"))]
-mod tests {
-    #[test]
-    fn empty_def_compilation() {
-        #[crabtime::function]
-        fn empty_def_compilation() {}
-        empty_def_compilation!();
-    }
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats a `match` expression from `(pattern, body)` pairs, one arm per line. For example,
+/// `emit_match("self", &[("Self::A", "1"), ("Self::B", "2")])` returns
+///
+/// ```text
+/// match self {
+///     Self::A => 1,
+///     Self::B => 2,
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_match(_scrutinee: &str, _arms: &[(&str, &str)]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
 
-    // ===
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats `(pattern, body)` pairs as match arms, to be inserted into an existing `match`
+/// expression. For example, `emit_match_arms(&[("0", "\"zero\""), ("_", "\"many\"")])` returns
+///
+/// ```text
+/// 0 => "zero",
+/// _ => "many",
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_match_arms(_arms: &[(&str, &str)]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
 
-    mod mod_a {
-        #[crabtime::function]
-        fn inter_module_macro() -> &str {
-            "pub struct Generated;"
-        }
-        #[allow(clippy::single_component_path_imports)]
-        pub(super) use inter_module_macro;
-    }
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats a `Display` impl for the enum, writing the string of each `(variant, string)` pair.
+/// For example, `emit_display("Color", &[("Red", "red")])` returns
+///
+/// ```text
+/// impl ::core::fmt::Display for Color {
+///     fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+///         match *self {
+///             Self::Red => f.write_str("red"),
+///         }
+///     }
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_display(_enum_name: &str, _variants: &[(&str, &str)]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats a `FromStr` impl for the enum, parsing the string of each `(variant, string)` pair.
+/// Other strings are rejected with a `&'static str` error listing the expected ones, so the impl
+/// works in `no_std` crates as well. For example, `emit_from_str("Color", &[("Red", "red")])`
+/// returns
+///
+/// ```text
+/// impl ::core::str::FromStr for Color {
+///     type Err = &'static str;
+///     fn from_str(s: &str) -> ::core::result::Result<Self, Self::Err> {
+///         match s {
+///             "red" => Ok(Self::Red),
+///             _ => Err("Unknown variant of Color. Expected one of: red."),
+///         }
+///     }
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_from_str(_enum_name: &str, _variants: &[(&str, &str)]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Writes the code to the named output section. Each section is emitted as a module of the same
+/// name with `use super::*;`, placed before the rest of the output. The `tests` module is
+/// annotated with `#[cfg(test)]`.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn output_to(_section: &str, _code: &str) {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Writes the code to the output, unless another invocation in the crate being compiled already
+/// did it for the same key.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn output_once(_key: &str, _code: &str) {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Returns the value stored for the key by an earlier evaluation, or computes and stores it.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn memoize(_key: &str, _compute: impl FnOnce() -> String) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats an impl of the enum with the `NAMES` array of the variant names, indexed by the
+/// discriminants if they are not given explicitly, and the `name` accessor. For example,
+/// `emit_variant_names("Opcode", &["Push", "Pop"])` returns
+///
+/// ```text
+/// impl Opcode {
+///     pub const NAMES: &'static [&'static str] = &["Push", "Pop"];
+///     pub fn name(&self) -> &'static str {
+///         match *self {
+///             Self::Push => "Push",
+///             Self::Pop => "Pop",
+///         }
+///     }
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_variant_names(_enum_name: &str, _variants: &[&str]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats a `use` of the path given relative to an anchor module, for output placed `depth`
+/// modules below it. For example, `relative_use(2, "types::Id")` returns
+/// `use super::super::types::Id;` and `relative_use(0, "types::Id")` returns
+/// `use self::types::Id;`.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn relative_use(_depth: usize, _path: &str) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats a `pub static` array of the values, encoded as a byte string literal, which compiles
+/// much faster than an array literal. It supports the unsigned integers (except `usize`), `f32`,
+/// and `f64`. For example, `emit_table("TABLE", &[1_u8, 2])` returns
+/// `pub static TABLE: [u8; 2] = *b"\x01\x02";`. Tables of other types use `transmute`, so they
+/// can't be emitted in crates with `#![forbid(unsafe_code)]`.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_table<T>(_name: &str, _values: &[T]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Prepends attributes to an item, each in its own line. Attributes can be given with or without
+/// the `#[...]` brackets. For example, `with_attrs(&["inline(always)", "must_use"], "fn f() {}")`
+/// returns
+///
+/// ```text
+/// #[inline(always)]
+/// #[must_use]
+/// fn f() {}
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn with_attrs(_attrs: &[&str], _item: &str) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats an enum with the given `(variant, attrs)` pairs, each attribute in its own line above
+/// its variant, just like in [`with_attrs`]. The name can be preceded by a visibility. For
+/// example, `emit_enum("pub Mode", &[("Fast", &["cfg(feature = \"fast\")"]), ("Slow", &[])])`
+/// returns
+///
+/// ```text
+/// pub enum Mode {
+///     #[cfg(feature = "fast")]
+///     Fast,
+///     Slow,
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_enum(_name: &str, _variants: &[(&str, &[&str])]) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats a `#[cfg(test)]` test function for each `(name, value)` case. The `{{name}}` and
+/// `{{value}}` placeholders of the body template are replaced with the case name and value. Tests
+/// are named `{base_name}_{index}_{name}`, with the characters of the name that are not allowed in
+/// identifiers replaced by `_`. For example,
+/// `emit_parameterized_tests("parse", &[("one", "1")], "assert_eq!(parse(\"{{value}}\"), 1);")`
+/// returns
+///
+/// ```text
+/// #[cfg(test)]
+/// #[test]
+/// fn parse_0_one() {
+///     assert_eq!(parse("1"), 1);
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_parameterized_tests(
+    _base_name: &str,
+    _cases: &[(&str, &str)],
+    _body_template: &str
+) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Formats a `pub const` definition of the value, with the type name given by the `TypeName`
+/// trait. It is implemented for the signed and unsigned integers, `f32`, and `f64`. For example,
+/// `emit_const("SIZE", 16_u32)` returns `pub const SIZE: u32 = 16;`. Floats are emitted with the
+/// type suffix, like `2f32`, as their whole values are displayed without the fractional part. NaN
+/// and infinities are emitted as the constants of the type, like `f64::NAN`.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_const<T>(_name: &str, _value: T) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Reads a file relative to the crate's manifest directory. The macro is expanded again when the
+/// file changes.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn include_str(_path: &str) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Reads and parses a JSON file relative to the crate's manifest directory, returning a
+/// `serde_json::Value`. The `serde_json` dependency is added automatically. The macro is expanded
+/// again when the file changes.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn include_json(_path: &str) -> ! {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Returns the sorted paths matching the pattern, relative to the crate's manifest directory. In
+/// the pattern, `*` matches any sequence of characters in a name, `?` matches a single character,
+/// and `**` matches any number of directories.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn glob(_pattern: &str) -> Vec<std::path::PathBuf> {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Reads rows of a CSV file, or a TSV file if the extension is `tsv`. The `path` is relative to
+/// the crate's manifest directory. The macro is expanded again when the file changes.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn read_csv(_path: &str) -> Vec<Vec<String>> {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Fails the macro if the `[dependencies]` of the calling crate's `Cargo.toml` do not list the
+/// package with a version compatible with the requirement, like `1.2`, `~0.3`, or `>=1.2, <1.5`.
+/// The manifest is inspected, not the lockfile.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn require_dependency(_name: &str, _version_req: &str) {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Returns the line and column range of each input token tree of a macro with a `TokenStream`
+/// parameter. Lines are 1-based and columns are 0-based. If the compiler does not provide span
+/// locations, all ranges are `(0, 0)..(0, 0)`. Requires the `proc-macro2` dependency.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn input_spans() -> Vec<core::ops::Range<(usize, usize)>> {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Returns a unique identifier starting with `__crabtime_{prefix}_`, seeded by the hash of the
+/// macro body and a counter, so it is the same in every build.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn gensym(_prefix: &str) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Writes `code` to `path`, relative to the crate's manifest directory, and returns the
+/// `#[path = "..."] mod name;` declaration of the module. Refuses to overwrite files that were not
+/// generated by Crabtime.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn emit_mod_file(_name: &str, _path: &str, _code: &str) -> String {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+pub const WORKSPACE_PATH: &str = "AVAILABLE ONLY WITHIN THE CRABTIME MACRO.";
+pub const CRATE_CONFIG_PATH: &str = "AVAILABLE ONLY WITHIN THE CRABTIME MACRO.";
+pub const CALL_SITE_FILE_PATH: &str = "AVAILABLE ONLY WITHIN THE CRABTIME MACRO.";
+pub const CALL_SITE_FILE: &str = "AVAILABLE ONLY WITHIN THE CRABTIME MACRO.";
+pub const MANIFEST_DIR_PATH: &str = "AVAILABLE ONLY WITHIN THE CRABTIME MACRO.";
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Seed derived from the content of the macro body, including its arguments.
+pub const INVOCATION_SEED: u64 = 0;
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Resolved `(package, version)` pairs of the dependencies of the macro, read from the lockfile of
+/// the generated project. Within the macro, it is a lazily initialized `static` dereferencing to a
+/// slice.
+pub static DEP_VERSIONS: &[(&str, &str)] = &[];
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Features enabled for the crate invoking the macro.
+pub const FEATURES: &[&str] = &[];
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Checks whether the feature is enabled for the crate invoking the macro.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn has_feature(_name: &str) -> bool {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Target triple of the crate invoking the macro. It differs from the target the macro is
+/// evaluated on when cross-compiling.
+pub const COMPILE_TARGET: &str = "";
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Configuration of [`COMPILE_TARGET`] as `(key, value)` pairs, like
+/// `("target_pointer_width", "64")`, as printed by `rustc --print cfg`.
+pub const TARGET_CFG: &[(&str, &str)] = &[];
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Returns the first value of the key in [`TARGET_CFG`].
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn target_cfg(_key: &str) -> Option<&'static str> {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Pointer width of [`COMPILE_TARGET`] in bits.
+#[cfg(feature = "std")]
+#[allow(clippy::panic)]
+pub fn target_pointer_width() -> u32 {
+    panic!("AVAILABLE ONLY WITHIN THE CRABTIME MACRO.")
+}
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Set to `true` when the macro is evaluated by Rust Analyzer.
+pub const UNDER_RUST_ANALYZER: bool = false;
+
+/// AVAILABLE ONLY WITHIN THE CRABTIME MACRO.
+///
+/// Set to `true` when the toolchain evaluating the macro is nightly.
+pub const IS_NIGHTLY: bool = false;
+
+// =============
+// === Tests ===
+// =============
+
+/// Most of the tests are included in the documentation above. These tests cover corner cases to
+/// ensure that the macro works as expected.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    #[test]
+    fn empty_def_compilation() {
+        #[crabtime::function]
+        fn empty_def_compilation() {}
+        empty_def_compilation!();
+    }
+
+    // ===
+
+    mod mod_a {
+        #[crabtime::function]
+        fn inter_module_macro() -> &str {
+            "pub struct Generated;"
+        }
+        #[allow(clippy::single_component_path_imports)]
+        pub(super) use inter_module_macro;
+    }
 
     mod mod_b {
         super::mod_a::inter_module_macro!();
     }
 
     #[test]
-    fn inter_module_macro() {
-        let _p = mod_b::Generated;
+    fn inter_module_macro() {
+        let _p = mod_b::Generated;
+    }
+
+    // ===
+
+    #[test]
+    fn named_args() {
+        #[crabtime::function]
+        fn gen_named(name: String, count: usize, fields: Vec<String>) {
+            let fields = fields.iter().map(|f| format!("pub {f}: usize")).collect::<Vec<_>>();
+            let fields = fields.join(", ");
+            crabtime::output! {
+                pub struct {{name}} { {{fields}} }
+                impl {{name}} {
+                    pub const COUNT: usize = {{count}};
+                }
+            }
+        }
+        gen_named!(count = 3, fields = [a, "b"], name = "Named");
+        gen_named!(Positional, 2, [c]);
+        let named = Named { a: 1, b: 2 };
+        let positional = Positional { c: 3 };
+        assert_eq!((Named::COUNT, named.a + named.b), (3, 3));
+        assert_eq!((Positional::COUNT, positional.c), (2, 3));
+    }
+
+    // ===
+
+    #[test]
+    fn float_args() {
+        #[crabtime::function]
+        fn gen_scaled(scale: f32, offsets: Vec<f64>) -> String {
+            let total = offsets.iter().sum::<f64>() * f64::from(scale);
+            format!("const SCALE: f32 = {scale:?}; const TOTAL: f64 = {total:?};")
+        }
+        gen_scaled!(1.5, [0.5, 1.5]);
+        assert_eq!(SCALE, 1.5);
+        assert_eq!(TOTAL, 3.0);
+    }
+
+    // ===
+
+    #[test]
+    fn tuple_args() {
+        #[crabtime::function]
+        fn gen_tuple_const(pair: (usize, &str), triple: (String, u8, bool)) -> String {
+            let (count, label) = pair;
+            let (name, value, enabled) = triple;
+            let value = format!("({count}, {label:?}, {value}, {enabled})");
+            format!("const {name}: (usize, &str, u8, bool) = {value};")
+        }
+        gen_tuple_const!((3, "Foo"), (POSITIONAL, 2, true));
+        gen_tuple_const!(triple = (NAMED, 4, false), pair = (5, "Bar"));
+        assert_eq!(POSITIONAL, (3, "Foo", 2, true));
+        assert_eq!(NAMED, (5, "Bar", 4, false));
+    }
+
+    // ===
+
+    #[test]
+    fn nested_vec_args() {
+        #[crabtime::function]
+        fn gen_grid(name: String, grid: Vec<Vec<usize>>, labels: Vec<(String, usize)>) -> String {
+            let sums = grid.iter().map(|row| row.iter().sum::<usize>().to_string());
+            let sums = sums.collect::<Vec<_>>().join(", ");
+            let labels = labels.iter().map(|(label, i)| format!("({label:?}, {i})"));
+            let labels = labels.collect::<Vec<_>>().join(", ");
+            let tp = format!("([usize; {}], &[(&str, usize)])", grid.len());
+            format!("const {name}: {tp} = ([{sums}], &[{labels}]);")
+        }
+        gen_grid!(POSITIONAL, [[1, 2], [3, 4], []], [(a, 1), (b, 2)]);
+        gen_grid!(labels = [], grid = [[5], [6, 7]], name = NAMED);
+        assert_eq!(POSITIONAL, ([3, 7, 0], &[("a", 1), ("b", 2)][..]));
+        assert_eq!(NAMED, ([5, 13], &[][..]));
+    }
+
+    // ===
+
+    #[test]
+    fn map_args() {
+        #[crabtime::function]
+        fn gen_env(
+            name: String,
+            vars: std::collections::BTreeMap<String, String>,
+            #[default({})] ports: std::collections::HashMap<String, u16>,
+        ) -> String {
+            let vars = vars.iter().map(|(k, v)| format!("({k:?}, {v:?})")).collect::<Vec<_>>();
+            let mut ports = ports.iter().map(|(k, v)| format!("({k:?}, {v})")).collect::<Vec<_>>();
+            ports.sort();
+            let (vars, ports) = (vars.join(", "), ports.join(", "));
+            let tp = "(&[(&str, &str)], &[(&str, u16)])";
+            format!("const {name}: {tp} = (&[{vars}], &[{ports}]);")
+        }
+        gen_env!(FULL, { "b": "2", a: "1", }, { http: 80, "https": 443 });
+        gen_env!(EMPTY, {});
+        assert_eq!(FULL, (&[("a", "1"), ("b", "2")][..], &[("http", 80), ("https", 443)][..]));
+        assert_eq!(EMPTY, (&[][..], &[][..]));
+    }
+
+    // ===
+
+    #[test]
+    fn str_input() {
+        #[crabtime::function]
+        fn gen_str_input(#[input] input: &str) -> String {
+            let names = input.split(',').map(|t| t.trim().to_uppercase()).collect::<Vec<_>>();
+            let consts = names.iter().enumerate()
+                .map(|(i, name)| format!("const {name}: u8 = {i};"))
+                .collect::<Vec<_>>();
+            format!("const INPUT: &str = {input:?}; {}", consts.join(" "))
+        }
+        gen_str_input!(red, green,blue);
+        assert_eq!(INPUT, "red, green, blue");
+        assert_eq!((RED, GREEN, BLUE), (0, 1, 2));
+    }
+
+    #[test]
+    fn str_arg() {
+        #[crabtime::function]
+        fn gen_str_arg(name: &str) -> String {
+            format!("pub const NAME: &str = {name:?};")
+        }
+        gen_str_arg!("hello");
+        assert_eq!(NAME, "hello");
+        mod named {
+            gen_str_arg!(name = "hello");
+        }
+        assert_eq!(named::NAME, "hello");
+    }
+
+    // ===
+
+    #[test]
+    fn bool_args() {
+        #[crabtime::function]
+        fn gen_verbosity(name: String, verbose: bool) -> String {
+            let level = if verbose { 2 } else { 0 };
+            format!("const {name}: u32 = {level};")
+        }
+        gen_verbosity!(VERBOSE, true);
+        gen_verbosity!(QUIET, false);
+        assert_eq!((VERBOSE, QUIET), (2, 0));
+    }
+
+    // ===
+
+    #[test]
+    fn char_args() {
+        #[crabtime::function]
+        fn gen_separator(name: String, separator: char) -> String {
+            format!("const {name}: char = {separator:?};")
+        }
+        gen_separator!(COMMA, ',');
+        gen_separator!(NEWLINE, '\n');
+        gen_separator!(QUOTE, '\'');
+        assert_eq!((COMMA, NEWLINE, QUOTE), (',', '\n', '\''));
+    }
+
+    // ===
+
+    #[test]
+    fn default_args() {
+        #[crabtime::function]
+        fn gen_default_args(
+            name: String,
+            #[default(1)] count: usize,
+            #[default([value])] fields: Vec<String>,
+        ) {
+            let fields = fields.iter().map(|f| format!("pub {f}: usize")).collect::<Vec<_>>();
+            let fields = fields.join(", ");
+            crabtime::output! {
+                pub struct {{name}} { {{fields}} }
+                impl {{name}} {
+                    pub const COUNT: usize = {{count}};
+                }
+            }
+        }
+        gen_default_args!(AllDefaults);
+        gen_default_args!(OneDefault, 2);
+        gen_default_args!(NoDefaults, 3, [a, b]);
+        gen_default_args!(name = NamedDefaults, fields = [c]);
+        assert_eq!((AllDefaults::COUNT, AllDefaults { value: 1 }.value), (1, 1));
+        assert_eq!((OneDefault::COUNT, OneDefault { value: 2 }.value), (2, 2));
+        assert_eq!((NoDefaults::COUNT, NoDefaults { a: 1, b: 2 }.b), (3, 2));
+        assert_eq!((NamedDefaults::COUNT, NamedDefaults { c: 4 }.c), (1, 4));
+    }
+
+    // ===
+
+    mod mod_c {
+        #[crabtime::function(visibility = "pub(super)")]
+        fn visible_macro() -> &str {
+            "pub struct Visible;"
+        }
+
+        #[crabtime::function(export = "crate::tests::mod_c")]
+        fn exported_macro() -> &str {
+            "pub struct Exported;"
+        }
+    }
+
+    mod mod_d {
+        super::mod_c::visible_macro!();
+        crate::tests::mod_c::exported_macro!();
+    }
+
+    #[test]
+    fn macro_visibility() {
+        let _v = mod_d::Visible;
+        let _e = mod_d::Exported;
+    }
+
+    // ===
+
+    #[test]
+    fn dump() {
+        #[crabtime::function(dump = "../target/crabtime-dump/gen_dumped.rs")]
+        fn gen_dumped() -> &str {
+            "const DUMPED: u32 = 1;"
+        }
+        gen_dumped!();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../target/crabtime-dump/gen_dumped.rs");
+        assert_eq!(std::fs::read_to_string(path).ok().as_deref(), Some("const DUMPED: u32 = 1;\n"));
+        assert_eq!(DUMPED, 1);
+    }
+
+    // ===
+
+    #[test]
+    fn output_hash() {
+        #[crabtime::function(hash = true, dump = "../target/crabtime-dump/gen_hashed.rs")]
+        fn gen_hashed() -> &str {
+            "const HASHED: u32 = 1;"
+        }
+        gen_hashed!();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../target/crabtime-dump/gen_hashed.rs");
+        let dump = std::fs::read_to_string(path).unwrap_or_default();
+        let (comment, code) = dump.split_once('\n').unwrap_or_default();
+        assert!(comment.strip_prefix("// crabtime-hash: ").is_some_and(|hash| hash.len() == 16));
+        assert_eq!(code, "const HASHED: u32 = 1;\n");
+        assert_eq!(HASHED, 1);
+    }
+
+    // ===
+
+    #[test]
+    fn pattern_with_typed_args() {
+        #[crabtime::function]
+        fn gen_pattern_with_typed_args(
+            pattern!($name:ident): _,
+            count: usize,
+            label: String
+        ) -> String {
+            let name = stringify!($name);
+            format!("const {name}: (usize, &str) = ({count}, {label:?});")
+        }
+        gen_pattern_with_typed_args!(PATTERN_WITH_TYPED_ARGS, 3, "three");
+        assert_eq!(PATTERN_WITH_TYPED_ARGS, (3, "three"));
+    }
+
+    #[test]
+    fn require_dependency() {
+        #[crabtime::function]
+        fn gen_with_requirements() -> String {
+            crabtime::require_dependency("crabtime-internal", "1.1");
+            crabtime::require_dependency("crabtime-internal", ">=0.9");
+            crabtime::require_dependency("crabtime-internal", "~1.1, <2");
+            crabtime::require_dependency("crabtime-internal", "=1.1.4");
+            "const REQUIREMENTS_MET: u32 = 4;".to_string()
+        }
+        gen_with_requirements!();
+        assert_eq!(REQUIREMENTS_MET, 4);
+    }
+
+    // ===
+
+    #[test]
+    fn renamed_proc_macro2_input() {
+        #[crabtime::function]
+        fn gen_renamed_input(input: TokenStream) -> String {
+            #![dependency(pm2 = { package = "proc-macro2", version = "1" })]
+            format!("const RENAMED_INPUT: &str = {:?};", input.to_string())
+        }
+        gen_renamed_input!(a + b);
+        assert_eq!(RENAMED_INPUT, "a + b");
+    }
+
+    // ===
+
+    #[test]
+    fn dep_versions() {
+        #[crabtime::function]
+        fn gen_dep_versions() -> String {
+            #![dependency(syn = "2")]
+            #![dependency(pm2 = { package = "proc-macro2", version = "1" })]
+            let mut versions = crabtime::DEP_VERSIONS.iter()
+                .map(|(name, version)| format!("({name:?}, {:?})", version.split('.').next()))
+                .collect::<Vec<_>>();
+            versions.sort();
+            format!("const VERSIONS: &[(&str, Option<&str>)] = &[{}];", versions.join(", "))
+        }
+        gen_dep_versions!();
+        assert_eq!(VERSIONS, [("proc-macro2", Some("1")), ("syn", Some("2"))]);
+    }
+
+    // ===
+
+    #[test]
+    fn invocation_seed() {
+        #[crabtime::function]
+        fn gen_seed(name: String, _arg: usize) {
+            let seed = crabtime::INVOCATION_SEED;
+            crabtime::output! {
+                const {{name}}: u64 = {{seed}};
+            }
+        }
+        let first = { gen_seed!("SEED", 1); SEED };
+        let first_again = { gen_seed!("SEED", 1); SEED };
+        let second = { gen_seed!("SEED", 2); SEED };
+        assert_eq!(first, first_again);
+        assert_ne!(first, second);
+    }
+
+    // ===
+
+    #[test]
+    fn has_feature() {
+        #[crabtime::function]
+        fn gen_std_enabled() -> String {
+            format!("const STD_ENABLED: bool = {};", crabtime::has_feature("std"))
+        }
+        gen_std_enabled!();
+        assert_eq!(STD_ENABLED, cfg!(feature = "std"));
+    }
+
+    // ===
+
+    #[test]
+    fn nested_quotes() {
+        #[crabtime::function]
+        fn gen_nested_quotes(names: Vec<String>) {
+            let consts = names.iter().enumerate().map(|(ix, name)| crabtime::quote! {
+                const {{name}}: usize = {{ix * 10}};
+            }).collect::<Vec<_>>();
+            let wrapped = crabtime::quote! {
+                {{consts.join("")}}
+                const NESTED_COUNT: usize = {{ crabtime::quote! { {{names.len()}} } }};
+            };
+            crabtime::output! {
+                {{wrapped}}
+            }
+        }
+        gen_nested_quotes!(["NESTED_A", "NESTED_B"]);
+        assert_eq!((NESTED_A, NESTED_B, NESTED_COUNT), (0, 10, 2));
+    }
+
+    // ===
+
+    #[test]
+    fn compile_target() {
+        #[crabtime::function]
+        fn gen_compile_target() -> String {
+            let is_host = crabtime::COMPILE_TARGET.contains(std::env::consts::ARCH);
+            let bits = crabtime::target_pointer_width();
+            format!("const TARGET_IS_HOST: bool = {is_host}; const TARGET_BITS: u32 = {bits};")
+        }
+        gen_compile_target!();
+        assert_eq!((TARGET_IS_HOST, TARGET_BITS), (true, usize::BITS));
+    }
+
+    // ===
+
+    #[test]
+    fn is_nightly() {
+        #[crabtime::function]
+        fn gen_is_nightly() -> String {
+            format!("const NIGHTLY: bool = {};", crabtime::IS_NIGHTLY)
+        }
+        gen_is_nightly!();
+        let version = std::process::Command::new("rustc").arg("-vV").output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+            .unwrap_or_default();
+        let release = version.lines().find(|line| line.starts_with("release:")).unwrap_or_default();
+        assert_eq!(NIGHTLY, release.contains("-nightly") || release.contains("-dev"));
+    }
+
+    // ===
+
+    #[test]
+    fn call_site_file() {
+        #[crabtime::function]
+        fn gen_call_site_file() -> String {
+            format!("const CALL_SITE: &str = {:?};", crabtime::CALL_SITE_FILE)
+        }
+        gen_call_site_file!();
+        assert_eq!(CALL_SITE, file!());
+    }
+
+    // ===
+
+    #[test]
+    fn gensym() {
+        #[crabtime::function]
+        fn gen_gensyms() -> String {
+            let first = crabtime::gensym("tmp");
+            let second = crabtime::gensym!();
+            assert!(first.starts_with("__crabtime_tmp_") && first.ends_with("_0"));
+            assert!(second.starts_with("__crabtime_tmp_") && second.ends_with("_1"));
+            let sum = format!("const SUM: u32 = {first} + {second};");
+            format!("const {first}: u32 = 1; const {second}: u32 = 2; {sum}")
+        }
+        gen_gensyms!();
+        assert_eq!(SUM, 3);
     }
 
     #[test] fn interpolation_before_brace() {
@@ -940,6 +3239,654 @@ mod tests {
         interpolation_before_brace!();
     }
 
+    #[test]
+    fn derive_output() {
+        #[crabtime::function]
+        fn gen_point() {
+            let fields = ["x: f32", "y: f32"];
+            let mut derives = vec!["Clone", "Debug"];
+            if fields.iter().all(|field| field.ends_with("f32")) {
+                derives.push("Default");
+            }
+            let fields = fields.join(",");
+            crabtime::derive_output!(derives, {
+                struct Point { {{fields}} }
+            });
+        }
+        gen_point!();
+        let point = Point::default().clone();
+        assert_eq!(format!("{point:?}"), "Point { x: 0.0, y: 0.0 }");
+    }
+
+    // ===
+
+    // Evaluation would fail, so the test compiles only if cfg-disabled invocations are skipped.
+    #[crabtime::function]
+    #[allow(unused_macros)]
+    fn gen_failing() {
+        #![allow(unreachable_code)]
+        panic!("Evaluated a cfg-disabled invocation.");
+    }
+
+    #[crabtime::expression]
+    #[allow(unused_macros)]
+    fn gen_failing_expr() -> u32 {
+        panic!("Evaluated a cfg-disabled invocation.")
+    }
+
+    #[crabtime::function]
+    #[cfg(any())]
+    fn gen_disabled() {
+        panic!("Evaluated a cfg-disabled macro.");
+    }
+
+    #[cfg(any())]
+    gen_failing!();
+
+    #[test]
+    fn cfg_disabled_invocation() {
+        #[cfg(any())]
+        gen_failing!();
+        #[cfg(any())]
+        let _value = gen_failing_expr!();
+        #[cfg(any())]
+        let _value: u32 = crabtime::eval! {
+            panic!("Evaluated a cfg-disabled eval.")
+        };
+        let value = 1;
+        assert_eq!(value, 1);
+    }
+
+    // ===
+
+    #[test]
+    fn eval_in_const_positions() {
+        struct Buffer<const N: usize>([u8; N]);
+        let bytes = [0_u8; crabtime::eval! { 2 + 3 }];
+        let buffer = Buffer::<{ crabtime::eval! {
+            let width = 3;
+            width * 2
+        } }>([0; 6]);
+        const OFFSET: i64 = crabtime::eval! { -3 * 2 };
+        assert_eq!(bytes.len(), 5);
+        assert_eq!(buffer.0.len(), 6);
+        assert_eq!(OFFSET, -6);
+    }
+
+    // ===
+
+    #[test]
+    fn emit_table() {
+        #[crabtime::function]
+        fn gen_tables() -> String {
+            let bytes = crabtime::emit_table("BYTES", &[0_u8, b'"', b'\\', b'a', 255]);
+            let words = crabtime::emit_table("WORDS", &[1_u32, 0xDEAD_BEEF, u32::MAX]);
+            let floats = crabtime::emit_table("FLOATS", &[0.5_f64, -2.0]);
+            format!("{bytes}\n{words}\n{floats}")
+        }
+        gen_tables!();
+        assert_eq!(BYTES, [0, b'"', b'\\', b'a', 255]);
+        assert_eq!(WORDS, [1, 0xDEAD_BEEF, u32::MAX]);
+        assert_eq!(FLOATS, [0.5, -2.0]);
+    }
+
+    // ===
+
+    #[test]
+    fn with_attrs() {
+        #[crabtime::function]
+        fn gen_attributed_fn() -> String {
+            let item = "fn attributed() -> u32 { 7 }";
+            let code = crabtime::with_attrs(&["inline(always)", "#[must_use]", " cold "], item);
+            let expected = "#[inline(always)]\n#[must_use]\n#[cold]\nfn attributed() -> u32 { 7 }";
+            assert_eq!(code, expected);
+            code
+        }
+        gen_attributed_fn!();
+        assert_eq!(attributed(), 7);
+    }
+
+    // ===
+
+    #[test]
+    fn emit_where() {
+        trait Describe {
+            fn describe(&self) -> String;
+        }
+
+        #[crabtime::function]
+        fn gen_describe_impl() {
+            let where_clause = crabtime::emit_where(&[
+                ("T", &["std::fmt::Debug", "Clone"]),
+                ("U", &[]),
+                ("Vec<T>", &["PartialEq", "std::fmt::Debug"]),
+            ]);
+            crabtime::output! {
+                impl<T, U> Describe for (T, U) {{where_clause}} {
+                    fn describe(&self) -> String {
+                        format!("{:?}", vec![self.0.clone()])
+                    }
+                }
+            }
+        }
+        gen_describe_impl!();
+        assert_eq!((1, ()).describe(), "[1]");
+    }
+
+    // ===
+
+    #[test]
+    fn emit_match() {
+        #[derive(Clone, Copy)]
+        enum Planet {
+            Mercury,
+            Venus,
+            Earth,
+        }
+
+        #[crabtime::function]
+        fn gen_planet_impl() {
+            let planets = [("Mercury", 1), ("Venus", 2), ("Earth", 3)];
+            let patterns = planets.iter()
+                .map(|(name, _)| format!("Planet::{name}"))
+                .collect::<Vec<_>>();
+            let orders = planets.iter().map(|(_, order)| order.to_string()).collect::<Vec<_>>();
+            let arms = patterns.iter().zip(&orders)
+                .map(|(pattern, order)| (pattern.as_str(), order.as_str()))
+                .collect::<Vec<_>>();
+            let order_match = crabtime::emit_match("self", &arms);
+            let name_arms = crabtime::emit_match_arms(&[
+                ("Planet::Earth", "\"home\""),
+                ("Planet::Mercury | Planet::Venus", "\"inner\""),
+            ]);
+            crabtime::output! {
+                impl Planet {
+                    fn order(self) -> u32 {
+                        {{order_match}}
+                    }
+
+                    fn kind(self) -> &'static str {
+                        match self {
+                            {{name_arms}}
+                        }
+                    }
+                }
+            }
+        }
+        gen_planet_impl!();
+        assert_eq!(Planet::Venus.order(), 2);
+        assert_eq!(Planet::Earth.kind(), "home");
+        assert_eq!(Planet::Mercury.kind(), "inner");
+    }
+
+    // ===
+
+    #[test]
+    fn emit_display_and_from_str() {
+        #[derive(Debug, PartialEq)]
+        enum Direction {
+            Up,
+            Down,
+            Quoted,
+        }
+
+        #[crabtime::function]
+        fn gen_direction_impls() -> String {
+            let variants = [("Up", "up"), ("Down", "down"), ("Quoted", "\"q\"")];
+            let display = crabtime::emit_display("Direction", &variants);
+            let from_str = crabtime::emit_from_str("Direction", &variants);
+            format!("{display}\n{from_str}")
+        }
+        gen_direction_impls!();
+        assert_eq!(Direction::Down.to_string(), "down");
+        assert_eq!(Direction::Quoted.to_string(), "\"q\"");
+        assert_eq!("up".parse::<Direction>(), Ok(Direction::Up));
+        assert_eq!("\"q\"".parse::<Direction>(), Ok(Direction::Quoted));
+        assert_eq!(
+            "left".parse::<Direction>(),
+            Err("Unknown variant of Direction. Expected one of: up, down, \"q\".")
+        );
+    }
+
+    // ===
+
+    #[test]
+    fn emit_variant_names() {
+        #[derive(Clone, Copy)]
+        enum Command {
+            Start,
+            Stop,
+            Reset,
+        }
+
+        #[crabtime::function]
+        fn gen_command_names() -> String {
+            crabtime::emit_variant_names("Command", &["Start", "Stop", "Reset"])
+        }
+        gen_command_names!();
+        assert_eq!(Command::NAMES, ["Start", "Stop", "Reset"]);
+        assert_eq!(Command::NAMES[Command::Reset as usize], "Reset");
+        assert_eq!((Command::Start.name(), Command::Stop.name()), ("Start", "Stop"));
+    }
+
+    // ===
+
+    #[test]
+    fn emit_enum() {
+        #[crabtime::function]
+        fn gen_enum_with_variant_attrs() -> String {
+            let never: &[&str] = &["cfg(any())"];
+            let always: &[&str] = &["#[cfg(all())]", "doc(alias = \"Ice\")"];
+            let variants = [("Gas", never), ("Solid", always), ("Plasma(u8)", &[])];
+            let state = crabtime::emit_enum("pub State", &variants);
+            let rename: &[&str] = &["serde(rename = \"liquid\")"];
+            let renamed = crabtime::emit_enum("Renamed", &[("Liquid", rename), ("Solid", &[])]);
+            format!("#[derive(Debug, PartialEq)]\n{state}\nconst RENAMED: &str = {renamed:?};")
+        }
+        gen_enum_with_variant_attrs!();
+        // The match is exhaustive only if the `Gas` variant is compiled out.
+        let name = |state: State| match state {
+            State::Solid => "solid",
+            State::Plasma(_) => "plasma",
+        };
+        assert_eq!((name(State::Solid), name(State::Plasma(1))), ("solid", "plasma"));
+        assert_eq!(
+            RENAMED,
+            "enum Renamed {\n    #[serde(rename = \"liquid\")]\n    Liquid,\n    Solid,\n}"
+        );
+    }
+
+    // ===
+
+    fn checked_div(input: &str) -> Option<u32> {
+        let (a, b) = input.split_once('/')?;
+        a.trim().parse::<u32>().ok()?.checked_div(b.trim().parse().ok()?)
+    }
+
+    #[crabtime::function]
+    fn gen_checked_div_tests() -> String {
+        let cases = [
+            ("exact", r#"("6 / 3", Some(2))"#),
+            ("by zero", r#"("1 / 0", None)"#),
+            ("", r#"("x / 1", None)"#),
+        ];
+        let body = "let (input, expected) = {{value}}; assert_eq!(checked_div(input), expected);";
+        let tests = crabtime::emit_parameterized_tests("checked_div", &cases, body);
+        let names = tests.lines().filter_map(|t| t.strip_prefix("fn ")?.split('(').next());
+        let names = names.map(|name| format!("{name:?}")).collect::<Vec<_>>().join(", ");
+        format!("{tests}\nconst CHECKED_DIV_TESTS: &[&str] = &[{names}];")
+    }
+    gen_checked_div_tests!();
+
+    #[test]
+    fn emit_parameterized_tests() {
+        let names = ["checked_div_0_exact", "checked_div_1_by_zero", "checked_div_2"];
+        assert_eq!(CHECKED_DIV_TESTS, names);
+    }
+
+    // ===
+
+    pub struct RelativeUseTarget(u32);
+
+    #[crabtime::function]
+    fn gen_relative_use_getter(depth: usize) -> String {
+        let use_target = crabtime::relative_use(depth, "RelativeUseTarget;");
+        format!("{use_target}\npub fn get() -> RelativeUseTarget {{ RelativeUseTarget({depth}) }}")
+    }
+
+    mod relative_use_outer {
+        gen_relative_use_getter!(1);
+        pub mod inner {
+            gen_relative_use_getter!(2);
+        }
+    }
+
+    #[test]
+    fn relative_use() {
+        assert_eq!(relative_use_outer::get().0, 1);
+        assert_eq!(relative_use_outer::inner::get().0, 2);
+    }
+
+    // ===
+
+    #[crabtime::function]
+    fn gen_squares_mod() {
+        let squares = (0..4).map(|x| (x * x).to_string()).collect::<Vec<_>>().join(", ");
+        let code = format!("pub const SQUARES: [u32; 4] = [{squares}];");
+        // Written to the target directory, so running the tests doesn't change the sources.
+        let path = "../target/crabtime-generated/squares.rs";
+        let module = crabtime::emit_mod_file("squares", path, &code);
+        crabtime::output_str!("{module}");
+    }
+    gen_squares_mod!();
+
+    #[test]
+    fn emit_mod_file() {
+        assert_eq!(squares::SQUARES, [0, 1, 4, 9]);
+    }
+
+    // ===
+
+    #[crabtime::function]
+    fn gen_consts() -> (String, String, String, String, String, String) {
+        let width = (1..=4_u16).sum::<u16>();
+        let ratio = f64::from(width) / 10.0 + 0.5;
+        let scale = f32::from(width) / 5.0;
+        let offset = -i32::from(width);
+        (
+            crabtime::emit_const("WIDTH", width),
+            crabtime::emit_const("RATIO", ratio),
+            crabtime::emit_const("SCALE", scale),
+            crabtime::emit_const("OFFSET", offset),
+            crabtime::emit_const("MISSING", f64::NAN),
+            crabtime::emit_const("LOWER_BOUND", f32::NEG_INFINITY),
+        )
+    }
+    gen_consts!();
+
+    #[test]
+    fn emit_const() {
+        assert_eq!(WIDTH, 10_u16);
+        assert_eq!(RATIO, 1.5_f64);
+        assert_eq!(SCALE, 2.0_f32);
+        assert_eq!(OFFSET, -10_i32);
+        assert!(MISSING.is_nan());
+        assert_eq!(LOWER_BOUND, f32::NEG_INFINITY);
+    }
+
+    // ===
+
+    #[crabtime::function]
+    fn gen_countries() -> String {
+        let rows = crabtime::read_csv("tests/data/countries.csv");
+        let countries = rows.iter().skip(1)
+            .map(|row| format!("({:?}, {:?}, {:?})", row[0], row[1], row[2]))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("const COUNTRIES: &[(&str, &str, &str)] = &[{countries}];")
+    }
+    gen_countries!();
+
+    #[test]
+    fn read_csv() {
+        assert_eq!(COUNTRIES, &[
+            ("PL", "Poland", "Warsaw, Krakow"),
+            ("US", "United States", "the \"states\""),
+            ("JP", "Japan", ""),
+        ]);
+    }
+
+    // ===
+
+    #[crabtime::function]
+    fn gen_opcodes() {
+        let opcodes = crabtime::include_str("tests/data/opcodes.txt");
+        let variants = opcodes.lines().collect::<Vec<_>>().join(", ");
+        crabtime::output! {
+            #[derive(Debug, PartialEq)]
+            enum Opcode { {{variants}} }
+        }
+    }
+    gen_opcodes!();
+
+    #[test]
+    fn include_str() {
+        assert_eq!(format!("{:?}", [Opcode::Push, Opcode::Pop, Opcode::Add]), "[Push, Pop, Add]");
+    }
+
+    // ===
+
+    #[crabtime::function]
+    fn gen_config() {
+        let config = crabtime::include_json("tests/data/config.json");
+        let name = config["name"].as_str().unwrap();
+        let fields = config["fields"].as_array().unwrap().iter()
+            .map(|field| (field["name"].as_str().unwrap(), field["type"].as_str().unwrap()))
+            .map(|(name, tp)| format!("{name}: {tp},"))
+            .collect::<String>();
+        crabtime::output! {
+            struct {{name}} { {{fields}} }
+        }
+    }
+    gen_config!();
+
+    #[test]
+    fn include_json() {
+        let config = Config { width: 10, title: "Main".to_string() };
+        assert_eq!((config.width, config.title.as_str()), (10, "Main"));
+    }
+
+    // ===
+
+    #[test]
+    fn glob() {
+        #[crabtime::function]
+        fn gen_assets() -> String {
+            let paths = |pattern| crabtime::glob(pattern).iter()
+                .map(|path| format!("{:?}", path.to_string_lossy().replace('\\', "/")))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let png = paths("tests/data/assets/*.png");
+            let all_png = paths("tests/data/assets/**/?.png");
+            format!("const PNG: &[&str] = &[{png}]; const ALL_PNG: &[&str] = &[{all_png}];")
+        }
+        gen_assets!();
+        assert_eq!(PNG, ["tests/data/assets/a.png", "tests/data/assets/b.png"]);
+        assert_eq!(ALL_PNG, [
+            "tests/data/assets/a.png",
+            "tests/data/assets/b.png",
+            "tests/data/assets/icons/c.png",
+        ]);
+    }
+
+    // ===
+
+    #[test]
+    fn eval_str() {
+        const SUM: usize = crabtime::eval_str!(r#"
+            let values = [1, 2, 3];
+            values.iter().sum::<usize>()
+        "#);
+        assert_eq!(SUM, 6);
+    }
+
+    // ===
+
+    trait FieldCount {
+        const FIELD_COUNT: usize;
+    }
+
+    #[crabtime::derive]
+    fn field_count(input: TokenStream) -> TokenStream {
+        #![dependency(syn = { version = "2", features = ["full"] })]
+        let input: syn::DeriveInput = syn::parse2(input).unwrap();
+        let name = &input.ident;
+        let syn::Data::Struct(data) = &input.data else { panic!("Expected a struct.") };
+        let count = data.fields.len();
+        format!("impl FieldCount for {name} {{ const FIELD_COUNT: usize = {count}; }}")
+            .parse().unwrap()
+    }
+
+    #[crabtime::derive_with(field_count)]
+    #[derive(Clone, Debug)]
+    #[allow(dead_code)]
+    struct Rgb(u8, u8, u8);
+
+    #[test]
+    fn derive() {
+        assert_eq!(Rgb::FIELD_COUNT, 3);
+    }
+
+    // ===
+
+    #[crabtime::attribute]
+    fn with_double(attr: TokenStream, item: TokenStream) -> TokenStream {
+        #![dependency(syn = { version = "2", features = ["full"] })]
+        let name: syn::Ident = syn::parse2(attr).unwrap();
+        let item_fn: syn::ItemFn = syn::parse2(item.clone()).unwrap();
+        let fn_name = &item_fn.sig.ident;
+        format!("{item} fn {name}() -> u32 {{ {fn_name}() * 2 }}").parse().unwrap()
+    }
+
+    #[crabtime::apply(with_double(twenty))]
+    fn ten() -> u32 { 10 }
+
+    #[test]
+    fn attribute() {
+        assert_eq!(ten(), 10);
+        assert_eq!(twenty(), 20);
+    }
+
+    // ===
+
+    #[test]
+    fn input_spans() {
+        #[crabtime::function]
+        fn gen_input_spans(input: TokenStream) -> String {
+            #![dependency(proc-macro2 = "1")]
+            let spans = crabtime::input_spans();
+            assert_eq!(spans.len(), INPUT_TOKEN_COUNT);
+            assert_eq!(input.into_iter().count(), INPUT_TOKEN_COUNT);
+            format!("pub const INPUT_SPANS: usize = {};", spans.len())
+        }
+        gen_input_spans!(a + (b, c));
+        assert_eq!(INPUT_SPANS, 3);
+        mod empty {
+            gen_input_spans!();
+        }
+        assert_eq!(empty::INPUT_SPANS, 0);
+    }
+
+    // ===
+
+    #[test]
+    fn sentinel_output() {
+        #[crabtime::function(sentinel_output = true)]
+        fn gen_sentinel_output() {
+            println!("[OUTPUT] const SENTINEL_OUTPUT: u32 = 0;");
+            println!("[ERROR] is the prefix of the error lines.");
+            crabtime::output_str!("const SENTINEL_OUTPUT: u32 = 1;");
+            crabtime::output! {
+                const SENTINEL_OUTPUT_2: u32 = 2;
+            }
+        }
+        gen_sentinel_output!();
+        assert_eq!((SENTINEL_OUTPUT, SENTINEL_OUTPUT_2), (1, 2));
+    }
+
+    // ===
+
+    #[test]
+    fn coverage() {
+        #[crabtime::function(coverage = true)]
+        fn gen_covered() {
+            crabtime::output! {
+                fn covered() -> u32 { 3 }
+            }
+        }
+        gen_covered!();
+        #[crabtime::expression(coverage = true)]
+        fn covered_expression() {
+            crabtime::output! { covered() * 2 }
+        }
+        assert_eq!(covered_expression!(), 6);
+    }
+
+    // ===
+
+    #[test]
+    fn memoize() {
+        #[crabtime::function]
+        fn gen_memoized(name: String, value: String) {
+            let value = crabtime::memoize("crabtime-test-memoize", || value);
+            crabtime::output! {
+                const {{name}}: &str = {{value}};
+            }
+        }
+        gen_memoized!(MEMOIZED_FIRST, "\"first\"");
+        gen_memoized!(MEMOIZED_SECOND, "\"second\"");
+        assert_eq!((MEMOIZED_FIRST, MEMOIZED_SECOND), ("first", "first"));
+    }
+
+    // ===
+
+    #[crabtime::function]
+    fn gen_output_sections() {
+        crabtime::output_to("sectioned", "pub fn doubled() -> u32 { section_base() * 2 }");
+        crabtime::output_to("tests", "use super::sectioned::doubled;");
+        // Runs as `tests::tests::section_test`.
+        crabtime::output_to("tests", "#[test] fn section_test() { assert_eq!(doubled(), 2); }");
+        crabtime::output! {
+            fn section_base() -> u32 { 1 }
+        }
+    }
+    gen_output_sections!();
+
+    #[test]
+    fn output_sections() {
+        assert_eq!(sectioned::doubled(), 2);
+    }
+
+    // ===
+
+    #[crabtime::function]
+    fn gen_once_users(name: String) {
+        crabtime::output_once("OnceShared", "fn once_shared() -> u32 { 7 }");
+        crabtime::output! {
+            fn {{name}}() -> u32 { once_shared() }
+        }
+    }
+    gen_once_users!(once_user_a);
+    gen_once_users!(once_user_b);
+
+    #[test]
+    fn output_once() {
+        assert_eq!((once_user_a(), once_user_b()), (7, 7));
+    }
+
+    // ===
+
+    #[test]
+    fn tuple_output() {
+        #[crabtime::function]
+        fn gen_counter() -> (String, String, &'static str) {
+            let def = "struct Counter(u32);".to_string();
+            let imp = "impl Counter { fn next(&mut self) -> u32 { self.0 += 1; self.0 } }";
+            (def, imp.to_string(), "const START: u32 = 10;")
+        }
+        gen_counter!();
+        let mut counter = Counter(START);
+        assert_eq!(counter.next(), 11);
+    }
+
+    // ===
+
+    #[test]
+    fn forward_methods() {
+        #[crabtime::function]
+        fn gen_stack() {
+            let methods = crabtime::forward_methods("items", &[
+                ("pub push", "(&mut self, value: u32)"),
+                ("pub pop", "(&mut self) -> Option<u32>"),
+                ("pub len", "(&self) -> usize"),
+                ("pub retain", "<F: FnMut(&u32) -> bool>(&mut self, f: F)"),
+                ("pub extend_from_slice", "(&mut self, other: &[u32])"),
+            ]);
+            crabtime::output! {
+                #[derive(Default)]
+                struct Stack { items: Vec<u32> }
+                impl Stack { {{methods}} }
+            }
+        }
+        gen_stack!();
+        let mut stack = Stack::default();
+        stack.push(1);
+        stack.extend_from_slice(&[2, 3, 4]);
+        stack.retain(|t| *t != 2);
+        assert_eq!(stack.pop(), Some(4));
+        assert_eq!(stack.len(), 2);
+    }
+
     // ===
 
     // https://github.com/wdanilo/crabtime/issues/25
@@ -948,6 +3895,7 @@ mod tests {
         fn fn_in_impl() -> &str {
             "pub fn test(&self) {}"
         }
+        #[allow(dead_code)]
         struct Test;
         impl Test {
             fn_in_impl!();
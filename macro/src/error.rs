@@ -16,11 +16,21 @@ impl Level {
     pub const WARNING_PREFIX: &'static str = "[WARNING]";
     pub const ERROR_PREFIX: &'static str = "[ERROR]";
 
+    /// Label of the cargo-style diagnostics, like `warning: ...`.
     #[cfg(not(nightly))]
-    fn prefix(&self) -> &str {
+    fn label(&self) -> &str {
         match self {
-            Level::Warning => Self::WARNING_PREFIX,
-            Level::Error => Self::ERROR_PREFIX,
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+
+    /// ANSI escape code of the label color, bold yellow or bold red, as used by cargo.
+    #[cfg(not(nightly))]
+    fn color(&self) -> &str {
+        match self {
+            Level::Warning => "\x1b[1;33m",
+            Level::Error => "\x1b[1;31m",
         }
     }
 }
@@ -41,8 +51,39 @@ pub(crate) fn print(level: Level, message: &str) {
         proc_macro::Diagnostic::spanned(span, level.into(), message).emit();
     }
     #[cfg(not(nightly))] {
-        println!("{} {message}", level.prefix());
+        println!("{}", render(level, message, use_color()));
+    }
+}
+
+/// Formats the message like cargo diagnostics, with the `warning:` or `error:` label, which is
+/// colored if `color` is set.
+#[cfg(not(nightly))]
+pub(crate) fn render(level: Level, message: &str, color: bool) -> String {
+    let label = level.label();
+    let message = message.trim_start();
+    if color {
+        format!("{}{label}\x1b[0m\x1b[1m:\x1b[0m {message}", level.color())
+    } else {
+        format!("{label}: {message}")
+    }
+}
+
+/// Whether the diagnostics are colored. The macro runs in the compiler process, whose output is
+/// piped to Cargo even if Cargo prints to a terminal, and Cargo does not tell the compiler whether
+/// it colors its output. Thus, colors are enabled only if `CARGO_TERM_COLOR` is set to `always`.
+/// A non-empty `NO_COLOR` disables them regardless.
+#[cfg(not(nightly))]
+fn use_color() -> bool {
+    let no_color = std::env::var("NO_COLOR").ok();
+    color_enabled(no_color.as_deref(), std::env::var("CARGO_TERM_COLOR").ok().as_deref())
+}
+
+#[cfg(not(nightly))]
+pub(crate) fn color_enabled(no_color: Option<&str>, cargo_term_color: Option<&str>) -> bool {
+    if no_color.is_some_and(|t| !t.is_empty()) {
+        return false
     }
+    cargo_term_color.map(str::trim) == Some("always")
 }
 
 macro_rules! debug         { ($($ts:tt)*) => { if DEBUG { println!( $($ts)* )}  }; }
@@ -63,11 +104,18 @@ pub(crate) struct Issue {
     pub span: Option<Span>,
     pub message: String,
     pub context: Option<Box<Issue>>,
+    /// Other issues reported together with this one.
+    pub related: Vec<Issue>,
 }
 
 impl Issue {
     pub fn msg(level: Level, span: Option<Span>, message: String) -> Self {
-        Self { level, span, message, context: None }
+        Self { level, span, message, context: None, related: vec![] }
+    }
+
+    pub fn with_related(mut self, related: Vec<Issue>) -> Self {
+        self.related = related;
+        self
     }
 
     pub fn context(mut self, f: impl FnOnce() -> Issue) -> Self {
@@ -90,6 +138,9 @@ impl Issue {
         let level = self.level.into();
         let message = self.message_with_cause();
         proc_macro::Diagnostic::spanned(span, level, message).emit();
+        for issue in &self.related {
+            issue.emit();
+        }
     }
 
     // This is a hack to make compile errors with spans on stable.
@@ -97,7 +148,11 @@ impl Issue {
     pub fn compile_error(&self) -> TokenStream {
         let span = self.span.unwrap_or_else(Span::call_site);
         let message = self.message_with_cause();
-        quote::quote_spanned! { span => compile_error!(#message) }
+        if self.related.is_empty() {
+            return quote::quote_spanned! { span => compile_error!{#message} }
+        }
+        let related = self.related.iter().map(|issue| issue.compile_error());
+        quote::quote_spanned! { span => compile_error!{#message} #(#related)* }
     }
 }
 
@@ -1,5 +1,6 @@
 #![cfg_attr(nightly, feature(proc_macro_span))]
 #![cfg_attr(nightly, feature(proc_macro_diagnostic))]
+#![cfg_attr(nightly, feature(proc_macro_tracked_path))]
 
 #![cfg_attr(not(nightly), allow(dead_code))]
 #![cfg_attr(not(nightly), allow(unused_macros))]
@@ -24,10 +25,8 @@ use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
 use std::default::Default;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hash;
-use std::hash::Hasher;
 
 // =================
 // === Constants ===
@@ -40,9 +39,76 @@ const CRATE: &str = "crabtime";
 /// Module with utils functions in the generated project.
 const GEN_MOD: &str = CRATE;
 const DEFAULT_EDITION: &str = "2024";
+/// Edition of crates which do not specify it in their `Cargo.toml`.
+const CARGO_DEFAULT_EDITION: &str = "2015";
 const DEFAULT_RESOLVER: &str = "3";
+/// Rust editions accepted by the `min_edition` attribute.
+const EDITIONS: &[u32] = &[2015, 2018, 2021, 2024];
+/// Inline attributes which can be specified only once.
+const SINGLETON_ATTRIBUTES: &[&str] = &[
+    "edition", "resolver", "min_edition", "timeout", "profile", "opt_level", "registry_index",
+    "no_std", "allow_inline_dependencies",
+];
+const PROC_MACRO2: &str = "proc-macro2";
+/// Used when `proc-macro2` is injected automatically because the macro returns a `TokenStream`.
+const DEFAULT_PROC_MACRO2_VERSION: &str = "\"1\"";
+const SERDE_JSON: &str = "serde_json";
+/// Used when `serde_json` is injected automatically because the macro uses `include_json`.
+const DEFAULT_SERDE_JSON_VERSION: &str = "\"1\"";
+const TOML: &str = "toml";
+/// Used when `toml` is injected automatically because the macro uses `require_dependency`.
+const DEFAULT_TOML_VERSION: &str = "\"0.8\"";
+const SEMVER: &str = "semver";
+/// Used when `semver` is injected automatically because the macro uses `require_dependency`.
+const DEFAULT_SEMVER_VERSION: &str = "\"1\"";
 const OUTPUT_PREFIX: &str = "[OUTPUT]";
+/// Prefix of lines with the code of a named output section, followed by the name, like
+/// `[OUTPUT:tests]`.
+const OUTPUT_SECTION_PREFIX: &str = "[OUTPUT:";
+/// Prefix of the names of the sections emitted by `output_once`, followed by the hex-encoded key.
+const ONCE_SECTION_PREFIX: &str = "once:";
+/// Prefix of the comment with the output hash, emitted if the `hash` option is set.
+const HASH_COMMENT_PREFIX: &str = "// crabtime-hash:";
+/// Prefix of lines with paths of files read by the macro, which trigger re-expansion on change.
+const TRACK_PREFIX: &str = "[TRACK]";
 const OUT_DIR: &str = env!("OUT_DIR");
+/// Maximum number of outputs kept in the output cache read by Rust Analyzer.
+const OUTPUT_CACHE_LIMIT: usize = 1024;
+/// Environment variable setting the default `rust_analyzer` macro option.
+const RUST_ANALYZER_MODE_ENV: &str = "CRABTIME_RUST_ANALYZER";
+/// Environment variable overriding the directory where the generated projects are stored, for
+/// example, when `OUT_DIR` does not follow the Cargo layout.
+const OUT_DIR_ENV: &str = "CRABTIME_OUT_DIR";
+/// Environment variable setting the default evaluation timeout, like `30s`.
+const TIMEOUT_ENV: &str = "CRABTIME_TIMEOUT";
+/// Environment variable setting `RUST_BACKTRACE` of the evaluated code. If not set, the value of
+/// `RUST_BACKTRACE` is forwarded.
+const BACKTRACE_ENV: &str = "CRABTIME_BACKTRACE";
+/// Environment variable setting the directory where the outputs of all macros are written.
+const DUMP_DIR_ENV: &str = "CRABTIME_DUMP_DIR";
+/// Environment variable enabling the display of the generated lines as they are produced.
+const VERBOSE_ENV: &str = "CRABTIME_VERBOSE";
+/// Environment variable disabling the removal of one-shot projects whose evaluation failed.
+const KEEP_ON_ERROR_ENV: &str = "CRABTIME_KEEP_ON_ERROR";
+/// Environment variable overriding the `cargo` binary used to build the generated projects.
+const CARGO_ENV: &str = "CRABTIME_CARGO";
+/// Environment variable overriding the `rustc` binary used to detect the host target and to build
+/// the generated projects.
+const RUSTC_ENV: &str = "CRABTIME_RUSTC";
+/// Environment variable passing `--offline`, or `--frozen` if set to `frozen`, to Cargo building
+/// the generated projects.
+const OFFLINE_ENV: &str = "CRABTIME_OFFLINE";
+/// Environment variable overriding the directory where the lock files of the `lock_file` option
+/// are stored.
+const LOCK_DIR_ENV: &str = "CRABTIME_LOCK_DIR";
+/// Environment variable of rustup selecting the toolchain, which takes precedence over the
+/// `rust-toolchain.toml` files and the toolchain of the calling build.
+const RUSTUP_TOOLCHAIN_ENV: &str = "RUSTUP_TOOLCHAIN";
+/// Toolchain building the generated projects using unstable features on the stable channel.
+const NIGHTLY_TOOLCHAIN: &str = "nightly";
+/// Environment variable passing the randomized sentinel, which delimits the generated code in the
+/// `sentinel_output` mode, to the evaluated code.
+const OUTPUT_SENTINEL_ENV: &str = "CRABTIME_OUTPUT_SENTINEL";
 
 /// Rust keywords for special handling. This is not needed for this macro to work, it is only used
 /// to make `IntelliJ` / `RustRover` work correctly, as their `TokenStream` spans are incorrect.
@@ -81,10 +147,31 @@ impl TokenRange {
 // === Generated Code Prelude ===
 // ==============================
 
-fn gen_prelude(include_token_stream_impl: bool, paths: &Paths) -> String {
+/// Parts of the prelude depending on the macro.
+#[derive(Default)]
+struct PreludeConfig<'a> {
+    /// The name under which `proc-macro2` is visible in the generated code.
+    token_stream_crate: Option<&'a str>,
+    /// The name under which `serde_json` is visible in the generated code.
+    json_crate: Option<&'a str>,
+    /// The names under which `toml` and `semver` are visible in the generated code.
+    manifest_crates: Option<(&'a str, &'a str)>,
+    input_spans: &'a [InputSpan],
+    /// If set, the generated code is printed between sentinel lines instead of with line prefixes.
+    sentinel_output: bool,
+}
+
+fn gen_prelude(config: &PreludeConfig, paths: &Paths) -> String {
     let warning_prefix = Level::WARNING_PREFIX;
     let error_prefix = Level::ERROR_PREFIX;
-    let prelude_tok_stream = if include_token_stream_impl { PRELUDE_FOR_TOKEN_STREAM } else { "" };
+    let prelude_tok_stream = config.token_stream_crate
+        .map(|crate_name| gen_prelude_for_token_stream(crate_name, config.input_spans))
+        .unwrap_or_default();
+    let prelude_json = config.json_crate.map(gen_prelude_for_json).unwrap_or_default();
+    let prelude_manifest = config.manifest_crates
+        .map(|(toml_crate, semver_crate)| gen_prelude_for_manifest(toml_crate, semver_crate))
+        .unwrap_or_default();
+    let sentinel_output = config.sentinel_output;
 
     let workspace_path =
         format!("pub const WORKSPACE_PATH: &str = r#\"{}\"#;", paths.workspace.display());
@@ -95,12 +182,32 @@ fn gen_prelude(include_token_stream_impl: bool, paths: &Paths) -> String {
     #[cfg(not(nightly))]
     let crate_config_path = "";
 
+    let manifest_dir = paths.manifest_dir().unwrap_or_default();
+    let manifest_dir_path =
+        format!("pub const MANIFEST_DIR_PATH: &str = r#\"{}\"#;", manifest_dir.display());
+
+    let under_rust_analyzer = is_rust_analyzer();
+    // The generated project is built by the same `rustc`, which is checked only if it is found.
+    let is_nightly = HostRustc::get().map_or(cfg!(nightly), |host| host.is_nightly);
+    let invocation_seed = paths.invocation_seed;
+    // In the verbose mode, the lines of `output!` are printed immediately instead of at exit.
+    let stream_output = verbose_mode();
+    let features = format!("pub const FEATURES: &[&str] = &{:?};", caller_features());
+    let (target, target_cfg) = CompileTarget::get();
+    let compile_target = format!("
+        pub const COMPILE_TARGET: &str = {target:?};
+        pub const TARGET_CFG: &[(&str, &str)] = &{target_cfg:?};
+    ");
+
     #[cfg(nightly)]
     let call_site_file_path =
         format!("pub const CALL_SITE_FILE_PATH: &str = r#\"{}\"#;", paths.call_site_file.display());
     #[cfg(not(nightly))]
     let call_site_file_path = "";
 
+    let call_site_file = format!("pub const CALL_SITE_FILE: &str = r#\"{}\"#;", call_site_file());
+    let memo_dir = format!("pub const MEMO_DIR: &str = r#\"{}\"#;", memo_dir().display());
+
     format!("
         #[allow(unused_macros)]
         #[allow(unused_imports)]
@@ -110,46 +217,316 @@ fn gen_prelude(include_token_stream_impl: bool, paths: &Paths) -> String {
             {workspace_path}
             {crate_config_path}
             {call_site_file_path}
-
+            {call_site_file}
+            {manifest_dir_path}
+            {memo_dir}
+            {features}
+            {compile_target}
+
+            pub const UNDER_RUST_ANALYZER: bool = {under_rust_analyzer};
+            pub const IS_NIGHTLY: bool = {is_nightly};
+            pub const INVOCATION_SEED: u64 = {invocation_seed};
+            pub const STREAM_OUTPUT: bool = {stream_output};
+            pub const SENTINEL_OUTPUT: bool = {sentinel_output};
+            pub const OUTPUT_SENTINEL_ENV: &str = \"{OUTPUT_SENTINEL_ENV}\";
+
+            pub static DEP_VERSIONS: std::sync::LazyLock<Vec<(&str, &str)>> =
+                std::sync::LazyLock::new(|| {{
+                    let lock = include_str!(concat!(env!(\"CARGO_MANIFEST_DIR\"), \"/Cargo.lock\"));
+                    dependency_versions(lock, env!(\"CARGO_PKG_NAME\"))
+                }});
             pub const OUTPUT_PREFIX: &str = \"{OUTPUT_PREFIX}\";
+            pub const OUTPUT_SECTION_PREFIX: &str = \"{OUTPUT_SECTION_PREFIX}\";
+            pub const ONCE_SECTION_PREFIX: &str = \"{ONCE_SECTION_PREFIX}\";
+            pub const TRACK_PREFIX: &str = \"{TRACK_PREFIX}\";
             pub const WARNING_PREFIX: &str = \"{warning_prefix}\";
             pub const ERROR_PREFIX: &str = \"{error_prefix}\";
 
             macro_rules! output_str {{
-                ($($ts:tt)*) => {{
-                    println!(\"{{}}\", {GEN_MOD}::prefix_lines_with_output(&format!($($ts)*)));
-                }};
+                ($($ts:tt)*) => {{{{
+                    {GEN_MOD}::print_output(&format!($($ts)*));
+                }}}};
             }}
             pub(super) use output_str;
 
             macro_rules! warning {{
-                ($($ts:tt)*) => {{
+                ($($ts:tt)*) => {{{{
                     println!(\"{{}}\", {GEN_MOD}::prefix_lines_with_warning(&format!($($ts)*)));
-                }};
+                }}}};
             }}
             pub(super) use warning;
 
             macro_rules! error {{
-                ($($ts:tt)*) => {{
+                ($($ts:tt)*) => {{{{
                     println!(\"{{}}\", {GEN_MOD}::prefix_lines_with_error(&format!($($ts)*)));
-                }};
+                }}}};
             }}
             pub(super) use error;
 
             {PRELUDE_STATIC}
             {prelude_tok_stream}
+            {prelude_json}
+            {prelude_manifest}
             {PRELUDE_ADDONS}
         }}
     ")
 }
 
-const PRELUDE_FOR_TOKEN_STREAM: &str = "
-    impl CodeFromOutput for proc_macro2::TokenStream {
-        fn code_from_output(output: Self) -> String {
-            output.to_string()
+/// Directory of the values stored by `memoize`, shared by all macros of the crate being compiled
+/// and kept between builds. Empty if the output root can't be determined.
+fn memo_dir() -> PathBuf {
+    let crate_name = std::env::var("CARGO_CRATE_NAME").unwrap_or_default();
+    Paths::get_output_root().map(|dir| dir.join("memo").join(crate_name)).unwrap_or_default()
+}
+
+/// The file where the macro was invoked, as displayed in diagnostics, like the `file!` macro.
+/// Empty if the call site does not come from a real source file, for example, if the invocation
+/// was generated from a string by another macro.
+fn call_site_file() -> String {
+    if !proc_macro::is_available() {
+        return String::new()
+    }
+    let span = proc_macro::Span::call_site();
+    span.local_file().map(|_| span.file()).unwrap_or_default()
+}
+
+/// Features enabled for the crate being compiled. Cargo passes them to rustc as
+/// `--cfg feature="name"` flags. The `CARGO_FEATURE_*` variables are checked as well, as they are
+/// set when the code is expanded in build scripts.
+fn caller_features() -> Vec<String> {
+    let mut features = features_from_rustc_args(std::env::args());
+    if features.is_empty() {
+        features = std::env::vars()
+            .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+            .collect();
+    }
+    features.sort();
+    features.dedup();
+    features
+}
+
+/// The target of the crate being compiled, which differs from the host target the generated
+/// project is built for when cross-compiling.
+struct CompileTarget;
+
+impl CompileTarget {
+    /// Returns the target triple and its configuration, like `("target_pointer_width", "64")`, as
+    /// printed by `rustc --print cfg`. Options without values, like `unix`, have empty values.
+    /// Computed once per compilation, as the target doesn't change.
+    fn get() -> &'static (String, Vec<(String, String)>) {
+        static COMPILE_TARGET: std::sync::OnceLock<(String, Vec<(String, String)>)> =
+            std::sync::OnceLock::new();
+        COMPILE_TARGET.get_or_init(|| {
+            let target = Self::triple();
+            let rustc = tool_path(RUSTC_ENV, "rustc");
+            let output = Command::new(rustc).args(["--print", "cfg", "--target", &target]).output();
+            let cfg = output.ok()
+                .filter(|output| output.status.success())
+                .map(|output| Self::parse_cfg(&String::from_utf8_lossy(&output.stdout)))
+                .unwrap_or_default();
+            (target, cfg)
+        })
+    }
+
+    /// The target passed to rustc with `--target`. Falls back to the `TARGET` variable, set when
+    /// the code is expanded in build scripts, and to the host target, used if none is passed.
+    fn triple() -> String {
+        Self::from_rustc_args(std::env::args())
+            .or_else(|| std::env::var("TARGET").ok().filter(|t| !t.is_empty()))
+            .or_else(|| HostRustc::get().ok().map(|host| host.target.clone()))
+            .unwrap_or_default()
+    }
+
+    fn from_rustc_args(args: impl IntoIterator<Item = String>) -> Option<String> {
+        let mut is_target = false;
+        for arg in args {
+            let target = if is_target { Some(arg.as_str()) } else { arg.strip_prefix("--target=") };
+            if let Some(target) = target {
+                return Some(target.to_string())
+            }
+            is_target = arg == "--target";
         }
+        None
     }
-";
+
+    fn parse_cfg(cfg: &str) -> Vec<(String, String)> {
+        cfg.lines().filter(|line| !line.trim().is_empty()).map(|line| {
+            match line.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.trim_matches('"').to_string()),
+                None => (line.to_string(), String::new()),
+            }
+        }).collect()
+    }
+}
+
+fn features_from_rustc_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut features = vec![];
+    let mut is_cfg = false;
+    for arg in args {
+        let cfg = if is_cfg { Some(arg.as_str()) } else { arg.strip_prefix("--cfg=") };
+        is_cfg = arg == "--cfg";
+        if let Some(name) = cfg.and_then(|cfg| cfg.strip_prefix("feature=")) {
+            features.push(name.trim_matches('"').to_string());
+        }
+    }
+    features
+}
+
+/// The `crate_name` is the name under which `proc-macro2` is visible in the generated code. It
+/// differs from `proc_macro2` if the dependency was renamed.
+fn gen_prelude_for_token_stream(crate_name: &str, input_spans: &[InputSpan]) -> String {
+    let input_spans = input_spans.iter()
+        .map(|(start, end)| {
+            format!("({}, {})..({}, {})", start.line, start.column, end.line, end.column)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("
+        /// Bound to the `TokenStream` parameters, also if `proc-macro2` is renamed.
+        pub use {crate_name}::TokenStream;
+
+        impl CodeFromOutput for {crate_name}::TokenStream {{
+            fn code_from_output(output: Self) -> String {{
+                output.to_string()
+            }}
+        }}
+
+        pub fn input_spans() -> Vec<std::ops::Range<(usize, usize)>> {{
+            vec![{input_spans}]
+        }}
+    ")
+}
+
+/// The `crate_name` is the name under which `serde_json` is visible in the generated code. A file
+/// which fails to parse is reported as an error instead of a panic.
+fn gen_prelude_for_json(crate_name: &str) -> String {
+    format!("
+        pub fn include_json(path: &str) -> {crate_name}::Value {{
+            let content = include_str(path);
+            {crate_name}::from_str(&content).unwrap_or_else(|err| {{
+                let (line, column) = (err.line(), err.column());
+                let message = format!(
+                    \"Failed to parse JSON file '{{path}}' at line {{line}}, column {{column}}: \\
+                    {{err}}\"
+                );
+                println!(\"{{}}\", prefix_lines_with_error(&message));
+                std::process::exit(1)
+            }})
+        }}
+    ")
+}
+
+/// The `toml_crate` and `semver_crate` are the names under which `toml` and `semver` are visible in
+/// the generated code. The requirement of the dependency in the manifest is checked by its lowest
+/// allowed version, as the lockfile of the calling crate is not inspected.
+fn gen_prelude_for_manifest(toml_crate: &str, semver_crate: &str) -> String {
+    format!("
+        pub fn require_dependency(name: &str, version_req: &str) {{
+            let manifest_path = std::path::Path::new(MANIFEST_DIR_PATH).join(\"Cargo.toml\");
+            let fail = |message: String| -> ! {{
+                println!(\"{{}}\", prefix_lines_with_error(&message));
+                std::process::exit(1)
+            }};
+            let parse_req = |version_req: &str, path: &std::path::Path| {{
+                {semver_crate}::VersionReq::parse(version_req).unwrap_or_else(|err| fail(format!(
+                    \"Invalid version requirement `{{version_req}}` of the `{{name}}` dependency \\
+                    in '{{}}': {{err}}\", path.display()
+                )))
+            }};
+            let required = parse_req(version_req, &manifest_path);
+            let parse_manifest = |content: &str, path: &std::path::Path| {{
+                content.parse::<{toml_crate}::Table>().unwrap_or_else(|err| fail(format!(
+                    \"Failed to parse '{{}}': {{err}}\", path.display()
+                )))
+            }};
+            let manifest = parse_manifest(&include_str(\"Cargo.toml\"), &manifest_path);
+            let mut spec = manifest_dependency(&manifest, name);
+            let inherited = spec.as_ref()
+                .and_then(|spec| spec.get(\"workspace\"))
+                .and_then(|t| t.as_bool())
+                .unwrap_or(false);
+            if inherited {{
+                let workspace_path = std::path::Path::new(WORKSPACE_PATH).join(\"Cargo.toml\");
+                let workspace = std::fs::read_to_string(&workspace_path).unwrap_or_default();
+                track_path(&workspace_path);
+                let workspace = parse_manifest(&workspace, &workspace_path);
+                spec = workspace.get(\"workspace\")
+                    .and_then(|t| t.get(\"dependencies\"))
+                    .and_then(|t| t.as_table())
+                    .and_then(|dependencies| find_dependency(dependencies, name));
+            }}
+            let Some(spec) = spec else {{
+                fail(format!(
+                    \"The macro requires the `{{name}}` dependency ({{version_req}}), but it is \\
+                    not in the [dependencies] of '{{}}'.\", manifest_path.display()
+                ))
+            }};
+            let version = match &spec {{
+                {toml_crate}::Value::String(version) => Some(version.as_str()),
+                spec => spec.get(\"version\").and_then(|t| t.as_str()),
+            }};
+            let Some(version) = version else {{
+                println!(\"{{}}\", prefix_lines_with_warning(&format!(
+                    \"Cannot check the version of the `{{name}}` dependency, as it has no version \\
+                    requirement in '{{}}'.\", manifest_path.display()
+                )));
+                return
+            }};
+            if !required.matches(&lowest_version(&parse_req(version, &manifest_path))) {{
+                fail(format!(
+                    \"The macro requires `{{name}}` {{version_req}}, but the crate depends on \\
+                    `{{name}}` {{version}} in '{{}}'.\", manifest_path.display()
+                ))
+            }}
+        }}
+
+        /// Finds the specification of the dependency in the `[dependencies]` of a manifest,
+        /// including the target-specific ones. Renamed dependencies are found by their `package`
+        /// key.
+        fn manifest_dependency(
+            manifest: &{toml_crate}::Table,
+            name: &str
+        ) -> Option<{toml_crate}::Value> {{
+            let targets = manifest.get(\"target\").and_then(|t| t.as_table());
+            let targets = targets.into_iter().flatten();
+            std::iter::once(manifest.get(\"dependencies\"))
+                .chain(targets.map(|(_, target)| target.get(\"dependencies\")))
+                .filter_map(|dependencies| dependencies?.as_table())
+                .find_map(|dependencies| find_dependency(dependencies, name))
+        }}
+
+        fn find_dependency(
+            dependencies: &{toml_crate}::Table,
+            name: &str
+        ) -> Option<{toml_crate}::Value> {{
+            dependencies.iter()
+                .find(|(key, spec)| {{
+                    spec.get(\"package\").and_then(|t| t.as_str()).unwrap_or(key.as_str()) == name
+                }})
+                .map(|(_, spec)| spec.clone())
+        }}
+
+        /// The lowest version allowed by a version requirement, like `1.2.0` for `^1.2, <1.5`.
+        fn lowest_version(version_req: &{semver_crate}::VersionReq) -> {semver_crate}::Version {{
+            use {semver_crate}::Op;
+            use {semver_crate}::Version;
+            version_req.comparators.iter().fold(Version::new(0, 0, 0), |lowest, cmp| {{
+                let bound = match (cmp.op, cmp.minor, cmp.patch) {{
+                    (Op::Less | Op::LessEq, _, _) => Version::new(0, 0, 0),
+                    (Op::Greater, None, _) => Version::new(cmp.major + 1, 0, 0),
+                    (Op::Greater, Some(minor), None) => Version::new(cmp.major, minor + 1, 0),
+                    (Op::Greater, Some(minor), Some(patch)) =>
+                        Version::new(cmp.major, minor, patch + 1),
+                    (_, minor, patch) => Version {{
+                        pre: cmp.pre.clone(),
+                        ..Version::new(cmp.major, minor.unwrap_or(0), patch.unwrap_or(0))
+                    }},
+                }};
+                lowest.max(bound)
+            }})
+        }}
+    ")
+}
 
 const PRELUDE_STATIC: &str = "
     pub(super) trait CodeFromOutput {
@@ -174,54 +551,149 @@ const PRELUDE_STATIC: &str = "
         }
     }
 
-    impl CodeFromOutput for usize {
-        fn code_from_output(output: Self) -> String {
-            format!(\"{output}\")
-        }
+    macro_rules! impl_code_from_output_for_int {
+        ($($t:ty),*) => {
+            $(
+                impl CodeFromOutput for $t {
+                    fn code_from_output(output: Self) -> String {
+                        format!(\"{output}\")
+                    }
+                }
+            )*
+        };
     }
 
-    impl CodeFromOutput for u8 {
-        fn code_from_output(output: Self) -> String {
-            format!(\"{output}\")
-        }
+    impl_code_from_output_for_int!(usize, u8, u16, u32, u64, u128);
+    impl_code_from_output_for_int!(isize, i8, i16, i32, i64, i128);
+
+    /// NaN and infinities have no literals, so they are emitted as the constants of the type.
+    macro_rules! impl_code_from_output_for_float {
+        ($($t:ty),*) => {
+            $(
+                impl CodeFromOutput for $t {
+                    fn code_from_output(output: Self) -> String {
+                        let tp = stringify!($t);
+                        if output.is_nan() {
+                            format!(\"{tp}::NAN\")
+                        } else if output == <$t>::INFINITY {
+                            format!(\"{tp}::INFINITY\")
+                        } else if output == <$t>::NEG_INFINITY {
+                            format!(\"{tp}::NEG_INFINITY\")
+                        } else {
+                            format!(\"{output}\")
+                        }
+                    }
+                }
+            )*
+        };
     }
 
-    impl CodeFromOutput for u16 {
-        fn code_from_output(output: Self) -> String {
-            format!(\"{output}\")
+    impl_code_from_output_for_float!(f32, f64);
+
+    /// Rust type name of values emitted with `emit_const`.
+    pub(super) trait TypeName {
+        fn type_name() -> String;
+
+        /// Code of the value in a `const` definition. Float literals need the type suffix, as `1.0`
+        /// is displayed as `1`.
+        fn const_code(value: Self) -> String where Self: CodeFromOutput + Sized {
+            code_from_output(value)
         }
     }
 
-    impl CodeFromOutput for u32 {
-        fn code_from_output(output: Self) -> String {
-            format!(\"{output}\")
-        }
+    macro_rules! impl_type_name {
+        ($($t:ty),*) => {
+            $(
+                impl TypeName for $t {
+                    fn type_name() -> String {
+                        stringify!($t).to_string()
+                    }
+                }
+            )*
+        };
     }
 
-    impl CodeFromOutput for u64 {
-        fn code_from_output(output: Self) -> String {
-            format!(\"{output}\")
-        }
+    macro_rules! impl_type_name_for_float {
+        ($($t:ty),*) => {
+            $(
+                impl TypeName for $t {
+                    fn type_name() -> String {
+                        stringify!($t).to_string()
+                    }
+
+                    fn const_code(value: Self) -> String {
+                        let code = code_from_output(value);
+                        if value.is_finite() { format!(\"{code}{}\", stringify!($t)) } else { code }
+                    }
+                }
+            )*
+        };
     }
 
-    impl CodeFromOutput for u128 {
-        fn code_from_output(output: Self) -> String {
-            format!(\"{output}\")
-        }
+    impl_type_name!(usize, u8, u16, u32, u64, u128);
+    impl_type_name!(isize, i8, i16, i32, i64, i128);
+    impl_type_name_for_float!(f32, f64);
+
+    pub(super) trait TableElement: TypeName + Copy {
+        fn to_le_bytes_vec(self) -> Vec<u8>;
+        fn to_be_bytes_vec(self) -> Vec<u8>;
+    }
+
+    macro_rules! impl_table_element {
+        ($($t:ty),*) => {
+            $(
+                impl TableElement for $t {
+                    fn to_le_bytes_vec(self) -> Vec<u8> {
+                        self.to_le_bytes().to_vec()
+                    }
+                    fn to_be_bytes_vec(self) -> Vec<u8> {
+                        self.to_be_bytes().to_vec()
+                    }
+                }
+            )*
+        };
     }
 
-    impl CodeFromOutput for f32 {
+    impl_table_element!(u8, u16, u32, u64, u128, f32, f64);
+
+    /// Result of a body returning `impl Iterator`. The code of the items is joined with newlines.
+    pub(super) struct OutputLines<I>(pub(super) I);
+
+    impl<I: IntoIterator<Item: CodeFromOutput>> CodeFromOutput for OutputLines<I> {
         fn code_from_output(output: Self) -> String {
-            format!(\"{output}\")
+            output.0.into_iter().map(code_from_output).collect::<Vec<_>>().join(\"\\n\")
         }
     }
 
-    impl CodeFromOutput for f64 {
+    /// Result of a body returning `impl Display`.
+    pub(super) struct OutputDisplay<T>(pub(super) T);
+
+    impl<T: std::fmt::Display> CodeFromOutput for OutputDisplay<T> {
         fn code_from_output(output: Self) -> String {
-            format!(\"{output}\")
+            output.0.to_string()
         }
     }
 
+    macro_rules! impl_code_from_output_for_tuple {
+        ($($t:ident),*) => {
+            impl<$($t: CodeFromOutput),*> CodeFromOutput for ($($t,)*) {
+                #[allow(non_snake_case)]
+                fn code_from_output(output: Self) -> String {
+                    let ($($t,)*) = output;
+                    [$(code_from_output($t)),*].join(\"\\n\")
+                }
+            }
+        };
+    }
+
+    impl_code_from_output_for_tuple!(T1, T2);
+    impl_code_from_output_for_tuple!(T1, T2, T3);
+    impl_code_from_output_for_tuple!(T1, T2, T3, T4);
+    impl_code_from_output_for_tuple!(T1, T2, T3, T4, T5);
+    impl_code_from_output_for_tuple!(T1, T2, T3, T4, T5, T6);
+    impl_code_from_output_for_tuple!(T1, T2, T3, T4, T5, T6, T7);
+    impl_code_from_output_for_tuple!(T1, T2, T3, T4, T5, T6, T7, T8);
+
     pub(super) fn code_from_output<T: CodeFromOutput>(output: T) -> String {
         <T as CodeFromOutput>::code_from_output(output)
     }
@@ -238,18 +710,95 @@ const PRELUDE_STATIC: &str = "
         prefix_lines_with(OUTPUT_PREFIX, input)
     }
 
+    /// Prints the generated code, prefixing its lines or, in the sentinel mode, between the lines
+    /// of the sentinel given by the macro, so logs can't be mistaken for the code.
+    pub(super) fn print_output(code: &str) {
+        print_output_section(\"\", code)
+    }
+
+    /// Like [`print_output`], but the code belongs to the named section. The default section has
+    /// an empty name.
+    pub(super) fn print_output_section(section: &str, code: &str) {
+        if SENTINEL_OUTPUT {
+            let sentinel = std::env::var(OUTPUT_SENTINEL_ENV).unwrap_or_default();
+            let begin = format!(\"{sentinel} BEGIN {section}\");
+            let newline = if code.is_empty() || code.ends_with('\\n') { \"\" } else { \"\\n\" };
+            println!(\"{}\\n{code}{newline}{sentinel} END\", begin.trim_end());
+        } else if section.is_empty() {
+            println!(\"{}\", prefix_lines_with_output(code));
+        } else {
+            let prefix = format!(\"{OUTPUT_SECTION_PREFIX}{section}]\");
+            println!(\"{}\", prefix_lines_with(&prefix, code));
+        }
+    }
+
+    pub fn output_to(section: &str, code: &str) {
+        print_output_section(section, code)
+    }
+
+    pub fn output_once(key: &str, code: &str) {
+        let key = key.bytes().map(|byte| format!(\"{byte:02x}\")).collect::<String>();
+        print_output_section(&format!(\"{ONCE_SECTION_PREFIX}{key}\"), code)
+    }
+
+    /// Returns the value stored for the key by an earlier evaluation of any macro in the crate, or
+    /// computes and stores it. The key has to change whenever the inputs of the computation do.
+    pub fn memoize(key: &str, compute: impl FnOnce() -> String) -> String {
+        if MEMO_DIR.is_empty() {
+            return compute()
+        }
+        // The FNV-1a hash, which is the same for all toolchains, unlike `DefaultHasher`.
+        let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        let file = std::path::Path::new(MEMO_DIR).join(format!(\"{hash:016x}\"));
+        if let Ok(stored) = std::fs::read_to_string(&file) {
+            if let Some((stored_key, value)) = stored.split_once('\\0') {
+                if stored_key == key {
+                    return value.to_string()
+                }
+            }
+        }
+        let value = compute();
+        // Written to a temporary file first, so concurrent evaluations never read a partial value.
+        let tmp = file.with_extension(format!(\"{}.tmp\", std::process::id()));
+        let written = std::fs::create_dir_all(MEMO_DIR)
+            .and_then(|_| std::fs::write(&tmp, format!(\"{key}\\0{value}\")))
+            .and_then(|_| std::fs::rename(&tmp, &file));
+        if let Err(err) = written {
+            let message = format!(\"Failed to store the memoized value of '{key}': {err}\");
+            println!(\"{}\", prefix_lines_with_warning(&message));
+        }
+        value
+    }
+
+    /// Prefix of the warning, error, and tracking lines. In the sentinel mode, it is the sentinel
+    /// followed by the marker, so logs can't be mistaken for them either.
+    pub(super) fn protocol_prefix(prefix: &str, marker: &str) -> String {
+        if SENTINEL_OUTPUT {
+            let sentinel = std::env::var(OUTPUT_SENTINEL_ENV).unwrap_or_default();
+            format!(\"{sentinel} {marker}\")
+        } else {
+            prefix.to_string()
+        }
+    }
+
     pub(super) fn prefix_lines_with_warning(input: &str) -> String {
-        prefix_lines_with(WARNING_PREFIX, input)
+        prefix_lines_with(&protocol_prefix(WARNING_PREFIX, \"WARNING\"), input)
     }
 
     pub(super) fn prefix_lines_with_error(input: &str) -> String {
-        prefix_lines_with(ERROR_PREFIX, input)
+        prefix_lines_with(&protocol_prefix(ERROR_PREFIX, \"ERROR\"), input)
     }
 
     macro_rules! write_ln {
         ($target:expr, $($ts:tt)*) => {
             $target.push_str(&format!( $($ts)* ));
             $target.push_str(\"\n\");
+            if crabtime::STREAM_OUTPUT {
+                crabtime::print_output(&$target);
+                $target.clear();
+            }
         };
     }
     pub(super) use write_ln;
@@ -260,6 +809,32 @@ const PRELUDE_STATIC: &str = "
     }
     pub(super) use stringify_if_needed;
 
+    pub(super) fn derive_attribute<T: AsRef<str>>(derives: &[T]) -> String {
+        if derives.is_empty() {
+            return String::new();
+        }
+        let derives = derives.iter().map(|t| t.as_ref()).collect::<Vec<_>>().join(\", \");
+        format!(\"#[derive({derives})]\")
+    }
+
+    static GENSYM_COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    /// Returns a unique identifier, like `__crabtime_tmp_1a2b3c4d_0`. It is seeded by the hash of
+    /// the macro body and a counter, so it is stable across builds.
+    pub(super) fn gensym(prefix: &str) -> String {
+        let hash = super::SOURCE_CODE.bytes().fold(0x811c9dc5_u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+        });
+        let counter = GENSYM_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        format!(\"__crabtime_{prefix}_{hash:08x}_{counter}\")
+    }
+
+    macro_rules! gensym_macro {
+        () => { crabtime::gensym(\"tmp\") };
+        ($prefix:expr) => { crabtime::gensym($prefix) };
+    }
+    pub(super) use gensym_macro as gensym;
+
     // This is defined only to prevent compilation errors. The real expansion is done by the
     // `function` attribute macro.
     macro_rules! output {
@@ -273,6 +848,13 @@ const PRELUDE_STATIC: &str = "
         ($($ts:tt)*) => { String::new() };
     }
     pub(super) use quote;
+
+    // This is defined only to prevent compilation errors. The real expansion is done by the
+    // `function` attribute macro.
+    macro_rules! derive_output {
+        ($($ts:tt)*) => {};
+    }
+    pub(super) use derive_output;
 ";
 
 const PRELUDE_ADDONS: &str = "
@@ -298,143 +880,667 @@ const PRELUDE_ADDONS: &str = "
         generate(n, vec![], &mut result);
         result
     }
-";
-
-// =============
-// === Paths ===
-// =============
-
-#[derive(Debug)]
-struct Paths {
-    workspace: PathBuf,
-    output_dir: PathBuf,
-    #[cfg(nightly)]
-    call_site_file: PathBuf,
-    #[cfg(nightly)]
-    crate_config: PathBuf,
-    // Whether we should remove `output_dir` after usage.
-    one_shot_output_dir: bool,
-    /// None if we are on stable.
-    cargo_toml_path: Option<CargoConfigPaths>,
-}
-
-impl Paths {
-    #[cfg(nightly)]
-    fn new(options: MacroOptions, macro_name: &str, input_str: &str) -> Result<Self> {
-        let name = if options.content_base_name {
-            Self::project_name_from_input(input_str)
-        } else {
-            macro_name.to_string()
-        };
-        let call_site_path = Self::get_call_site_rel();
-        let output_dir = Self::get_output_root()?.join(&call_site_path).join(&name);
-        let target = path::find_parent(&output_dir, "target")?;
-        let workspace = path::parent(target)?.to_path_buf();
-        let call_site_file = workspace.join(&call_site_path);
-        let cargo_toml_path = find_cargo_configs(&call_site_file)?;
-        let crate_config = cargo_toml_path.crate_config.clone();
-        let cargo_toml_path = Some(cargo_toml_path);
-        let one_shot_output_dir = false;
-        let out = Self {
-            workspace,
-            output_dir,
-            crate_config,
-            call_site_file,
-            cargo_toml_path,
-            one_shot_output_dir
-        }.init(options);
-        Ok(out)
-    }
 
-    #[cfg(not(nightly))]
-    fn new(options: MacroOptions, _macro_name: &str, input_str: &str) -> Result<Self> {
-        let name = Self::project_name_from_input(input_str);
-        let output_dir = Self::get_output_root()?.join(&name);
-        let target = path::find_parent(&output_dir, "target")?;
-        let workspace = path::parent(target)?.to_path_buf();
-        let cargo_toml_path = None;
-        let one_shot_output_dir = false;
-        Ok(Self { workspace, output_dir, cargo_toml_path, one_shot_output_dir }.init(options))
+    pub fn forward_methods(inner: &str, methods: &[(&str, &str)]) -> String {
+        methods.iter().map(|(name, signature)| {
+            let method = name.rsplit(' ').next().unwrap_or(name);
+            let visibility = &name[..name.len() - method.len()];
+            let args = forwarded_args(signature).join(\", \");
+            format!(\"{visibility}fn {method}{signature} {{ self.{inner}.{method}({args}) }}\")
+        }).collect::<Vec<_>>().join(\"\\n\")
     }
 
-    fn init(mut self, options: MacroOptions) -> Self {
-        // We cache projects on nightly by default. On stable, the project name is based on the
-        // input code.
-        self.one_shot_output_dir = cfg!(not(nightly)) || !options.cache;
-        // If we are removing projects after usage, it is possible that multiple processes try to
-        // expand the same macro in parallel – e.g. user's watch script and IDE checker. In such a
-        // case, one of the processes might end while another is still running. This can cause
-        // the other process to fail if it still needs project access on disk.
-        if self.one_shot_output_dir {
-            let pid = std::process::id();
-            self.output_dir = self.output_dir.join(format!("pid_{pid}"));
+    pub fn emit_where(bounds: &[(&str, &[&str])]) -> String {
+        let predicates = bounds.iter()
+            .filter(|(_, traits)| !traits.is_empty())
+            .map(|(tp, traits)| format!(\"{tp}: {}\", traits.join(\" + \")))
+            .collect::<Vec<_>>();
+        if predicates.is_empty() {
+            return String::new();
         }
-        self
+        format!(\"where {}\", predicates.join(\", \"))
     }
 
-    #[cfg(nightly)]
-    fn get_call_site_rel() -> PathBuf {
-        // Sometimes `proc_macro::Span::call_site()` returns a relative path, sometimes an absolute
-        // one. In the latter case, we need to discover the relative part from the project root.
-        let mut call_site_path = proc_macro::Span::call_site()
-            .local_file()
-            .unwrap_or_default();
-        call_site_path.set_extension("");
-        if call_site_path.is_relative() {
-            return call_site_path.to_path_buf();
-        }
-
-        // We strip the common prefix of `proc_macro::Span::call_site()` and `OUT_DIR`.
-        let mut common_prefix_len = 0;
-        let mut common_prefix = PathBuf::new();
-        let mut components1 = call_site_path.components();
-        let mut components2 = Path::new(OUT_DIR).components();
-        while let (Some(part1), Some(part2)) = (components1.next(), components2.next()) {
-            if part1 == part2 {
-                common_prefix_len += 1;
-                common_prefix.push(part1);
-            } else {
-                break;
-            }
-        }
-
-        // We don't want to strip small prefix, like `/` or `C:\\`. E.g. when running tests, cargo
-        // generates projects in `var/folders/wm/...`
-        if common_prefix_len <= 3 {
-            return call_site_path.to_path_buf();
-        }
+    pub fn emit_match_arms(arms: &[(&str, &str)]) -> String {
+        arms.iter()
+            .map(|(pattern, body)| format!(\"{} => {},\", pattern.trim(), body.trim()))
+            .collect::<Vec<_>>()
+            .join(\"\\n\")
+    }
 
-        match call_site_path.strip_prefix(&common_prefix) {
-            Ok(relative_path) => relative_path.to_path_buf(),
-            Err(_) => call_site_path.to_path_buf(), // Should not happen
+    pub fn emit_match(scrutinee: &str, arms: &[(&str, &str)]) -> String {
+        if arms.is_empty() {
+            return format!(\"match {} {{}}\", scrutinee.trim());
         }
+        let arms = emit_match_arms(arms).lines()
+            .map(|line| format!(\"    {line}\"))
+            .collect::<Vec<_>>()
+            .join(\"\\n\");
+        format!(\"match {} {{\\n{arms}\\n}}\", scrutinee.trim())
     }
 
-    fn project_name_from_input(input_str: &str) -> String {
-        let mut hasher = DefaultHasher::new();
-        input_str.hash(&mut hasher);
-        format!("project_{:016x}", hasher.finish())
+    pub fn emit_display(enum_name: &str, variants: &[(&str, &str)]) -> String {
+        let arms = variants.iter()
+            .map(|(variant, string)| {
+                (format!(\"Self::{variant}\"), format!(\"f.write_str({string:?})\"))
+            })
+            .collect::<Vec<_>>();
+        let arms = arms.iter().map(|(p, b)| (p.as_str(), b.as_str())).collect::<Vec<_>>();
+        let body = emit_match(\"*self\", &arms);
+        format!(\"impl ::core::fmt::Display for {enum_name} {{
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {{
+                {body}
+            }}
+        }}\")
     }
 
-    fn get_output_root() -> Result<PathBuf> {
-        let crate_out_str = OUT_DIR;
-        let crate_out = Path::new(&crate_out_str);
-        let build_dir = path::find_parent(crate_out, "build")?;
+    pub fn emit_from_str(enum_name: &str, variants: &[(&str, &str)]) -> String {
+        let expected = variants.iter().map(|(_, string)| *string).collect::<Vec<_>>().join(\", \");
+        let error = format!(\"Unknown variant of {enum_name}. Expected one of: {expected}.\");
+        let mut arms = variants.iter()
+            .map(|(variant, string)| (format!(\"{string:?}\"), format!(\"Ok(Self::{variant})\")))
+            .collect::<Vec<_>>();
+        arms.push((\"_\".to_string(), format!(\"Err({error:?})\")));
+        let arms = arms.iter().map(|(p, b)| (p.as_str(), b.as_str())).collect::<Vec<_>>();
+        let body = emit_match(\"s\", &arms);
+        format!(\"impl ::core::str::FromStr for {enum_name} {{
+            type Err = &'static str;
+            fn from_str(s: &str) -> ::core::result::Result<Self, Self::Err> {{
+                {body}
+            }}
+        }}\")
+    }
+
+    pub fn emit_variant_names(enum_name: &str, variants: &[&str]) -> String {
+        let names = variants.iter().map(|variant| format!(\"{variant:?}\")).collect::<Vec<_>>();
+        let names = names.join(\", \");
+        let arms = variants.iter()
+            .map(|variant| (format!(\"Self::{variant}\"), format!(\"{variant:?}\")))
+            .collect::<Vec<_>>();
+        let arms = arms.iter().map(|(p, b)| (p.as_str(), b.as_str())).collect::<Vec<_>>();
+        let body = emit_match(\"*self\", &arms);
+        format!(\"impl {enum_name} {{
+            pub const NAMES: &'static [&'static str] = &[{names}];
+            pub fn name(&self) -> &'static str {{
+                {body}
+            }}
+        }}\")
+    }
+
+    pub fn relative_use(depth: usize, path: &str) -> String {
+        let path = path.trim().trim_end_matches(';').trim_end();
+        let prefix = if depth == 0 { \"self::\".to_string() } else { \"super::\".repeat(depth) };
+        format!(\"use {prefix}{path};\")
+    }
+
+    pub fn emit_const<T: CodeFromOutput + TypeName>(name: &str, value: T) -> String {
+        let tp = <T as TypeName>::type_name();
+        format!(\"pub const {name}: {tp} = {};\", <T as TypeName>::const_code(value))
+    }
+
+    pub fn with_attrs(attrs: &[&str], item: &str) -> String {
+        let attrs = attrs.iter().map(|attr| {
+            let attr = attr.trim();
+            if attr.starts_with(\"#[\") { attr.to_string() } else { format!(\"#[{attr}]\") }
+        });
+        let item = std::iter::once(item.trim_start().to_string());
+        attrs.chain(item).collect::<Vec<_>>().join(\"\\n\")
+    }
+
+    pub fn emit_enum(name: &str, variants: &[(&str, &[&str])]) -> String {
+        let ident = name.rsplit(' ').next().unwrap_or(name);
+        let visibility = &name[..name.len() - ident.len()];
+        let variants = variants.iter()
+            .map(|(variant, attrs)| with_attrs(attrs, &format!(\"{},\", variant.trim())))
+            .collect::<Vec<_>>();
+        if variants.is_empty() {
+            return format!(\"{visibility}enum {ident} {{}}\");
+        }
+        let body = variants.iter()
+            .flat_map(|variant| variant.lines())
+            .map(|line| format!(\"    {line}\"))
+            .collect::<Vec<_>>()
+            .join(\"\\n\");
+        format!(\"{visibility}enum {ident} {{\\n{body}\\n}}\")
+    }
+
+    pub fn emit_parameterized_tests(
+        base_name: &str,
+        cases: &[(&str, &str)],
+        body_template: &str
+    ) -> String {
+        cases.iter().enumerate().map(|(index, (name, value))| {
+            let suffix = name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+                .collect::<String>();
+            let suffix = suffix.trim_matches('_');
+            let test_name = if suffix.is_empty() {
+                format!(\"{base_name}_{index}\")
+            } else {
+                format!(\"{base_name}_{index}_{suffix}\")
+            };
+            let body = body_template.replace(\"{{name}}\", name).replace(\"{{value}}\", value);
+            format!(\"#[cfg(test)]\\n#[test]\\nfn {test_name}() {{\\n    {}\\n}}\", body.trim())
+        }).collect::<Vec<_>>().join(\"\\n\")
+    }
+
+    pub fn emit_table<T: TableElement>(name: &str, values: &[T]) -> String {
+        let tp = <T as TypeName>::type_name();
+        let len = values.len();
+        let le = values.iter().flat_map(|value| value.to_le_bytes_vec()).collect::<Vec<_>>();
+        if tp == \"u8\" {
+            return format!(\"pub static {name}: [u8; {len}] = *b\\\"{}\\\";\", escape_bytes(&le));
+        }
+        let be = values.iter().flat_map(|value| value.to_be_bytes_vec()).collect::<Vec<_>>();
+        let bytes_len = le.len();
+        let static_for = |endian: &str, bytes: &[u8]| format!(
+            \"#[cfg(target_endian = \\\"{endian}\\\")]
+            #[allow(unsafe_code)]
+            pub static {name}: [{tp}; {len}] = unsafe {{
+                ::core::mem::transmute::<[u8; {bytes_len}], [{tp}; {len}]>(*b\\\"{}\\\")
+            }};\",
+            escape_bytes(bytes)
+        );
+        format!(\"{}\\n{}\", static_for(\"little\", &le), static_for(\"big\", &be))
+    }
+
+    /// Escapes bytes for a byte string literal. Printable ASCII characters are kept as they are,
+    /// which makes the literal shorter.
+    fn escape_bytes(bytes: &[u8]) -> String {
+        let mut out = String::with_capacity(bytes.len() * 4);
+        for &byte in bytes {
+            match byte {
+                b'\"' | b'\\\\' => out.push_str(&format!(\"\\\\x{byte:02x}\")),
+                0x20..=0x7e => out.push(byte as char),
+                _ => out.push_str(&format!(\"\\\\x{byte:02x}\")),
+            }
+        }
+        out
+    }
+
+    /// Finds the versions of the direct dependencies of the package in the lockfile. A dependency
+    /// is listed as `name` if there is one package with this name, or as `name version` otherwise.
+    fn dependency_versions<'t>(lock: &'t str, package: &str) -> Vec<(&'t str, &'t str)> {
+        let mut packages = vec![];
+        let mut dependencies = vec![];
+        let mut current = (\"\", \"\");
+        let mut in_dependencies = false;
+        for line in lock.lines().map(str::trim) {
+            let value = |line: &'t str, key: &str| line.strip_prefix(key)
+                .and_then(|t| t.trim_start().strip_prefix('='))
+                .map(|t| t.trim().trim_matches('\"'));
+            if line == \"[[package]]\" {
+                current = (\"\", \"\");
+            } else if let Some(name) = value(line, \"name\") {
+                current.0 = name;
+            } else if let Some(version) = value(line, \"version\") {
+                current.1 = version;
+                packages.push(current);
+            } else if line.starts_with(\"dependencies\") {
+                in_dependencies = current.0 == package;
+            } else if in_dependencies && line.starts_with('\"') {
+                dependencies.push(line.trim_end_matches(',').trim_matches('\"'));
+            } else if line == \"]\" {
+                in_dependencies = false;
+            }
+        }
+        dependencies.iter().filter_map(|dependency| match dependency.split_once(' ') {
+            Some((name, version)) => Some((name, version)),
+            None => packages.iter().find(|(name, _)| name == dependency).copied(),
+        }).collect()
+    }
+
+    pub fn has_feature(name: &str) -> bool {
+        FEATURES.contains(&name)
+    }
+
+    pub fn target_cfg(key: &str) -> Option<&'static str> {
+        TARGET_CFG.iter().find(|(name, _)| *name == key).map(|(_, value)| *value)
+    }
+
+    pub fn target_pointer_width() -> u32 {
+        target_cfg(\"target_pointer_width\").and_then(|t| t.parse().ok()).unwrap_or(usize::BITS)
+    }
+
+    pub fn include_str(path: &str) -> String {
+        let file = std::path::Path::new(MANIFEST_DIR_PATH).join(path);
+        let content = std::fs::read_to_string(&file)
+            .unwrap_or_else(|err| panic!(\"Failed to read '{}': {err}\", file.display()));
+        track_path(&file);
+        content
+    }
+
+    pub fn glob(pattern: &str) -> Vec<std::path::PathBuf> {
+        let segments = pattern.split('/').filter(|t| !t.is_empty()).collect::<Vec<_>>();
+        let mut matches = vec![];
+        let root = std::path::Path::new(MANIFEST_DIR_PATH);
+        glob_walk(root, std::path::PathBuf::new(), &segments, &mut matches);
+        matches.sort();
+        matches.dedup();
+        matches
+    }
+
+    /// Matches the pattern segments against the entries of `root.join(rel)`. The `**` segment
+    /// matches any number of directories. Listed directories are tracked, so the macro is expanded
+    /// again when their entries change.
+    fn glob_walk(
+        root: &std::path::Path,
+        rel: std::path::PathBuf,
+        segments: &[&str],
+        matches: &mut Vec<std::path::PathBuf>,
+    ) {
+        let Some((segment, rest)) = segments.split_first() else {
+            if root.join(&rel).exists() {
+                matches.push(rel);
+            }
+            return;
+        };
+        if !segment.contains(['*', '?']) {
+            return glob_walk(root, rel.join(segment), rest, matches);
+        }
+        let dir = root.join(&rel);
+        let Ok(entries) = std::fs::read_dir(&dir) else { return };
+        track_path(&dir);
+        let mut names = entries.filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        if *segment == \"**\" {
+            glob_walk(root, rel.clone(), rest, matches);
+        }
+        for name in names {
+            let is_hidden = name.starts_with('.') && !segment.starts_with('.');
+            if *segment == \"**\" {
+                if !is_hidden && root.join(&rel).join(&name).is_dir() {
+                    glob_walk(root, rel.join(&name), segments, matches);
+                }
+            } else if !is_hidden && wildcard_match(&chars(segment), &chars(&name)) {
+                glob_walk(root, rel.join(&name), rest, matches);
+            }
+        }
+    }
+
+    /// Matches a name against a pattern, where `*` matches any sequence of characters and `?`
+    /// matches a single character.
+    fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+        match (pattern.split_first(), name.split_first()) {
+            (None, _) => name.is_empty(),
+            (Some(('*', rest)), _) => wildcard_match(rest, name)
+                || (!name.is_empty() && wildcard_match(pattern, &name[1..])),
+            (Some(('?', rest)), Some((_, name_rest))) => wildcard_match(rest, name_rest),
+            (Some((p, rest)), Some((n, name_rest))) => p == n && wildcard_match(rest, name_rest),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    pub fn read_csv(path: &str) -> Vec<Vec<String>> {
+        let content = include_str(path);
+        let is_tsv = std::path::Path::new(path).extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(\"tsv\"));
+        parse_csv(&content, if is_tsv { '\\t' } else { ',' })
+    }
+
+    /// Makes the compiler expand the macro again when the file changes.
+    fn track_path(path: &std::path::Path) {
+        println!(\"{} {}\", protocol_prefix(TRACK_PREFIX, \"TRACK\"), path.display());
+    }
+
+    /// Parses fields separated by the delimiter. Fields can be quoted to contain delimiters, line
+    /// breaks, and quotes, which are escaped by doubling them. Empty lines are skipped.
+    fn parse_csv(content: &str, delimiter: char) -> Vec<Vec<String>> {
+        let mut rows = vec![];
+        let mut row = vec![];
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut chars = content.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_quotes {
+                match c {
+                    '\"' if chars.peek() == Some(&'\"') => {
+                        chars.next();
+                        field.push('\"');
+                    }
+                    '\"' => in_quotes = false,
+                    _ => field.push(c),
+                }
+            } else if c == '\"' && field.is_empty() {
+                in_quotes = true;
+            } else if c == delimiter {
+                row.push(std::mem::take(&mut field));
+            } else if c == '\\n' || c == '\\r' {
+                if c == '\\r' && chars.peek() == Some(&'\\n') {
+                    chars.next();
+                }
+                row.push(std::mem::take(&mut field));
+                let row = std::mem::take(&mut row);
+                if row != [\"\"] {
+                    rows.push(row);
+                }
+            } else {
+                field.push(c);
+            }
+        }
+        if !field.is_empty() || !row.is_empty() {
+            row.push(field);
+            rows.push(row);
+        }
+        rows
+    }
+
+    /// Marks files written by `emit_mod_file`. Files without it are never overwritten.
+    pub const GENERATED_FILE_HEADER: &str =
+        \"// @generated by crabtime. Do not edit, changes will be overwritten.\";
+
+    pub fn emit_mod_file(name: &str, path: &str, code: &str) -> String {
+        let file = std::path::Path::new(MANIFEST_DIR_PATH).join(path);
+        let content = format!(\"{GENERATED_FILE_HEADER}\\n{code}\\n\");
+        match std::fs::read_to_string(&file) {
+            // Rewriting an unchanged file would trigger a rebuild.
+            Ok(existing) if existing == content => {}
+            Ok(existing) if !existing.starts_with(GENERATED_FILE_HEADER) => panic!(
+                \"Refusing to overwrite '{}', which was not generated by crabtime.\",
+                file.display()
+            ),
+            _ => {
+                let dir = file.parent().unwrap_or(std::path::Path::new(MANIFEST_DIR_PATH));
+                std::fs::create_dir_all(dir)
+                    .and_then(|_| std::fs::write(&file, content))
+                    .unwrap_or_else(|err| panic!(\"Failed to write '{}': {err}\", file.display()));
+            }
+        }
+        format!(\"#[path = {:?}] mod {name};\", file.display().to_string())
+    }
+
+    /// Names of the non-receiver parameters of a method signature.
+    fn forwarded_args(signature: &str) -> Vec<String> {
+        let mut params = vec![];
+        let mut param = String::new();
+        let mut in_params = false;
+        let mut depth = 0;
+        let mut prev = ' ';
+        for c in signature.chars() {
+            match c {
+                '(' if !in_params && depth == 0 => {
+                    in_params = true;
+                    prev = c;
+                    continue;
+                }
+                ')' if in_params && depth == 0 => {
+                    params.push(std::mem::take(&mut param));
+                    break;
+                }
+                ',' if in_params && depth == 0 => {
+                    params.push(std::mem::take(&mut param));
+                    prev = c;
+                    continue;
+                }
+                '<' | '(' | '[' | '{' => depth += 1,
+                // The `>` of `->` in closure types, like `impl Fn(u8) -> u8`, is not a bracket.
+                '>' if prev != '-' => depth -= 1,
+                ')' | ']' | '}' => depth -= 1,
+                _ => {}
+            }
+            if in_params {
+                param.push(c);
+            }
+            prev = c;
+        }
+        params.iter().filter_map(|param| {
+            let pattern = param.split(':').next()?.trim();
+            let pattern = pattern.strip_prefix(\"mut \").unwrap_or(pattern).trim();
+            (!pattern.is_empty() && !pattern.ends_with(\"self\")).then(|| pattern.to_string())
+        }).collect()
+    }
+";
+
+// =============
+// === Paths ===
+// =============
+
+#[derive(Debug)]
+struct Paths {
+    workspace: PathBuf,
+    output_dir: PathBuf,
+    #[cfg(nightly)]
+    call_site_file: PathBuf,
+    #[cfg(nightly)]
+    crate_config: PathBuf,
+    // Whether we should remove `output_dir` after usage.
+    one_shot_output_dir: bool,
+    /// Hash of the macro input, exposed to the macro body as `INVOCATION_SEED`.
+    invocation_seed: u64,
+    /// None if we are on stable.
+    cargo_toml_path: Option<CargoConfigPaths>,
+}
+
+impl Paths {
+    #[cfg(nightly)]
+    fn new(options: MacroOptions, macro_name: &str, input_str: &str) -> Result<Self> {
+        let name = if options.content_base_name {
+            Self::project_name_from_input(input_str)
+        } else {
+            macro_name.to_string()
+        };
+        let call_site_path = Self::get_call_site_rel();
+        let output_dir = Self::get_output_root()?.join(&call_site_path).join(&name);
+        let workspace = Self::get_workspace()?;
+        let call_site_file = workspace.join(&call_site_path);
+        let cargo_toml_path = find_cargo_configs(&call_site_file)?;
+        let crate_config = cargo_toml_path.crate_config.clone();
+        let cargo_toml_path = Some(cargo_toml_path);
+        let one_shot_output_dir = false;
+        let invocation_seed = Self::input_hash(input_str);
+        let out = Self {
+            workspace,
+            output_dir,
+            crate_config,
+            call_site_file,
+            cargo_toml_path,
+            one_shot_output_dir,
+            invocation_seed,
+        }.init(options);
+        Ok(out)
+    }
+
+    #[cfg(not(nightly))]
+    fn new(options: MacroOptions, _macro_name: &str, input_str: &str) -> Result<Self> {
+        let name = Self::project_name_from_input(input_str);
+        let output_dir = Self::get_output_root()?.join(&name);
+        let workspace = Self::get_workspace()?;
+        let cargo_toml_path = None;
+        let one_shot_output_dir = false;
+        let invocation_seed = Self::input_hash(input_str);
+        let out = Self {
+            workspace,
+            output_dir,
+            cargo_toml_path,
+            one_shot_output_dir,
+            invocation_seed,
+        }.init(options);
+        Ok(out)
+    }
+
+    fn init(mut self, options: MacroOptions) -> Self {
+        // We cache projects on nightly by default. On stable, the project name is based on the
+        // input code, so caching is opt-in, as every change of the input creates a new project.
+        self.one_shot_output_dir = !options.cache;
+        // If we are removing projects after usage, it is possible that multiple processes try to
+        // expand the same macro in parallel – e.g. user's watch script and IDE checker. In such a
+        // case, one of the processes might end while another is still running. This can cause
+        // the other process to fail if it still needs project access on disk.
+        if self.one_shot_output_dir {
+            let pid = std::process::id();
+            self.output_dir = self.output_dir.join(format!("pid_{pid}"));
+        }
+        self
+    }
+
+    #[cfg(nightly)]
+    fn get_call_site_rel() -> PathBuf {
+        // Sometimes `proc_macro::Span::call_site()` returns a relative path, sometimes an absolute
+        // one. In the latter case, we need to discover the relative part from the project root.
+        let mut call_site_path = proc_macro::Span::call_site()
+            .local_file()
+            .unwrap_or_default();
+        call_site_path.set_extension("");
+        if call_site_path.is_relative() {
+            return call_site_path.to_path_buf();
+        }
+
+        // We strip the common prefix of `proc_macro::Span::call_site()` and `OUT_DIR`.
+        let mut common_prefix_len = 0;
+        let mut common_prefix = PathBuf::new();
+        let mut components1 = call_site_path.components();
+        let mut components2 = Path::new(OUT_DIR).components();
+        while let (Some(part1), Some(part2)) = (components1.next(), components2.next()) {
+            if part1 == part2 {
+                common_prefix_len += 1;
+                common_prefix.push(part1);
+            } else {
+                break;
+            }
+        }
+
+        // We don't want to strip small prefix, like `/` or `C:\\`. E.g. when running tests, cargo
+        // generates projects in `var/folders/wm/...`
+        if common_prefix_len <= 3 {
+            return call_site_path.to_path_buf();
+        }
+
+        match call_site_path.strip_prefix(&common_prefix) {
+            Ok(relative_path) => relative_path.to_path_buf(),
+            Err(_) => call_site_path.to_path_buf(), // Should not happen
+        }
+    }
+
+    fn project_name_from_input(input_str: &str) -> String {
+        format!("project_{:016x}", Self::input_hash(input_str))
+    }
+
+    fn input_hash(input_str: &str) -> u64 {
+        fnv_hash(input_str)
+    }
+
+    /// Directory of the generated projects, their shared target directory, and the output cache.
+    /// The [`OUT_DIR_ENV`] variable takes precedence over the `build` directory found from
+    /// `OUT_DIR`.
+    fn get_output_root() -> Result<PathBuf> {
+        if let Some(dir) = std::env::var_os(OUT_DIR_ENV).filter(|t| !t.is_empty()) {
+            return Ok(PathBuf::from(dir));
+        }
+        let crate_out = Path::new(OUT_DIR);
+        let build_dir = path::find_parent(crate_out, "build").map_err(|_| error!(
+            "Cannot locate the directory for the generated projects. Crabtime expects `OUT_DIR` \
+            to follow the Cargo layout `<target>/<profile>/build/<crate>-<hash>/out`, but it is \
+            '{}'. This happens with build systems other than Cargo. Set the `{OUT_DIR_ENV}` \
+            environment variable to a writable directory to override this location.",
+            crate_out.display()
+        ))?;
         Ok(build_dir.join(CRATE))
     }
 
+    /// The workspace directory, which contains the `target` directory. Falls back to the manifest
+    /// directory of the calling crate if `OUT_DIR` does not follow the Cargo layout.
+    fn get_workspace() -> Result<PathBuf> {
+        match path::find_parent(Path::new(OUT_DIR), "target") {
+            Ok(target) => Ok(path::parent(target)?.to_path_buf()),
+            Err(err) => std::env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from).ok_or(err),
+        }
+    }
+
+    /// Target directory shared by all generated projects, so their dependencies are compiled once.
+    fn target_dir() -> Result<PathBuf> {
+        Ok(Self::get_output_root()?.join("target"))
+    }
+
+    /// Name of the generated project. Projects share the target directory, so their binaries need
+    /// distinct names.
+    fn eval_project_name(&self) -> String {
+        let hash = Self::project_name_from_input(&self.output_dir.to_string_lossy());
+        format!("eval_{hash}")
+    }
+
+    /// Removes artifacts of a one-shot project from the shared target directory. Artifacts of its
+    /// dependencies are kept, as other projects reuse them.
+    fn remove_target_artifacts(&self) {
+        let Ok(target_dir) = Self::target_dir() else { return };
+        let name = self.eval_project_name();
+        let prefix = format!("{name}-");
+        let is_artifact = |file_name: &str| {
+            let stem = file_name.split('.').next().unwrap_or_default();
+            stem == name || file_name.starts_with(&prefix)
+        };
+        // Artifacts are placed in `<target>/<host>/<profile>` and its subdirectories.
+        for profile_dir in path::read_dir(&target_dir).iter().flat_map(|t| path::read_dir(t)) {
+            let sub_dirs = ["deps", ".fingerprint", "incremental"].map(|t| profile_dir.join(t));
+            let dirs = std::iter::once(profile_dir.clone()).chain(sub_dirs);
+            for entry in dirs.flat_map(|t| path::read_dir(&t)) {
+                if entry.file_name().is_some_and(|t| is_artifact(&t.to_string_lossy())) {
+                    path::remove(&entry);
+                }
+            }
+        }
+    }
+
+    /// File storing the output of the last evaluation of the given input.
+    fn output_cache_file(input_str: &str) -> Result<PathBuf> {
+        let name = Self::project_name_from_input(input_str);
+        Ok(Self::get_output_root()?.join("output_cache").join(format!("{name}.rs")))
+    }
+
+    /// Directory of the `Cargo.toml` of the crate calling the macro.
+    fn manifest_dir(&self) -> Option<PathBuf> {
+        self.cargo_toml_path.as_ref()
+            .and_then(|t| t.crate_config.parent().map(Path::to_path_buf))
+            .or_else(|| std::env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from))
+    }
+
     fn with_output_dir<T>(&self, f: impl FnOnce(&PathBuf) -> Result<T>) -> Result<T> {
+        // One-shot projects are unique per process, so only persistent ones need to be locked.
+        if !self.one_shot_output_dir {
+            return with_project_lock(&self.output_dir, || self.with_output_dir_unlocked(f))
+        }
+        self.with_output_dir_unlocked(f)
+    }
+
+    fn with_output_dir_unlocked<T>(&self, f: impl FnOnce(&PathBuf) -> Result<T>) -> Result<T> {
         if !self.output_dir.exists() {
             fs::create_dir_all(&self.output_dir).context("Failed to create project directory.")?;
         }
         let out = f(&self.output_dir);
-        if self.one_shot_output_dir {
+        if self.one_shot_output_dir && out.is_err() && keep_on_error() {
+            let dir = self.output_dir.display();
+            print_warning!("The project of the failed evaluation was kept in '{dir}'.");
+        } else if self.one_shot_output_dir {
+            // Cargo locks the shared target directory while building, so concurrent builds wait
+            // for each other instead of corrupting it. The lock is not held while the binary runs,
+            // but one-shot project names contain the process id, so the cleanup never removes
+            // artifacts used by another process.
             fs::remove_dir_all(&self.output_dir).ok();
+            self.remove_target_artifacts();
         }
         out
     }
 }
 
+/// Runs `f` holding an exclusive lock of the project directory. Concurrent evaluations of the same
+/// project, like the ones run by the compiler and the IDE, wait for each other instead of
+/// overwriting or removing files used by another process. The lock file is placed next to the
+/// project, as it must outlive the project directory.
+fn with_project_lock<T>(project_dir: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let lock_path = project_dir.with_extension("lock");
+    fs::create_dir_all(path::parent(&lock_path)?).context("Failed to create project directory.")?;
+    let lock_file = File::create(&lock_path).context("Failed to create project lock file.")?;
+    lock_file.lock().context("Failed to lock the project directory.")?;
+    let out = f();
+    lock_file.unlock().ok();
+    out
+}
+
 // ========================
 // === CargoConfigPaths ===
 // ========================
@@ -488,6 +1594,39 @@ impl Dependency {
         format!("{} = {}", self.label, self.tokens_str)
     }
 
+    /// The package name. It differs from the label for renamed dependencies, like
+    /// `pm2 = { package = "proc-macro2", version = "1" }`.
+    fn package(&self) -> String {
+        toml::from_str::<toml::Table>(&self.to_config_string()).ok()
+            .and_then(|t| t.get(&self.label)?.get("package")?.as_str().map(|s| s.to_string()))
+            .unwrap_or_else(|| self.label.clone())
+    }
+
+    /// Whether the dependency is a local crate, whose sources can change without changing the
+    /// dependency specification.
+    fn is_path(&self) -> bool {
+        toml::from_str::<toml::Table>(&self.to_config_string()).ok()
+            .is_some_and(|t| t.get(&self.label).and_then(|v| v.get("path")).is_some())
+    }
+
+    /// The name under which the dependency is visible in Rust code.
+    fn crate_name(&self) -> String {
+        self.label.replace('-', "_")
+    }
+
+    /// Makes a relative `path` of the dependency absolute. Otherwise, it would be resolved against
+    /// the generated project directory.
+    fn resolve_path(&mut self, base_dir: &Path) {
+        let Ok(mut spec) = toml::from_str::<toml::Table>(&format!("spec = {}", self.tokens_str))
+            else { return };
+        let Some(toml::Value::String(path)) = spec.get_mut("spec").and_then(|t| t.get_mut("path"))
+            else { return };
+        if Path::new(path).is_relative() {
+            *path = base_dir.join(&path).display().to_string();
+            self.tokens_str = spec["spec"].to_string();
+        }
+    }
+
     #[cfg(nightly)]
     fn span(&self) -> Span {
         self.token_range.as_ref().map_or(Span::call_site(), |t| t.span())
@@ -498,8 +1637,38 @@ impl Dependency {
 struct CargoConfig {
     edition: Option<String>,
     resolver: Option<String>,
+    /// The `rust-version` of the calling crate, so the project is not built with an older
+    /// toolchain.
+    rust_version: Option<String>,
     dependencies: Vec<Dependency>,
     lints: LintsConfig,
+    /// Settings of the profile used to build the generated project, as `(key, value)` pairs.
+    profile: Vec<(String, String)>,
+    /// Whether the generated project is built with `--release`.
+    release: bool,
+    /// Whether the code is placed in `src/lib.rs` instead of `src/main.rs`. It is still built as a
+    /// binary target, as the `#![no_main]` harness provides the entry point.
+    lib_target: bool,
+    /// Package name, unique per project, as all projects share the target directory.
+    name: Option<String>,
+    /// Edition of the crate invoking the macro. Known only on nightly.
+    caller_edition: Option<String>,
+    /// Minimum edition required by the emitted code, set with `#![min_edition(...)]`.
+    min_edition: Option<(u32, Span)>,
+    /// Time after which the evaluation is terminated, set with `#![timeout(...)]`.
+    timeout: Option<Duration>,
+    /// Index of the registry replacing crates.io, set with `#![registry_index(...)]`.
+    registry_index: Option<String>,
+    /// Specs of the automatically added dependencies, as `(name, spec)` pairs, set in the
+    /// `[package.metadata.crabtime.default_dep_versions]` table of the calling crate.
+    default_dep_versions: Vec<(String, String)>,
+    /// Span of the first `#![feature(...)]` attribute, which requires a nightly toolchain.
+    unstable_feature: Option<Span>,
+    /// Whether the output is checked in a `no_std` crate, set with `#![no_std]`.
+    no_std: bool,
+    /// Whether the `Cargo.lock` is stored per dependency set and reused, set by the `lock_file`
+    /// option.
+    lock_file: bool,
 }
 
 #[derive(Debug, Default)]
@@ -509,8 +1678,12 @@ struct LintsConfig {
 }
 
 impl CargoConfig {
-    fn contains_dependency(&self, name: &str) -> bool {
-        self.dependencies.iter().any(|d| d.label == name)
+    fn find_dependency(&self, package: &str) -> Option<&Dependency> {
+        self.dependencies.iter().find(|d| d.package() == package)
+    }
+
+    fn contains_dependency(&self, package: &str) -> bool {
+        self.find_dependency(package).is_some()
     }
 
     fn print(&self) -> String {
@@ -522,13 +1695,36 @@ impl CargoConfig {
             .map(|t| t.to_config_string())
             .collect::<Vec<_>>()
             .join("\n");
+        // The binary is built from `src/lib.rs`, which must not be built as a library as well.
+        let name = self.package_name();
+        let (autolib, bin_target) = if self.lib_target {
+            let bin_target = format!("
+                [[bin]]
+                name = \"{name}\"
+                path = \"src/{LIB_TARGET_FILE}\"
+            ");
+            ("autolib  = false", bin_target)
+        } else {
+            ("", String::new())
+        };
+        let rust_version = self.rust_version.as_ref()
+            .map(|t| format!("rust-version = \"{t}\""))
+            .unwrap_or_default();
+        let profile_name = self.profile_name();
+        let profile = if self.profile.is_empty() { String::new() } else {
+            let settings = self.profile.iter().map(|(key, value)| format!("{key} = {value}"));
+            format!("[profile.{profile_name}]\n{}", settings.collect::<Vec<_>>().join("\n"))
+        };
         let out = format!("
             [workspace]
             [package]
-            name     = \"eval_project\"
+            name     = \"{name}\"
             version  = \"1.0.0\"
             edition  = \"{edition}\"
             resolver = \"{resolver}\"
+            {rust_version}
+            {autolib}
+            {bin_target}
 
             [dependencies]
             {dependencies}
@@ -538,10 +1734,57 @@ impl CargoConfig {
 
             [lints.clippy]
             {lints_clippy}
+
+            {profile}
         ");
         out
     }
 
+    /// Sets a profile setting. Settings given by both `opt_level` and `eval_profile` would be
+    /// duplicate keys in `Cargo.toml`, so the later one wins.
+    fn set_profile_setting(&mut self, key: String, value: String) {
+        self.profile.retain(|(other_key, _)| *other_key != key);
+        self.profile.push((key, value));
+    }
+
+    /// Prints the `.cargo/config.toml` of the generated project. It takes precedence over the
+    /// configuration files found in the parent directories and in the Cargo home directory.
+    fn print_cargo_config(&self) -> Option<String> {
+        self.registry_index.as_ref().map(|index| format!("
+            [source.crates-io]
+            replace-with = \"crabtime-registry\"
+
+            [source.crabtime-registry]
+            registry = {index:?}
+        "))
+    }
+
+    /// Name of the package of the generated project.
+    fn package_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(EVAL_PROJECT_NAME)
+    }
+
+    /// File storing the `Cargo.lock` shared by the projects with the same dependencies. The
+    /// dependencies are sorted, so their order doesn't change the key. The key is hashed with
+    /// [`fnv_hash`], so it is the same for all toolchains.
+    fn lock_file_path(&self) -> Result<PathBuf> {
+        let mut dependencies = self.dependencies.iter()
+            .map(Dependency::to_config_string)
+            .collect::<Vec<_>>();
+        dependencies.sort();
+        let cargo_config = self.print_cargo_config().unwrap_or_default();
+        let key = format!("{}\n{cargo_config}", dependencies.join("\n"));
+        let dir = match std::env::var_os(LOCK_DIR_ENV).filter(|t| !t.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => Paths::get_output_root()?.join("locks"),
+        };
+        Ok(dir.join(format!("{:016x}.lock", fnv_hash(&key))))
+    }
+
+    fn profile_name(&self) -> &'static str {
+        if self.release { "release" } else { "dev" }
+    }
+
     fn is_workspace(path: &Path) -> Result<bool> {
         let cargo_toml_content = fs::read_to_string(path)?;
         let parsed: toml::Value = toml::from_str(&cargo_toml_content)?;
@@ -549,26 +1792,63 @@ impl CargoConfig {
     }
 
     fn is_workspace_table(value: &toml::Value) -> bool {
-        if let toml::Value::Table(table) = value {
-            if let Some(toml::Value::Boolean(true)) = table.get("workspace") {
-                return true;
-            }
+        if let toml::Value::Table(table) = value
+        && let Some(toml::Value::Boolean(true)) = table.get("workspace") {
+            return true;
         }
         false
     }
 
-    fn get_package_edition(table: &toml::Table) -> Option<&str> {
+    fn get_package_field<'t>(table: &'t toml::Table, field: &str) -> Option<&'t str> {
         table.get("package")
             .and_then(toml::Value::as_table)
-            .and_then(|pkg_table| pkg_table.get("edition"))
+            .and_then(|pkg_table| pkg_table.get(field))
             .and_then(toml::Value::as_str)
     }
 
-    fn get_package_version<'t>(table: &'t toml::Table, name: &str) -> Option<&'t toml::Value> {
-        table.get("dependencies")
-            .and_then(toml::Value::as_table)
-            .and_then(|pkg_table| pkg_table.get(name))
-            .filter(|v| v.is_str())
+    /// Reads a `[package]` field, which can be inherited from `[workspace.package]` with
+    /// `field.workspace = true`.
+    fn get_inheritable_package_field<'t>(
+        config: &'t toml::Value,
+        workspace_config: Option<&'t toml::Table>,
+        field: &str
+    ) -> Option<&'t str> {
+        config.get("package")
+            .and_then(|v| v.as_table())
+            .and_then(|table| table.get(field))
+            .and_then(|v| if !Self::is_workspace_table(v) { v.as_str() } else {
+                workspace_config.and_then(|t| Self::get_package_field(t, field))
+            })
+    }
+
+    /// Resolves a `workspace = true` dependency using the `[workspace.dependencies]` section. Keys
+    /// provided by the crate itself are merged in, with `features` being additive, like in Cargo.
+    fn get_workspace_dependency(
+        table: &toml::Table,
+        name: &str,
+        crate_spec: &toml::Value
+    ) -> Option<toml::Value> {
+        let inherited = table.get("dependencies")
+            .and_then(toml::Value::as_table)
+            .and_then(|deps_table| deps_table.get(name))?;
+        let mut spec = match inherited {
+            toml::Value::String(_) => {
+                toml::Table::from_iter([("version".into(), inherited.clone())])
+            }
+            toml::Value::Table(inherited_table) => inherited_table.clone(),
+            _ => return None,
+        };
+        for (key, value) in crate_spec.as_table().into_iter().flatten() {
+            if key == "workspace" { continue }
+            if key == "features"
+            && let Some(toml::Value::Array(features)) = spec.get_mut(key)
+            && let toml::Value::Array(extra_features) = value {
+                features.extend(extra_features.iter().cloned());
+            } else {
+                spec.insert(key.clone(), value.clone());
+            }
+        }
+        Some(toml::Value::Table(spec))
     }
 
     fn print_lints(lints: &toml::Value) -> String {
@@ -577,90 +1857,315 @@ impl CargoConfig {
         }).unwrap_or_default()
     }
 
+    fn resolve_dependency_paths(&mut self, base_dir: &Path) {
+        for dependency in &mut self.dependencies {
+            dependency.resolve_path(base_dir);
+        }
+    }
+
     fn fill_from_cargo_toml(&mut self, paths: &CargoConfigPaths) -> Result {
         use toml::Value;
+        let crate_dir = path::parent(&paths.crate_config)?;
         let config_str = fs::read_to_string(&paths.crate_config)?;
         let workspace_str = paths.workspace_config.as_ref().map(fs::read_to_string).transpose()?;
         let config = toml::from_str::<Value>(&config_str)?;
         let workspace_config_opt = workspace_str.map(|t| toml::from_str::<Value>(&t)).transpose()?;
         let workspace_config_table_opt = workspace_config_opt.as_ref()
             .and_then(|t| t.get("workspace")).and_then(|v| v.as_table());
+        let workspace_dir = paths.workspace_config.as_deref().map(path::parent).transpose()?;
         let dependencies = config
             .get("build-dependencies")
             .and_then(|v| v.as_table())
             .map_or(vec![], |t| t.iter().filter_map(|(k, v)|
                 if !Self::is_workspace_table(v) {
-                    Some(Dependency::new(k.clone(), v.to_string(), None))
+                    let mut dependency = Dependency::new(k.clone(), v.to_string(), None);
+                    dependency.resolve_path(crate_dir);
+                    Some(dependency)
                 } else {
-                    workspace_config_table_opt
-                        .and_then(|t| Self::get_package_version(t, k))
-                        .map(|t| Dependency::new(k.clone(), t.to_string(), None))
+                    let spec = workspace_config_table_opt
+                        .and_then(|t| Self::get_workspace_dependency(t, k, v))?;
+                    let mut dependency = Dependency::new(k.clone(), spec.to_string(), None);
+                    if let Some(workspace_dir) = workspace_dir {
+                        dependency.resolve_path(workspace_dir);
+                    }
+                    Some(dependency)
                 }
             ).collect());
-        let edition = config
-            .get("package")
-            .and_then(|v| v.as_table())
-            .and_then(|table| table.get("edition"))
-            .and_then(|v| if !Self::is_workspace_table(v) { v.as_str() } else {
-                workspace_config_table_opt.and_then(Self::get_package_edition)
-            })
-            .unwrap_or("2024");
+        let edition = Self::get_inheritable_package_field(
+            &config, workspace_config_table_opt, "edition"
+        );
+        let rust_version = Self::get_inheritable_package_field(
+            &config, workspace_config_table_opt, "rust-version"
+        );
         let lints = config.get("lints").map(|v| {
-            let table_opt = if Self::is_workspace_table(v) {
+            // With `lints.workspace = true`, lints are inherited from `[workspace.lints]`.
+            let lints_table_opt = if Self::is_workspace_table(v) {
                 workspace_config_table_opt
+                    .and_then(|t| t.get("lints"))
+                    .and_then(|t| t.as_table())
             } else {
                 v.as_table()
             };
-            let ws_lints = table_opt
-                .and_then(|t| t.get("lints"))
-                .and_then(|t| t.as_table());
-            let clippy = ws_lints.and_then(|t| t.get("clippy"))
+            let clippy = lints_table_opt.and_then(|t| t.get("clippy"))
                 .map(Self::print_lints).unwrap_or_default();
-            let rust = ws_lints.and_then(|t| t.get("rust"))
+            let rust = lints_table_opt.and_then(|t| t.get("rust"))
                 .map(Self::print_lints).unwrap_or_default();
             LintsConfig {clippy, rust}
         });
+        let default_dep_versions = config.get("package")
+            .and_then(|t| t.get("metadata"))
+            .and_then(|t| t.get("crabtime"))
+            .and_then(|t| t.get("default_dep_versions"))
+            .and_then(|v| v.as_table())
+            .map_or(vec![], |t| t.iter().map(|(k, v)| (k.clone(), v.to_string())).collect());
         self.dependencies.extend(dependencies);
-        self.edition = Some(edition.to_string());
+        self.default_dep_versions = default_dep_versions;
+        self.edition = Some(edition.unwrap_or(DEFAULT_EDITION).to_string());
+        // Cargo uses the 2015 edition for crates not specifying it.
+        self.caller_edition = Some(edition.unwrap_or(CARGO_DEFAULT_EDITION).to_string());
+        self.rust_version = rust_version.map(|t| t.to_string());
         self.lints = lints.unwrap_or_default();
         Ok(())
     }
 
+    /// Adds a dependency required by the macro, like `proc-macro2` for macros returning a
+    /// `TokenStream`. The spec from the `default_dep_versions` metadata of the calling crate takes
+    /// precedence over the `fallback` one.
+    fn add_default_dependency(&mut self, name: &str, fallback: &str) {
+        let spec = self.default_dep_versions.iter()
+            .find(|(other, _)| other == name)
+            .map_or_else(|| fallback.to_string(), |(_, spec)| spec.clone());
+        self.dependencies.push(Dependency::new(name.to_string(), spec, None));
+    }
+
+    /// Adds the dependency by [`Self::add_default_dependency`], unless the macro declares it.
+    fn ensure_dependency(&mut self, name: &str, fallback: &str) {
+        if !self.contains_dependency(name) {
+            self.add_default_dependency(name, fallback);
+        }
+    }
+
+    /// The evaluation timeout. The `#![timeout(...)]` attribute takes precedence over the
+    /// environment variable.
+    fn eval_timeout(&self) -> Result<Option<Duration>> {
+        if self.timeout.is_some() {
+            return Ok(self.timeout)
+        }
+        let Ok(timeout) = std::env::var(TIMEOUT_ENV) else { return Ok(None) };
+        let timeout = parse_duration(timeout.trim()).context(||
+            error!("Incorrect {TIMEOUT_ENV} value '{timeout}', use a duration like '30s'.")
+        )?;
+        Ok(Some(timeout))
+    }
+
+    /// Fails if the emitted code requires a newer edition than the one of the calling crate.
+    fn check_min_edition(&self) -> Result {
+        let Some((min_edition, span)) = self.min_edition else { return Ok(()) };
+        let Some(caller_edition) = &self.caller_edition else { return Ok(()) };
+        if caller_edition.parse::<u32>().is_ok_and(|t| t < min_edition) {
+            return err!(span,
+                "The macro emits code requiring Rust edition {min_edition}, but the crate invoking \
+                it uses edition {caller_edition}. Update the edition of the crate in its \
+                Cargo.toml."
+            )
+        }
+        Ok(())
+    }
+
     fn extract_inline_attributes(&mut self, attributes: Vec<syn::Attribute>) -> Result<String> {
         let mut other_attributes = Vec::with_capacity(attributes.len());
         let mut new_dependencies = vec![];
+        let mut singletons: Vec<(String, Span)> = vec![];
+        let mut allow_inline_dependencies = false;
         for attr in attributes {
+            let span = syn::spanned::Spanned::span(&attr);
+            if let Some(name) = attr.path().get_ident().map(|t| t.to_string())
+            && SINGLETON_ATTRIBUTES.contains(&name.as_str()) {
+                if let Some((_, first_span)) = singletons.iter().find(|(other, _)| *other == name) {
+                    return Err(error!(span, "The '{name}' attribute is specified more than once.")
+                        .with_related(vec![error!(*first_span, "First '{name}' attribute.")]))
+                }
+                singletons.push((name, span));
+            }
+            // The body is evaluated with `std`, as the output is printed to stdout, so only the
+            // output is checked in a `no_std` crate.
+            if attr.path().is_ident("no_std") && matches!(attr.meta, syn::Meta::Path(_)) {
+                self.no_std = true;
+                continue;
+            }
+            if attr.path().is_ident("allow_inline_dependencies")
+            && matches!(attr.meta, syn::Meta::Path(_)) {
+                allow_inline_dependencies = true;
+                continue;
+            }
             let tokens = attr.parse_args::<TokenStream>().context("Failed to parse attributes")?;
             let tokens_str = tokens.to_string().replace(" ", "");
             let token_range = tokens.clone().into_iter().next()
                 .zip(tokens.clone().into_iter().last())
                 .map(|(first, last)| TokenRange::new(first, last));
             if attr.path().is_ident("dependency") {
-                let (key, value) = tokens_str.split_once('=').context(||
+                let (key, value) = parse_dependency(&tokens).context(||
                     error!("Incorrect dependency '{tokens_str}'")
                 )?;
-                let key = key.to_string();
-                let value = value.to_string();
                 new_dependencies.push(Dependency::new(key, value, token_range));
             } else if attr.path().is_ident("edition") {
                 self.edition = Some(tokens_str);
+            } else if attr.path().is_ident("resolver") {
+                self.resolver = Some(tokens_str.trim_matches('"').to_string());
+            } else if attr.path().is_ident("min_edition") {
+                let edition = tokens_str.parse::<u32>().ok()
+                    .filter(|t| EDITIONS.contains(t))
+                    .context(|| error!(span, "Unsupported edition '{tokens_str}'."))?;
+                self.min_edition = Some((edition, span));
+            } else if attr.path().is_ident("timeout") {
+                self.timeout = Some(parse_duration(&tokens_str).context(||
+                    error!("Incorrect timeout '{tokens_str}', use a duration like '30s'.")
+                )?);
+            } else if attr.path().is_ident("profile") {
+                self.release = match tokens_str.as_str() {
+                    "dev" | "debug" => false,
+                    "release" => true,
+                    _ => return err!("Unsupported profile '{tokens_str}', use 'dev' or 'release'."),
+                };
+            } else if attr.path().is_ident("registry_index") {
+                let index = syn::parse2::<syn::LitStr>(tokens).context(||
+                    error!(span, "Incorrect registry index '{tokens_str}', use a string literal.")
+                )?;
+                self.registry_index = Some(index.value());
+            } else if attr.path().is_ident("opt_level") {
+                self.set_profile_setting("opt-level".to_string(), tokens_str);
+            } else if attr.path().is_ident("eval_profile") {
+                let settings = attr.parse_args_with(
+                    syn::punctuated::Punctuated::<syn::MetaNameValue, syn::Token![,]>
+                        ::parse_terminated
+                ).context("Failed to parse the 'eval_profile' attribute.")?;
+                for setting in settings {
+                    let key = setting.path.to_token_stream().to_string().replace('_', "-");
+                    let value = setting.value.to_token_stream().to_string();
+                    self.set_profile_setting(key, value);
+                }
             } else {
+                if attr.path().is_ident("feature") && self.unstable_feature.is_none() {
+                    self.unstable_feature = Some(span);
+                }
                 other_attributes.push(attr.to_token_stream().to_string());
             }
         }
         #[cfg(nightly)]
-        for dependency in &new_dependencies {
+        for dependency in new_dependencies.iter().filter(|_| !allow_inline_dependencies) {
             warning!(dependency.span(),
                 "When using the nightly Rust channel, dependencies should be specified in the \
                 [build-dependencies] section of your Cargo.toml file."
             ).emit();
         }
+        // Without spans, the warning is printed once per crate in the compilation.
+        #[cfg(not(nightly))]
+        for dependency in new_dependencies.iter().filter(|_| !allow_inline_dependencies) {
+            if first_inline_dependency(&dependency.label) {
+                print_warning!(
+                    "The inline dependency '{}' should be specified in the [build-dependencies] \
+                    section of your Cargo.toml file. Use '#![allow_inline_dependencies]' to \
+                    silence this warning.",
+                    dependency.to_config_string()
+                );
+            }
+        }
         self.dependencies.extend(new_dependencies);
         Ok(other_attributes.join("\n"))
     }
 }
 
-fn create_project_skeleton(project_dir: &Path, cfg: CargoConfig, main: &str) -> Result<bool> {
+/// Checks whether the inline dependency is used for the first time in the compilation, which runs
+/// in a single process.
+#[cfg(not(nightly))]
+fn first_inline_dependency(label: &str) -> bool {
+    static WARNED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(vec![]);
+    let mut warned = WARNED.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    if warned.iter().any(|t| t == label) {
+        return false
+    }
+    warned.push(label.to_string());
+    true
+}
+
+/// Parses a duration, like `30s`, `500ms`, or `2m`. A number without a unit is in seconds.
+fn parse_duration(input: &str) -> Option<Duration> {
+    let split = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (value, unit) = input.split_at(split);
+    let value = value.parse::<u64>().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(value)),
+        "" | "s" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_secs(value.checked_mul(60)?)),
+        _ => None,
+    }
+}
+
+/// Splits the `#![dependency(...)]` arguments into the crate name and its specification. The name
+/// can be a hyphenated identifier, like `proc-macro2`, or a string literal, like `"my-crate"`. The
+/// specification can be a version string or an inline table, like
+/// `{ version = "1", features = ["derive"] }`.
+fn parse_dependency(tokens: &TokenStream) -> Option<(String, String)> {
+    let mut tokens = tokens.clone().into_iter();
+    let mut name = String::new();
+    for token in tokens.by_ref() {
+        match &token {
+            TokenTree::Punct(punct) if punct.as_char() == '=' => break,
+            TokenTree::Punct(punct) if punct.as_char() == '-' => name.push('-'),
+            TokenTree::Ident(ident) => name.push_str(&ident.to_string()),
+            TokenTree::Literal(_) => {
+                let lit = syn::parse2::<syn::LitStr>(token.into()).ok()?;
+                name.push_str(&lit.value());
+            }
+            _ => return None,
+        }
+    }
+    let spec = print_toml_tokens(tokens.collect());
+    (!name.is_empty() && !spec.is_empty()).then_some((name, spec))
+}
+
+/// Prints tokens as a TOML value. Keys, like `default-features`, are printed without spaces around
+/// dashes, and trailing commas are removed from inline tables, as TOML does not allow them.
+fn print_toml_tokens(tokens: TokenStream) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) => {
+                let mut inner: Vec<TokenTree> = group.stream().into_iter().collect();
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Brace => {
+                        if matches!(inner.last(), Some(TokenTree::Punct(p)) if p.as_char() == ',') {
+                            inner.pop();
+                        }
+                        ("{ ", " }")
+                    }
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::None => ("", ""),
+                };
+                let inner = print_toml_tokens(inner.into_iter().collect());
+                out.push_str(&format!("{open}{inner}{close}"));
+            }
+            TokenTree::Punct(punct) if punct.as_char() == '=' => out.push_str(" = "),
+            TokenTree::Punct(punct) if punct.as_char() == ',' => out.push_str(", "),
+            token => out.push_str(&token.to_string()),
+        }
+    }
+    out.trim_end().to_string()
+}
+
+/// Entry point used instead of the default `main` function when building from `src/lib.rs`.
+const LIB_TARGET_HARNESS: &str = "
+    #[unsafe(export_name = \"main\")]
+    extern \"C\" fn __crabtime_main__(_argc: i32, _argv: *const *const u8) -> i32 {
+        let result = std::panic::catch_unwind(main);
+        std::io::Write::flush(&mut std::io::stdout()).ok();
+        if result.is_ok() { 0 } else { 101 }
+    }
+";
+const LIB_TARGET_FILE: &str = "lib.rs";
+
+fn create_project_skeleton(project_dir: &Path, cfg: &CargoConfig, main: &str) -> Result<bool> {
     let src_dir = project_dir.join("src");
     let existed = src_dir.exists();
     if !existed {
@@ -671,50 +2176,643 @@ fn create_project_skeleton(project_dir: &Path, cfg: CargoConfig, main: &str) ->
     let cargo_toml_content = cfg.print();
     fs::write(&cargo_toml, cargo_toml_content).context("Failed to write Cargo.toml.")?;
 
-    let main_rs = src_dir.join("main.rs");
-    let mut file = File::create(&main_rs).context("Failed to create main.rs")?;
-    file.write_all(main.as_bytes()).context("Failed to write main.rs")?;
+    // Project directories are reused, so the config is removed if it is not needed anymore.
+    let cargo_config_dir = project_dir.join(".cargo");
+    match cfg.print_cargo_config() {
+        Some(config) => {
+            fs::create_dir_all(&cargo_config_dir).context("Failed to create .cargo directory.")?;
+            fs::write(cargo_config_dir.join("config.toml"), config)
+                .context("Failed to write .cargo/config.toml.")?;
+        }
+        None => if cargo_config_dir.exists() {
+            fs::remove_dir_all(&cargo_config_dir).context("Failed to remove .cargo directory.")?;
+        }
+    }
+
+    if cfg.lock_file && let Ok(lock) = fs::read_to_string(cfg.lock_file_path()?) {
+        let lock = rename_lock_root(&lock, EVAL_PROJECT_NAME, cfg.package_name());
+        fs::write(project_dir.join("Cargo.lock"), lock).context("Failed to write Cargo.lock.")?;
+    }
+
+    let (file_name, other_file_name, code) = if cfg.lib_target {
+        // Kept on the first line, so errors map to the same lines as in the `main.rs` layout.
+        (LIB_TARGET_FILE, "main.rs", format!("#![no_main] {main}\n{LIB_TARGET_HARNESS}"))
+    } else {
+        ("main.rs", LIB_TARGET_FILE, main.to_string())
+    };
+    // Project directories are reused, so the file of the other layout is removed. Otherwise, Cargo
+    // would build it as another target.
+    let other_file = src_dir.join(other_file_name);
+    if other_file.exists() {
+        fs::remove_file(&other_file).context(|| error!("Failed to remove {other_file_name}"))?;
+    }
+    let mut file = File::create(src_dir.join(file_name))
+        .context(|| error!("Failed to create {file_name}"))?;
+    file.write_all(code.as_bytes()).context(|| error!("Failed to write {file_name}"))?;
     Ok(existed)
 }
 
-fn get_host_target() -> Result<String> {
-    let output = Command::new("rustc")
-        .arg("-vV")
-        .stdout(std::process::Stdio::piped())
-        .output()
-        .context("Failed to run rustc")?;
+/// Stores the `Cargo.lock` of the built project for the projects with the same dependencies, if
+/// it changed. Cargo updates the reused lock file only if it doesn't match the dependencies. The
+/// root package is stored under a fixed name, as the names of the projects differ.
+fn store_lock_file(project_dir: &Path, cfg: &CargoConfig) -> Result {
+    let stored = cfg.lock_file_path()?;
+    let lock = fs::read_to_string(project_dir.join("Cargo.lock"))
+        .context("Failed to read Cargo.lock.")?;
+    let lock = rename_lock_root(&lock, cfg.package_name(), EVAL_PROJECT_NAME);
+    if fs::read_to_string(&stored).ok().as_deref() != Some(lock.as_str()) {
+        fs::create_dir_all(path::parent(&stored)?).context("Failed to create lock directory.")?;
+        fs::write(&stored, lock).context("Failed to store Cargo.lock.")?;
+    }
+    Ok(())
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    for line in stdout.lines() {
-        if let Some(stripped) = line.strip_prefix("host:") {
-            return Ok(stripped.trim().to_string())
+/// Renames the root package in the `Cargo.lock`. Dependencies don't refer to it, so its entry is
+/// the only place to change.
+fn rename_lock_root(lock: &str, from: &str, to: &str) -> String {
+    let entry = |name: &str| format!("[[package]]\nname = \"{name}\"\n");
+    lock.replacen(&entry(from), &entry(to), 1)
+}
+
+/// The 64-bit FNV-1a hash, also used by `memoize` in the prelude. Unlike `DefaultHasher`, it is
+/// the same for all toolchains, so it can be used in names of files kept across builds.
+fn fnv_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// ====================
+// === Binary Cache ===
+// ====================
+
+const CACHE_METADATA_FILE: &str = "crabtime-cache.toml";
+const EVAL_PROJECT_NAME: &str = "eval_project";
+
+/// Cache entry of a compiled project, stored in the project directory. It maps the hash of the
+/// project inputs to the executable. The inputs are stored as well to simplify debugging.
+#[derive(Debug)]
+struct CacheEntry {
+    hash: String,
+    executable: PathBuf,
+}
+
+impl CacheEntry {
+    fn hash(cfg: &CargoConfig, main: &str) -> String {
+        let cargo_config = cfg.print_cargo_config().unwrap_or_default();
+        // The profile is printed only if it has settings.
+        let inputs = format!("{}\n{cargo_config}\n{}\n{main}", cfg.print(), cfg.release);
+        format!("{:016x}", fnv_hash(&inputs))
+    }
+
+    /// Reads the entry if it matches the hash and its executable still exists.
+    fn read(project_dir: &Path, hash: &str) -> Option<Self> {
+        let metadata = fs::read_to_string(project_dir.join(CACHE_METADATA_FILE)).ok()?;
+        let table = toml::from_str::<toml::Table>(&metadata).ok()?;
+        let entry = Self {
+            hash: table.get("hash")?.as_str()?.to_string(),
+            executable: PathBuf::from(table.get("executable")?.as_str()?),
+        };
+        (entry.hash == hash && entry.executable.is_file()).then_some(entry)
+    }
+
+    fn write(&self, project_dir: &Path, cfg: &CargoConfig) -> Result {
+        let dependencies = cfg.dependencies.iter().map(|t| t.to_config_string().into()).collect();
+        let edition = cfg.edition.as_deref().unwrap_or(DEFAULT_EDITION);
+        let table = toml::Table::from_iter([
+            ("hash".to_string(), toml::Value::String(self.hash.clone())),
+            ("executable".to_string(), self.executable.display().to_string().into()),
+            ("edition".to_string(), edition.into()),
+            ("profile".to_string(), cfg.profile_name().into()),
+            ("dependencies".to_string(), toml::Value::Array(dependencies)),
+        ]);
+        fs::write(project_dir.join(CACHE_METADATA_FILE), table.to_string())
+            .context("Failed to write the cache metadata.")
+    }
+}
+
+/// The `rustc` used to build the generated projects, as reported by `rustc -vV`.
+#[derive(Debug, PartialEq)]
+struct HostRustc {
+    target: String,
+    /// Whether the release channel is nightly, or a local dev build, which allows unstable
+    /// features as well.
+    is_nightly: bool,
+}
+
+impl HostRustc {
+    /// Runs `rustc -vV` once per compilation.
+    fn get() -> Result<&'static Self> {
+        static HOST_RUSTC: std::sync::OnceLock<HostRustc> = std::sync::OnceLock::new();
+        if let Some(host) = HOST_RUSTC.get() {
+            return Ok(host)
         }
+        let rustc = tool_path(RUSTC_ENV, "rustc");
+        let output = Command::new(&rustc)
+            .arg("-vV")
+            .stdout(std::process::Stdio::piped())
+            .output()
+            .map_err(|err| spawn_error(&rustc, err))
+            .context("Failed to run rustc")?;
+        let host = Self::parse(&String::from_utf8_lossy(&output.stdout))
+            .context("Could not determine host target from rustc")?;
+        Ok(HOST_RUSTC.get_or_init(|| host))
+    }
+
+    fn parse(version: &str) -> Option<Self> {
+        let field = |name: &str| version.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .map(str::trim);
+        let target = field("host")?.to_string();
+        let release = field("release").unwrap_or_default();
+        let is_nightly = release.contains("-nightly") || release.contains("-dev");
+        Some(Self { target, is_nightly })
+    }
+}
+
+/// The binary of a build tool, overridden by a non-empty value of the environment variable.
+fn tool_path(env: &str, default: &str) -> String {
+    std::env::var(env).ok()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// The network flag of Cargo set by [`OFFLINE_ENV`]. Any non-empty value other than `0` enables
+/// the offline mode, and `frozen` additionally requires the lock file to be up to date.
+fn offline_flag() -> Option<&'static str> {
+    let value = std::env::var(OFFLINE_ENV).ok()?;
+    match value.trim() {
+        "" | "0" => None,
+        "frozen" => Some("--frozen"),
+        _ => Some("--offline"),
+    }
+}
+
+/// Creates a `cargo` command, returned with the binary name for error messages. If `rustc` is
+/// overridden, Cargo is pointed at it as well, so the generated project is built by the same
+/// compiler whose host target was detected. The network flag is global, so it is passed before
+/// the subcommand.
+fn cargo_command() -> (Command, String) {
+    let cargo = tool_path(CARGO_ENV, "cargo");
+    let mut command = Command::new(&cargo);
+    let rustc = tool_path(RUSTC_ENV, "");
+    if !rustc.is_empty() {
+        command.env("RUSTC", rustc);
+    }
+    command.args(offline_flag());
+    (command, cargo)
+}
+
+/// Converts the error of running a build tool. If the tool is missing, the issue explains that it
+/// is required at build time, as fixing the environment differs from fixing a failed compilation.
+fn spawn_error(tool: &str, err: std::io::Error) -> Issue {
+    if err.kind() != std::io::ErrorKind::NotFound {
+        return err.into()
+    }
+    error!("Crabtime requires a working `{tool}` at build time, but it was not found. Make sure \
+        it is on PATH, or set its path with `{CARGO_ENV}` or `{RUSTC_ENV}`. Macros are evaluated \
+        by building and running a separate Cargo project, so the build environment has to \
+        provide the Rust toolchain.")
+}
+
+/// The rustup toolchain used to build the generated project instead of the one compiling the
+/// crate. If the body enables unstable features with `#![feature(...)]` and the crate is not
+/// compiled with nightly, the nightly toolchain is used, or an error is reported if it is not
+/// installed, or if the compiler is overridden with [`RUSTC_ENV`].
+fn eval_toolchain(cfg: &CargoConfig) -> Result<Option<&'static str>> {
+    let Some(span) = cfg.unstable_feature else { return Ok(None) };
+    if HostRustc::get()?.is_nightly {
+        return Ok(None)
     }
-    err!("Could not determine host target from rustc")
+    // Cargo would build the project with the overridden compiler, ignoring the toolchain.
+    if !tool_path(RUSTC_ENV, "").is_empty() {
+        return err!(span, "The '#![feature(...)]' attribute requires a nightly toolchain, but \
+            `{RUSTC_ENV}` overrides the compiler with a stable one. Point it to a nightly compiler \
+            or unset it.")
+    }
+    let output = Command::new(tool_path(RUSTC_ENV, "rustc"))
+        .arg("-vV")
+        .env(RUSTUP_TOOLCHAIN_ENV, NIGHTLY_TOOLCHAIN)
+        .output();
+    let is_nightly = output.ok()
+        .and_then(|output| HostRustc::parse(&String::from_utf8_lossy(&output.stdout)))
+        .is_some_and(|host| host.is_nightly);
+    if !is_nightly {
+        return err!(span, "The '#![feature(...)]' attribute requires a nightly toolchain, but the \
+            crate is compiled with a stable one, and the nightly toolchain is not available. \
+            Install it with `rustup toolchain install nightly`.")
+    }
+    Ok(Some(NIGHTLY_TOOLCHAIN))
 }
 
-fn run_cargo_project(project_dir: &PathBuf) -> Result<String> {
+/// Builds the project and returns the path of its executable. Building and running are separate
+/// steps, so cached binaries can be run without invoking Cargo at all, and dependencies built once
+/// in the shared target directory are reused by all projects.
+///
+/// The executable path is read from the `compiler-artifact` message Cargo prints for the binary
+/// target when `--message-format=json` is used. Diagnostics are still rendered to stderr.
+fn build_cargo_project(
+    project_dir: &Path,
+    target_dir: &Path,
+    host_target: &str,
+    release: bool,
+    toolchain: Option<&str>,
+    main: &str,
+    body: &SpanMap,
+) -> Result<PathBuf> {
     // In case the project uses .cargo/config.toml, we need to explicitly revert target to native.
-    let host_target = get_host_target()?;
-    let output = Command::new("cargo")
-        .arg("run")
+    let (mut command, cargo) = cargo_command();
+    let output = command
+        .arg("build")
+        .arg("--message-format=json")
+        .args(release.then_some("--release"))
         .arg("--target")
-        .arg(&host_target)
+        .arg(host_target)
         .current_dir(project_dir)
+        .env("CARGO_TARGET_DIR", target_dir)
+        .envs(toolchain.map(|toolchain| (RUSTUP_TOOLCHAIN_ENV, toolchain)))
+        // When expanded under `cargo clippy`, the wrapper would lint the generated project with
+        // the caller's flags. Its lints are configured in its own `Cargo.toml` instead.
+        .env_remove("RUSTC_WORKSPACE_WRAPPER")
+        .env_remove("CLIPPY_ARGS")
         .output()
-        .context("Failed to execute cargo run")?;
-
+        .map_err(|err| spawn_error(&cargo, err))
+        .context("Failed to execute cargo build")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let messages = stdout.lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .collect::<Vec<_>>();
     if !output.status.success() {
+        for message in &messages {
+            if let Some(rendered) = message["message"]["rendered"].as_str() {
+                eprintln!("{rendered}");
+            }
+        }
         let stderr = String::from_utf8_lossy(&output.stderr);
-        // TODO: Parse it and map gen code spans to call site spans.
         eprintln!("{stderr}");
-        #[allow(clippy::panic)]
-        if let Some(index) = stderr.find("thread 'main' panicked") {
-            panic!("{}", &stderr[index..]);
+        let issues = messages.iter()
+            .filter_map(|message| body.map_compiler_error(message, main))
+            .collect();
+        let details = build_failure_details(&messages, &stderr);
+        return Err(compilation_error(issues, &details))
+    }
+    messages.iter()
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .find_map(|message| message["executable"].as_str())
+        .map(PathBuf::from)
+        .context("Cargo did not report the executable of the generated project.")
+}
+
+/// Describes why the build failed. The errors are taken from the structured compiler messages.
+/// If there are none, for example, if Cargo failed before invoking the compiler, the errors are
+/// found in the text printed to stderr.
+fn build_failure_details(messages: &[serde_json::Value], stderr: &str) -> String {
+    let errors = messages.iter()
+        .filter(|message| message["reason"] == "compiler-message")
+        .filter(|message| {
+            message["message"]["level"].as_str().is_some_and(|level| level.starts_with("error"))
+        })
+        .filter_map(|message| message["message"]["rendered"].as_str())
+        .map(|rendered| rendered.trim_end())
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return errors.join("\n")
+    }
+    let lines = stderr.lines().collect::<Vec<_>>();
+    let first_error = lines.iter().position(|line| line.starts_with("error")).unwrap_or(0);
+    lines[first_error..].join("\n").trim().to_string()
+}
+
+/// Reports errors mapped to the macro body. On nightly, they are emitted as diagnostics. On
+/// stable, they are returned as `compile_error!` invocations spanning the failing tokens. If no
+/// error maps to the body, the error contains the details of the failure.
+fn compilation_error(issues: Vec<Issue>, details: &str) -> Issue {
+    let fallback = if issues.is_empty() && !details.is_empty() {
+        error!("Compilation of the generated code failed.\n{details}")
+    } else {
+        error!("Compilation of the generated code failed.")
+    };
+    #[cfg(nightly)] {
+        for issue in &issues {
+            issue.emit();
+        }
+        fallback
+    }
+    #[cfg(not(nightly))] {
+        let mut issues = issues.into_iter();
+        match issues.next() {
+            Some(issue) => issue.with_related(issues.collect()),
+            None => fallback,
+        }
+    }
+}
+
+/// Runs the executable of a built project and returns the generated code. The output is parsed
+/// while the executable runs, so warnings are reported as soon as they are printed. If it does not
+/// finish within the timeout, it is terminated together with the processes it spawned.
+fn run_executable(
+    project_dir: &Path,
+    executable: &Path,
+    timeout: Option<Duration>,
+    name: &str,
+    sentinel_output: bool,
+    once_owner: String
+) -> Result<EvalOutput> {
+    let backtrace = backtrace_mode();
+    let mut command = Command::new(executable);
+    command.current_dir(project_dir).env("RUST_BACKTRACE", backtrace.as_deref().unwrap_or("0"));
+    let sentinel = sentinel_output.then(output_sentinel);
+    match &sentinel {
+        Some(sentinel) => command.env(OUTPUT_SENTINEL_ENV, sentinel),
+        None => command.env_remove(OUTPUT_SENTINEL_ENV),
+    };
+    let verbose = verbose_mode().then(|| name.to_string());
+    let once_owner = Some(once_owner);
+    let mut parser = OutputParser { verbose, sentinel, once_owner, ..Default::default() };
+    let output = output_with_timeout(command, timeout, |line| parser.push_line(line))
+        .context("Failed to execute the generated project.")?;
+    let Some((status, stderr)) = output else {
+        let timeout = format_duration(timeout.unwrap_or_default());
+        return err!(
+            "Evaluation of the generated code did not finish within {timeout} and was terminated. \
+            The timeout can be changed with the `#![timeout(...)]` attribute or the \
+            `{TIMEOUT_ENV}` environment variable."
+        )
+    };
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&stderr);
+        debug!("{stderr}");
+        match panic_message(&stderr) {
+            Some(message) => match panic_backtrace(&stderr).filter(|_| backtrace.is_some()) {
+                Some(trace) => err!("The generated code panicked.\n{message}\n{trace}"),
+                None => err!("The generated code panicked.\n{message}"),
+            },
+            None => {
+                // Errors reported before exiting, like by `include_json`, describe the failure.
+                if parser.errors.is_empty() {
+                    eprintln!("{stderr}");
+                    err!("Evaluation of the generated code failed.")
+                } else {
+                    err!("{}", parser.errors.join("\n"))
+                }
+            }
         }
-        err!("Compilation of the generated code failed.")
     } else {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        let tracked_files = std::mem::take(&mut parser.tracked_files);
+        parser.finish().map(|code| EvalOutput { code, tracked_files })
+    }
+}
+
+/// The code generated by the evaluated project and the files it read.
+struct EvalOutput {
+    code: String,
+    tracked_files: Vec<String>,
+}
+
+/// Makes the compiler expand the macro again when the tracked files change. On stable, the files
+/// are included in `const _` items, which are valid only if the output is in an item position, so
+/// the files of other macros are not tracked.
+fn track_files(code: String, files: &[String], options: &MacroOptions) -> String {
+    if files.is_empty() {
+        return code
+    }
+    #[cfg(nightly)] {
+        let _ = options;
+        for file in files {
+            proc_macro::tracked::path(file);
+        }
+        code
+    }
+    #[cfg(not(nightly))] {
+        if options.expression || syn::parse_str::<syn::File>(&code).is_err() {
+            print_warning!(
+                "The files read by the macro are not tracked, as its output is not a list of \
+                items. Rebuild your crate after changing them."
+            );
+            return code
+        }
+        let includes = files.iter()
+            .map(|file| format!("const _: &[u8] = include_bytes!({file:?});\n"))
+            .collect::<String>();
+        code + "\n" + &includes
+    }
+}
+
+/// Like [`Command::output`], but the lines of the stdout are passed to `on_line` as soon as they
+/// are printed, and [`None`] is returned if the process does not finish within the timeout. The
+/// process runs in its own process group, so its subprocesses are terminated as well. Lines are
+/// handled on the calling thread, as the proc macro API can't be used from other threads.
+fn output_with_timeout(
+    mut command: Command,
+    timeout: Option<Duration>,
+    mut on_line: impl FnMut(&[u8]),
+) -> Result<Option<(std::process::ExitStatus, Vec<u8>)>> {
+    use std::io::BufRead;
+    use std::io::Read;
+    use std::process::Stdio;
+    use std::sync::mpsc;
+    use std::time::Instant;
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // Pipes are read in the background, so the process does not block on a full pipe buffer.
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+    let stdout = child.stdout.take().map(std::io::BufReader::new);
+    std::thread::spawn(move || {
+        let Some(mut stdout) = stdout else { return };
+        loop {
+            let mut line = vec![];
+            match stdout.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => if sender.send(line).is_err() { break },
+            }
+        }
+    });
+    let mut stderr_pipe = child.stderr.take();
+    let stderr = std::thread::spawn(move || {
+        let mut buffer = vec![];
+        stderr_pipe.as_mut().map(|pipe| pipe.read_to_end(&mut buffer));
+        buffer
+    });
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let remaining = || deadline.map(|t| t.saturating_duration_since(Instant::now()));
+    // Stdout is closed when the process exits, unless its subprocesses keep it open.
+    loop {
+        let line = match remaining() {
+            None => receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            Some(Duration::ZERO) => Err(mpsc::RecvTimeoutError::Timeout),
+            Some(remaining) => receiver.recv_timeout(remaining),
+        };
+        match line {
+            Ok(line) => on_line(&line),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                kill_process_tree(&mut child);
+                return Ok(None)
+            }
+        }
+    }
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status
+        }
+        if remaining() == Some(Duration::ZERO) {
+            kill_process_tree(&mut child);
+            return Ok(None)
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    Ok(Some((status, stderr.join().unwrap_or_default())))
+}
+
+fn kill_process_tree(child: &mut std::process::Child) {
+    let pid = child.id().to_string();
+    #[cfg(unix)]
+    Command::new("kill").args(["-KILL", "--", &format!("-{pid}")]).status().ok();
+    #[cfg(windows)]
+    Command::new("taskkill").args(["/T", "/F", "/PID", &pid]).status().ok();
+    child.kill().ok();
+    child.wait().ok();
+}
+
+/// A sentinel randomized for every evaluation, so it can't be printed by the evaluated code by
+/// accident. The generated code is printed between the `<sentinel> BEGIN` and `<sentinel> END`
+/// lines.
+fn output_sentinel() -> String {
+    use std::hash::BuildHasher;
+    let seed = std::collections::hash_map::RandomState::new().hash_one(get_current_time());
+    format!("[CRABTIME OUTPUT {seed:016x}]")
+}
+
+/// Checks whether the flag set by the environment variable is enabled, which is the case for any
+/// non-empty value other than `0`.
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|t| !t.trim().is_empty() && t.trim() != "0")
+}
+
+/// Checks whether the verbose mode is enabled by [`VERBOSE_ENV`].
+fn verbose_mode() -> bool {
+    env_flag(VERBOSE_ENV)
+}
+
+/// Checks whether the one-shot projects of failed evaluations should be kept, enabled by
+/// [`KEEP_ON_ERROR_ENV`].
+fn keep_on_error() -> bool {
+    env_flag(KEEP_ON_ERROR_ENV)
+}
+
+/// The `RUST_BACKTRACE` value for the evaluated code, or [`None`] if backtraces are disabled.
+fn backtrace_mode() -> Option<String> {
+    std::env::var(BACKTRACE_ENV).or_else(|_| std::env::var("RUST_BACKTRACE")).ok()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty() && t != "0")
+}
+
+/// Extracts the backtrace printed after the panic message from the stderr.
+fn panic_backtrace(stderr: &str) -> Option<&str> {
+    let start = stderr.find("stack backtrace:")?;
+    Some(stderr[start..].trim_end())
+}
+
+/// Extracts the panic message from the stderr. The main thread is unnamed when the binary is built
+/// from `src/lib.rs`, and newer toolchains print the thread id, like `thread 'main' (42) panicked`.
+/// The message can span multiple lines and ends before the backtrace or the note about it.
+fn panic_message(stderr: &str) -> Option<&str> {
+    let message = stderr.match_indices("thread '")
+        .map(|(index, _)| &stderr[index..])
+        .find(|rest| rest.lines().next().is_some_and(|line| line.contains(" panicked at ")))?;
+    let end = ["\nnote: run with `RUST_BACKTRACE", "\nstack backtrace:"].iter()
+        .filter_map(|marker| message.find(marker))
+        .min()
+        .unwrap_or(message.len());
+    Some(message[..end].trim_end())
+}
+
+// ================
+// === Span Map ===
+// ================
+
+/// Code printed from tokens, with the byte range of every token in it. It is used to map errors
+/// reported by the compiler in the generated project back to the macro body.
+#[derive(Debug, Default)]
+struct SpanMap {
+    code: String,
+    spans: Vec<(std::ops::Range<usize>, Span)>,
+}
+
+impl SpanMap {
+    fn new(tokens: &TokenStream) -> Self {
+        let mut span_map = Self::default();
+        span_map.print(tokens);
+        span_map
+    }
+
+    fn push(&mut self, code: &str, span: Span) {
+        let start = self.code.len();
+        self.code.push_str(code);
+        self.spans.push((start..self.code.len(), span));
+    }
+
+    fn print(&mut self, tokens: &TokenStream) {
+        let mut glue = true;
+        for token in tokens.clone() {
+            if !glue {
+                self.code.push(' ');
+            }
+            glue = false;
+            match &token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, group.span_open());
+                    self.print(&group.stream());
+                    self.push(close, group.span_close());
+                }
+                // Joint punctuation, like `::` or the `'` of a lifetime, must stay glued.
+                TokenTree::Punct(punct) => {
+                    self.push(&punct.as_char().to_string(), punct.span());
+                    glue = punct.spacing() == proc_macro2::Spacing::Joint;
+                }
+                _ => self.push(&token.to_string(), token.span()),
+            }
+        }
+    }
+
+    /// Span of the token at the byte offset, or of the closest token before it.
+    fn span_at(&self, offset: usize) -> Option<Span> {
+        self.spans.iter().rev().find(|(range, _)| range.start <= offset).map(|(_, span)| *span)
+    }
+
+    /// Maps an error reported by Cargo in the JSON format to an issue spanning the body tokens.
+    /// The `main` is the code of the generated project the body was pasted into.
+    fn map_compiler_error(&self, message: &serde_json::Value, main: &str) -> Option<Issue> {
+        let diagnostic = &message["message"];
+        if message["reason"] != "compiler-message" || diagnostic["level"] != "error" {
+            return None;
+        }
+        let spans = diagnostic["spans"].as_array()?;
+        let primary = spans.iter().find(|span| span["is_primary"] == true)?;
+        let line = usize::try_from(primary["line_start"].as_u64()?).ok()?;
+        let column = usize::try_from(primary["column_start"].as_u64()?).ok()?;
+        // Lines and columns are 1-based, and columns are counted in characters.
+        let line_start = main.split_inclusive('\n').take(line - 1).map(str::len).sum::<usize>();
+        let line_str = main[line_start..].lines().next()?;
+        let column_offset = line_str.char_indices().nth(column - 1).map_or(line_str.len(), |t| t.0);
+        let body_start = main.rfind(&self.code).filter(|_| !self.code.is_empty())?;
+        let offset = (line_start + column_offset).checked_sub(body_start)?;
+        if offset >= self.code.len() {
+            return None;
+        }
+        let mut text = diagnostic["message"].as_str()?.to_string();
+        if let Some(label) = primary["label"].as_str() {
+            text = format!("{text}\n{label}");
+        }
+        Some(error!(self.span_at(offset)?, text))
     }
 }
 
@@ -729,18 +2827,14 @@ fn expand_expand_macro(input: TokenStream) -> TokenStream {
     let mut output = TokenStream::new();
     let mut i = 0;
     while i < tokens.len() {
-        if let TokenTree::Ident(ref ident) = tokens[i] {
-            if *ident == "expand" && i + 1 < tokens.len() {
-                if let TokenTree::Punct(ref excl) = tokens[i + 1] {
-                    if excl.as_char() == '!' && i + 2 < tokens.len() {
-                        if let TokenTree::Group(ref group) = tokens[i + 2] {
-                            output.extend(group.stream());
-                            i += 3;
-                            continue;
-                        }
-                    }
-                }
-            }
+        if let TokenTree::Ident(ref ident) = tokens[i]
+        && *ident == "expand" && i + 1 < tokens.len()
+        && let TokenTree::Punct(ref excl) = tokens[i + 1]
+        && excl.as_char() == '!' && i + 2 < tokens.len()
+        && let TokenTree::Group(ref group) = tokens[i + 2] {
+            output.extend(group.stream());
+            i += 3;
+            continue;
         }
         match &tokens[i] {
             TokenTree::Group(group) => {
@@ -759,6 +2853,46 @@ fn expand_expand_macro(input: TokenStream) -> TokenStream {
     output
 }
 
+/// Replaces the `$crate` tokens in the generated code with the `$crate` of the `macro_rules!`
+/// generated by `crabtime::function`, so it resolves to the crate defining the macro.
+fn replace_crate_sentinel(input: TokenStream, crate_path: &proc_macro2::Ident) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let mut output = TokenStream::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if let TokenTree::Punct(ref dollar) = tokens[i]
+        && dollar.as_char() == '$' && i + 1 < tokens.len()
+        && let TokenTree::Ident(ref ident) = tokens[i + 1]
+        && *ident == "crate" {
+            output.extend(std::iter::once(TokenTree::Ident(crate_path.clone())));
+            i += 2;
+            continue;
+        }
+        match &tokens[i] {
+            TokenTree::Group(group) => {
+                let new_stream = replace_crate_sentinel(group.stream(), crate_path);
+                let mut new_group = proc_macro2::Group::new(group.delimiter(), new_stream);
+                new_group.set_span(group.span());
+                output.extend(std::iter::once(TokenTree::Group(new_group)));
+            }
+            _ => {
+                output.extend(std::iter::once(tokens[i].clone()));
+            }
+        }
+        i += 1;
+    }
+    output
+}
+
+/// Checks whether the tokens contain the identifier, for example, a call of a prelude function.
+fn contains_ident(input: &TokenStream, name: &str) -> bool {
+    input.clone().into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => ident == name,
+        TokenTree::Group(group) => contains_ident(&group.stream(), name),
+        _ => false,
+    })
+}
+
 fn expand_builtin_macro(
     name: &str,
     input: TokenStream,
@@ -771,34 +2905,23 @@ fn expand_builtin_macro(
 
     while i < len {
         // Check for the pattern: crabtime :: output ! ( group )
-        if i + 5 < len {
-            if let TokenTree::Ident(ref ident) = tokens[i] {
-                if ident == GEN_MOD {
-                    if let TokenTree::Punct(ref colon1) = tokens[i + 1] {
-                        if colon1.as_char() == ':' {
-                            if let TokenTree::Punct(ref colon2) = tokens[i + 2] {
-                                if colon2.as_char() == ':' {
-                                    if let TokenTree::Ident(ref out_ident) = tokens[i + 3] {
-                                        if out_ident == name {
-                                            if let TokenTree::Punct(ref excl) = tokens[i + 4] {
-                                                if excl.as_char() == '!' {
-                                                    if let TokenTree::Group(ref group) = tokens[i + 5] {
-                                                        let inner_rewritten = expand_builtin_macro(name, group.stream(), f);
-                                                        let new_tokens = f(inner_rewritten);
-                                                        output.extend(new_tokens);
-                                                        i += 6;
-                                                        continue;
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        if i + 5 < len
+        && let TokenTree::Ident(ref ident) = tokens[i]
+        && ident == GEN_MOD
+        && let TokenTree::Punct(ref colon1) = tokens[i + 1]
+        && colon1.as_char() == ':'
+        && let TokenTree::Punct(ref colon2) = tokens[i + 2]
+        && colon2.as_char() == ':'
+        && let TokenTree::Ident(ref out_ident) = tokens[i + 3]
+        && out_ident == name
+        && let TokenTree::Punct(ref excl) = tokens[i + 4]
+        && excl.as_char() == '!'
+        && let TokenTree::Group(ref group) = tokens[i + 5] {
+            let inner_rewritten = expand_builtin_macro(name, group.stream(), f);
+            let new_tokens = f(inner_rewritten);
+            output.extend(new_tokens);
+            i += 6;
+            continue;
         }
 
         // Recurse into groups or pass through token.
@@ -819,34 +2942,226 @@ fn expand_builtin_macro(
 fn expand_output_macro(input: TokenStream) -> TokenStream {
     let gen_mod = syn::Ident::new(GEN_MOD, Span::call_site());
     expand_builtin_macro("output", input, &|inner_rewritten| {
-        let content_str = print_tokens(&inner_rewritten);
-        let lit = syn::LitStr::new(&content_str, Span::call_site());
+        let args = format_args_with_exprs(&print_tokens(&inner_rewritten));
         quote! {
-            #gen_mod::write_ln!(__output_buffer__, #lit);
+            #gen_mod::write_ln!(__output_buffer__, #args);
         }
     })
 }
 
+/// Expands `quote! { ... }` to a `format!` call returning the code as a `String`. The
+/// interpolation is the same as in `output!`, so nested quotes can be composed.
 fn expand_quote_macro(input: TokenStream) -> TokenStream {
     expand_builtin_macro("quote", input, &|inner_rewritten| {
-        let content_str = print_tokens(&inner_rewritten);
-        let lit = syn::LitStr::new(&content_str, Span::call_site());
-        quote! { format!(#lit) }
+        let args = format_args_with_exprs(&print_tokens(&inner_rewritten));
+        quote! { format!(#args) }
     })
 }
 
-// =============
-// === Print ===
-// =============
-
-#[derive(Debug)]
-struct PrintOutput {
-    output: String,
-    start_token: Option<LineColumn>,
-    end_token: Option<LineColumn>,
-}
-
-/// Prints the token stream as a string ready to be used by the format macro. It is very careful
+/// Converts the code printed by [`print_tokens`] to the arguments of `format!`. Interpolated
+/// variables, like `{{name}}`, are captured by the format string. Other interpolated expressions,
+/// like `{{items.len()}}` or `{{crabtime::quote! { ... }}}`, are replaced with `{}` and passed as
+/// positional arguments. The braces of the code are doubled, so a `}` closes the placeholder only
+/// if it is not a part of a doubled pair balancing a `{{` in the expression.
+fn format_args_with_exprs(code: &str) -> TokenStream {
+    let mut format_str = String::with_capacity(code.len());
+    let mut args: Vec<TokenStream> = vec![];
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        let is_escaped = matches!(c, '{' | '}') && chars.peek() == Some(&c);
+        if is_escaped || c != '{' {
+            format_str.push(c);
+            if is_escaped {
+                format_str.extend(chars.next());
+            }
+            continue;
+        }
+        let mut expr = String::new();
+        let mut depth = 0;
+        while let Some(c) = chars.next() {
+            let is_doubled = chars.peek() == Some(&c);
+            match c {
+                '{' if is_doubled => depth += 1,
+                '}' if is_doubled && depth > 0 => depth -= 1,
+                '}' => break,
+                _ => {
+                    expr.push(c);
+                    continue
+                }
+            }
+            chars.next();
+            expr.push(c);
+        }
+        let is_capture = syn::parse_str::<syn::Ident>(expr.split(':').next().unwrap_or_default())
+            .is_ok() && !expr.contains("::");
+        // The tokens are printed with spaces, like `name :?`, which are not allowed in captures.
+        if is_capture {
+            expr.retain(|c| !c.is_whitespace());
+        }
+        match expr.parse::<TokenStream>() {
+            Ok(tokens) if !is_capture && syn::parse2::<syn::Expr>(tokens.clone()).is_ok() => {
+                format_str.push_str("{}");
+                args.push(tokens);
+            }
+            _ => format_str.push_str(&format!("{{{expr}}}")),
+        }
+    }
+    let lit = syn::LitStr::new(&format_str, Span::call_site());
+    quote! { #lit #(, #args)* }
+}
+
+/// Expands `derive_output!(derives, { item })`. The `derives` expression is evaluated in the
+/// generated code, so the list can be computed at build time. The item is printed just like the
+/// `output!` macro content.
+fn expand_derive_output_macro(input: TokenStream) -> TokenStream {
+    let gen_mod = syn::Ident::new(GEN_MOD, Span::call_site());
+    expand_builtin_macro("derive_output", input, &|inner_rewritten| {
+        let mut tokens: Vec<TokenTree> = inner_rewritten.into_iter().collect();
+        let item = match tokens.pop() {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace =>
+                group.stream(),
+            _ => return quote! {
+                compile_error!("Expected `derive_output!(derives, { item })`.");
+            }
+        };
+        if let Some(TokenTree::Punct(punct)) = tokens.last()
+        && punct.as_char() == ',' {
+            tokens.pop();
+        }
+        let derives: TokenStream = tokens.into_iter().collect();
+        let content_str = print_tokens(&item);
+        let lit = syn::LitStr::new(&content_str, Span::call_site());
+        quote! {
+            #gen_mod::write_ln!(__output_buffer__, "{}", #gen_mod::derive_attribute(&#derives));
+            #gen_mod::write_ln!(__output_buffer__, #lit);
+        }
+    })
+}
+
+/// Start and end line and column of an input token.
+type InputSpan = (LineColumn, LineColumn);
+
+/// Removes the `input_spans! { tokens }` call, emitted by function-like macros with a `TokenStream`
+/// parameter, and returns the spans of the tokens. Lines are 1-based and columns are 0-based. If
+/// the compiler does not provide span locations, all of them are `(0, 0)..(0, 0)`.
+fn expand_input_spans_macro(input: TokenStream) -> (TokenStream, Vec<InputSpan>) {
+    let spans = std::cell::RefCell::new(vec![]);
+    let output = expand_builtin_macro("input_spans", input, &|inner| {
+        spans.borrow_mut().extend(inner.into_iter().map(|t| (t.span().start(), t.span().end())));
+        TokenStream::new()
+    });
+    (output, spans.into_inner())
+}
+
+/// Expands `named_args!([name = value, ...] param: Type; param: Type = default; ...)`, emitted by
+/// the named arguments arm of function-like macros, to `let` bindings of the parameters. The values
+/// can be given in any order and are converted like positional arguments. The defaults are already
+/// converted. Unknown, duplicated, and missing arguments are reported as errors.
+fn expand_named_args_macro(input: TokenStream) -> Result<TokenStream> {
+    let errors = std::cell::RefCell::new(vec![]);
+    let output = expand_builtin_macro("named_args", input, &|inner| {
+        named_args_bindings(inner).unwrap_or_else(|error| {
+            errors.borrow_mut().push(error);
+            TokenStream::new()
+        })
+    });
+    match errors.into_inner().into_iter().next() {
+        Some(error) => err!("{error}"),
+        None => Ok(output),
+    }
+}
+
+fn named_args_bindings(input: TokenStream) -> std::result::Result<TokenStream, String> {
+    let mut tokens = input.into_iter();
+    let Some(TokenTree::Group(values)) = tokens.next() else {
+        return Err("Expected named arguments in brackets.".to_string())
+    };
+    let mut values = split_on_commas(values.stream()).into_iter()
+        .map(|arg| match &arg[..] {
+            [TokenTree::Ident(name), TokenTree::Punct(eq), value @ ..]
+            if eq.as_char() == '=' && !value.is_empty() =>
+                Ok((name.to_string(), value.iter().cloned().collect::<TokenStream>())),
+            _ => Err(format!("Expected a `name = value` argument, found `{}`.", print_args(&arg))),
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let is_punct = |token: &TokenTree, c| matches!(token, TokenTree::Punct(p) if p.as_char() == c);
+    let mut params = vec![];
+    let mut bindings = TokenStream::new();
+    while let Some(TokenTree::Ident(param)) = tokens.next() {
+        let decl = tokens.by_ref().skip(1).take_while(|t| !is_punct(t, ';')).collect::<Vec<_>>();
+        let (tp, default) = match decl.iter().position(|t| is_punct(t, '=')) {
+            Some(index) => (&decl[..index], Some(decl[index + 1..].iter().cloned().collect())),
+            None => (&decl[..], None),
+        };
+        let tp = tp.iter().cloned().collect::<TokenStream>();
+        let param_str = param.to_string();
+        let matching = values.iter().filter(|(name, _)| *name == param_str).count();
+        if matching > 1 {
+            return Err(format!("Argument `{param}` is given more than once."))
+        }
+        let value = match values.iter().position(|(name, _)| *name == param_str) {
+            Some(index) => {
+                let (_, value) = values.remove(index);
+                let ty = syn::parse2::<syn::Type>(tp.clone()).map_err(|e| e.to_string())?;
+                let expected = match ty {
+                    syn::Type::Tuple(_) => "a tuple, like `(a, b)`",
+                    _ => "an array, like `[a, b]`",
+                };
+                arg_value_code(&ty, value).ok_or_else(|| {
+                    format!("Argument `{param}` should be {expected}.")
+                })?
+            }
+            None => default.ok_or_else(|| format!("Missing argument `{param}`."))?,
+        };
+        bindings.extend(quote! { let #param: #tp = #value; });
+        params.push(param_str);
+    }
+    if let Some((name, _)) = values.first() {
+        return Err(format!("Unknown argument `{name}`. Expected one of: {}.", params.join(", ")))
+    }
+    Ok(bindings)
+}
+
+fn print_args(tokens: &[TokenTree]) -> String {
+    tokens.iter().cloned().collect::<TokenStream>().to_string()
+}
+
+/// Splits the tokens on top-level commas. Empty parts, like after a trailing comma, are skipped.
+fn split_on_commas(input: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![vec![]];
+    for token in input {
+        if matches!(&token, TokenTree::Punct(punct) if punct.as_char() == ',') {
+            parts.push(vec![]);
+        } else if let Some(part) = parts.last_mut() {
+            part.push(token);
+        }
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Unwraps the invisible groups around tokens captured by `macro_rules!` fragments, like `expr`.
+fn without_none_groups(input: TokenStream) -> TokenStream {
+    input.into_iter().flat_map(|token| match token {
+        TokenTree::Group(group) if group.delimiter() == Delimiter::None =>
+            without_none_groups(group.stream()),
+        token => std::iter::once(token).collect(),
+    }).collect()
+}
+
+// =============
+// === Print ===
+// =============
+
+#[derive(Debug)]
+struct PrintOutput {
+    output: String,
+    start_token: Option<LineColumn>,
+    end_token: Option<LineColumn>,
+}
+
+/// Prints the token stream as a string ready to be used by the format macro. It is very careful
 /// where spaces are inserted. In particular, spaces are not inserted around `{` and `}` tokens if
 /// they were not present in the original token stream. It is fine-tuned to work in different IDEs,
 /// such as `RustRover`.
@@ -867,12 +3182,14 @@ fn print_tokens_internal(tokens: &TokenStream) -> PrintOutput {
     let mut first_token_start = None;
     let mut prev_token_end: Option<LineColumn> = None;
     let mut prev_token_was_brace = false;
+    let mut prev_token_was_lifetime_quote = false;
     for (i, token) in token_vec.iter().enumerate() {
         let mut add_space = true;
         let mut token_start = token.span().start();
         let mut token_end = token.span().end();
         let mut is_brace = false;
         let mut is_keyword = false;
+        let mut is_lifetime_quote = false;
         let token_str = match token {
             TokenTree::Group(g) => {
                 let content = print_tokens_internal(&g.stream());
@@ -881,7 +3198,16 @@ fn print_tokens_internal(tokens: &TokenStream) -> PrintOutput {
                 let (open, close) = match g.delimiter() {
                     Delimiter::Brace => {
                         is_brace = true;
-                        if content_str.starts_with("{") && content_str.ends_with("}") {
+                        // Only the `{{ ... }}` interpolation is a brace group containing just a
+                        // brace group. Blocks starting and ending with interpolations, like
+                        // `{ {{a}} x {{b}} }`, are printed with their braces.
+                        let mut stream = g.stream().into_iter();
+                        let is_interpolation = matches!(
+                            (stream.next(), stream.next()),
+                            (Some(TokenTree::Group(inner)), None)
+                                if inner.delimiter() == Delimiter::Brace
+                        );
+                        if is_interpolation {
                             ("{%%%", "%%%}")
                         } else {
                             ("{", "}")
@@ -907,12 +3233,15 @@ fn print_tokens_internal(tokens: &TokenStream) -> PrintOutput {
             }
             TokenTree::Ident(ident) => {
                 let str = ident.to_string();
-                is_keyword = KEYWORDS.contains(&str.as_str());
+                // The ident following `'` is a lifetime name. It has to stay glued to the quote,
+                // and it is always followed by a space, so `&'a str` never becomes `&'astr`.
+                is_keyword = !prev_token_was_lifetime_quote && KEYWORDS.contains(&str.as_str());
                 str
             },
             TokenTree::Literal(lit) => lit.to_string(),
             TokenTree::Punct(punct) => {
-                if punct.spacing() == proc_macro2::Spacing::Joint {
+                is_lifetime_quote = punct.as_char() == '\'';
+                if is_lifetime_quote || punct.spacing() == proc_macro2::Spacing::Joint {
                     add_space = false;
                 }
                 punct.as_char().to_string()
@@ -921,16 +3250,15 @@ fn print_tokens_internal(tokens: &TokenStream) -> PrintOutput {
         debug!("{i}: [{token_start:?}-{token_end:?}] [{prev_token_end:?}]: {token}");
 
         // check if the punct has set flags to have no spaces
-        if is_brace || prev_token_was_brace {
-            if let Some(prev_token_end) = prev_token_end {
-                if prev_token_end.line == token_start.line
-                && prev_token_end.column >= token_start.column
-                && output.ends_with(" ") {
-                    output.pop();
-                }
-            }
+        if (is_brace || prev_token_was_brace)
+        && let Some(prev_token_end) = prev_token_end
+        && prev_token_end.line == token_start.line
+        && prev_token_end.column >= token_start.column
+        && output.ends_with(" ") {
+            output.pop();
         }
         prev_token_was_brace = is_brace;
+        prev_token_was_lifetime_quote = is_lifetime_quote;
 
         // Pushing a space before and after keywords is for IntelliJ only.
         // Their token spans are invalid.
@@ -957,124 +3285,334 @@ fn print_tokens_internal(tokens: &TokenStream) -> PrintOutput {
 
 enum Args {
     TokenStream { ident: syn::Ident },
-    Pattern { str: TokenStream }
+    /// The whole input as a string, bound to a single `#[input] &str` parameter.
+    Str { ident: syn::Ident },
+    /// Arguments of an attribute macro and the annotated item, passed as `[args] item`.
+    AttributeTokenStreams { attr: syn::Ident, item: syn::Ident },
+    /// A `macro_rules!` pattern. Macros with supported argument types can be also called with named
+    /// arguments, given by the `name: Type = default;` declarations for `crabtime::named_args!`,
+    /// and without the trailing arguments having defaults, given by the `(pattern, code)` pairs of
+    /// the additional arms.
+    Pattern {
+        str: TokenStream,
+        named: Option<TokenStream>,
+        defaults: Vec<(TokenStream, TokenStream)>,
+    }
 }
 
 impl Args {
     fn pattern(&self) -> TokenStream {
         match self {
             Self::TokenStream { ident } => quote! { $($#ident:tt)* },
-            Self::Pattern { str } => str.clone(),
+            Self::Str { ident } => quote! { $($#ident:tt)* },
+            Self::AttributeTokenStreams { attr, item } => quote! { [$($#attr:tt)*] $($#item:tt)* },
+            Self::Pattern { str, .. } => str.clone(),
         }
     }
 
     fn setup(&self) -> TokenStream {
-        if let Self::TokenStream { ident } = self {
-            quote! {
-                use proc_macro2::TokenStream;
+        match self {
+            Self::TokenStream { ident } => quote! {
+                use crabtime::TokenStream;
                 let #ident: TokenStream = stringify!($($#ident)*).parse().unwrap();
-            }
-        } else {
-            Default::default()
+                #[allow(dead_code)]
+                const INPUT_TOKEN_COUNT: usize = <[&str]>::len(&[$(stringify!($#ident)),*]);
+                crabtime::input_spans! { $($#ident)* }
+            },
+            Self::Str { ident } => quote! {
+                let #ident: &str = stringify!($($#ident)*);
+            },
+            Self::AttributeTokenStreams { attr, item } => quote! {
+                use crabtime::TokenStream;
+                let #attr: TokenStream = stringify!($($#attr)*).parse().unwrap();
+                let #item: TokenStream = stringify!($($#item)*).parse().unwrap();
+            },
+            Self::Pattern { .. } => Default::default(),
         }
     }
 }
 
+/// Binds two `TokenStream` parameters to the arguments of an attribute macro and the annotated
+/// item.
+fn parse_args_for_attribute(
+    args: &syn::punctuated::Punctuated<syn::FnArg, syn::token::Comma>
+) -> Option<Args> {
+    let [attr, item] = args.iter().collect::<Vec<_>>()[..] else { return None };
+    let Args::TokenStream { ident: attr } = parse_args_for_token_stream(attr)? else { return None };
+    let Args::TokenStream { ident: item } = parse_args_for_token_stream(item)? else { return None };
+    Some(Args::AttributeTokenStreams { attr, item })
+}
+
+/// Binds a single `&str` parameter annotated with `#[input]` to the whole input, stringified.
+/// Without the attribute, a `&str` parameter binds a single argument, like other typed parameters.
+fn parse_args_for_str(
+    args: &syn::punctuated::Punctuated<syn::FnArg, syn::token::Comma>
+) -> Option<Args> {
+    let [arg] = args.iter().collect::<Vec<_>>()[..] else { return None };
+    let syn::FnArg::Typed(pat) = arg else { return None };
+    if !pat.attrs.iter().any(|attr| attr.path().is_ident("input")) { return None }
+    let syn::Pat::Ident(pat_ident) = &*pat.pat else { return None };
+    let syn::Type::Reference(ty_ref) = &*pat.ty else { return None };
+    let syn::Type::Path(inner_path) = &*ty_ref.elem else { return None };
+    if ty_ref.mutability.is_some() || !inner_path.path.is_ident("str") { return None }
+    Some(Args::Str { ident: pat_ident.ident.clone() })
+}
+
 fn parse_args(
     args: &syn::punctuated::Punctuated<syn::FnArg, syn::token::Comma>
 ) -> Option<(Args, TokenStream)> {
     let Some(arg) = args.first() else {
-        return Some((Args::Pattern { str: Default::default() }, TokenStream::new()))
+        let args = Args::Pattern { str: Default::default(), named: None, defaults: vec![] };
+        return Some((args, TokenStream::new()))
     };
 
     // First try the specialized parsers, then fallback to our generic type handling.
-    parse_args_for_pattern(arg)
+    parse_args_for_attribute(args)
+        .or_else(|| parse_args_for_str(args))
+        .or_else(|| parse_args_for_pattern(args))
         .or_else(|| parse_args_for_token_stream(arg))
         .map(|t| (t, TokenStream::new()))
         .or_else(|| {
-            let mut is_first = true;
-            let mut pat = quote!{};
-            let mut code = TokenStream::new();
+            // Pattern, binding, and the binding of the default value of each argument.
+            let mut params: Vec<(TokenStream, TokenStream, Option<TokenStream>)> = vec![];
+            let mut named = Some(TokenStream::new());
 
             for arg in args {
-                if !is_first {
-                    pat = quote! {#pat, };
-                }
-                is_first = false;
-                if let syn::FnArg::Typed(pat_type) = arg {
-
-                    if let syn::Pat::Ident(name) = &*pat_type.pat {
-                        let name_str = name.ident.to_string();
-                        let ty = &*pat_type.ty;
-                        code = quote! {
-                            #code
-                            let #name: #ty =
-                        };
-                        if let Some((param_pat, param_code)) = parse_arg_type(&name_str, ty) {
-                            pat = quote! {#pat #param_pat};
-                            code = quote! {#code #param_code};
-                        }
-                        code = quote! {#code;};
-                    }
+                if let Some(pattern) = pattern_arg(arg) {
+                    // Raw patterns bind `macro_rules!` variables instead of Rust variables.
+                    named = None;
+                    params.push((pattern.clone(), TokenStream::new(), None));
+                } else if let syn::FnArg::Typed(pat_type) = arg
+                && let syn::Pat::Ident(name) = &*pat_type.pat {
+                    let name_str = name.ident.to_string();
+                    let ty = &*pat_type.ty;
+                    let parsed = parse_arg_type(&name_str, ty);
+                    let (param_pat, param_code) = parsed.clone().unwrap_or_default();
+                    let default = default_arg(&pat_type.attrs).and_then(|t| arg_value_code(ty, t));
+                    let named_supported = parsed.is_some() && !contains_map_type(ty);
+                    named = named.filter(|_| named_supported).map(|named| match &default {
+                        Some(default) => quote! { #named #name: #ty = #default; },
+                        None => quote! { #named #name: #ty; },
+                    });
+                    let binding = |code| quote! { let #name: #ty = #code; };
+                    params.push((param_pat, binding(param_code), default.map(binding)));
+                } else {
+                    named = None;
+                    params.push(Default::default());
                 }
             }
-            pat = quote! {#pat $(,)?};
-            Some((Args::Pattern { str: pat }, code))
+            // An arm for each number of given arguments. The omitted ones are bound to defaults.
+            let arm = |count: usize| {
+                let pats = params[..count].iter().map(|(pat, _, _)| pat);
+                let bindings = params.iter().enumerate().map(|(index, (_, binding, default))| {
+                    let binding = if index < count { Some(binding) } else { default.as_ref() };
+                    binding.cloned().unwrap_or_default()
+                });
+                (quote! { #(#pats),* $(,)? }, quote! { #(#bindings)* })
+            };
+            let defaults_count = params.iter().rev().take_while(|(_, _, d)| d.is_some()).count();
+            let defaults = (params.len() - defaults_count..params.len()).rev().map(arm).collect();
+            let (str, code) = arm(params.len());
+            Some((Args::Pattern { str, named, defaults }, code))
         })
 }
 
-/// Returns (pattern, code) for a given type. It supports both vector types and non‑vector types.
+/// Returns the tokens of the `#[default(...)]` attribute of an argument.
+fn default_arg(attrs: &[syn::Attribute]) -> Option<TokenStream> {
+    attrs.iter()
+        .filter(|attr| attr.path().is_ident("default"))
+        .find_map(|attr| attr.meta.require_list().ok())
+        .map(|list| list.tokens.clone())
+}
+
+/// Returns (pattern, code) for a given type. It supports vector types, tuple types, and
+/// non‑vector types. Vector and tuple elements are handled by [`parse_inner_type`], which recurses
+/// back here for nested vectors and tuples, so `Vec<Vec<usize>>` results in the nested repetition
+/// pattern `[$([$($x:literal),*$(,)?]),*$(,)?]`.
 #[inline(always)]
 fn parse_arg_type(pfx: &str, ty: &syn::Type) -> Option<(TokenStream, TokenStream)> {
+    if let syn::Type::Tuple(tuple) = ty {
+        return parse_tuple_type(pfx, tuple);
+    }
     if let syn::Type::Path(type_path) = ty {
-        let last_segment = type_path.path.segments.last()?;
-        if last_segment.ident == "Vec" {
-            if let syn::PathArguments::AngleBracketed(angle_bracketed) = &last_segment.arguments {
-                let generic_arg = angle_bracketed.args.first()?;
-                if let syn::GenericArgument::Type(inner_ty) = generic_arg {
-                    if let Some((inner_pat, inner_code)) = parse_inner_type(pfx, inner_ty) {
-                        let pat = quote! {[$(#inner_pat),*$(,)?]};
-                        let code = quote! { [$(#inner_code),*].into_iter().collect() };
-                        return Some((pat, code));
-                    }
-                }
-            }
-        } else {
-            return parse_inner_type(pfx, ty);
+        if let Some(inner_ty) = vec_element_type(type_path) {
+            let (inner_pat, inner_code) = parse_inner_type(pfx, inner_ty)?;
+            let pat = quote! {[$(#inner_pat),*$(,)?]};
+            let code = quote! { [$(#inner_code),*].into_iter().collect() };
+            return Some((pat, code));
         }
+        if let Some((key_ty, value_ty)) = map_entry_types(type_path) {
+            return parse_map_type(pfx, key_ty, value_ty);
+        }
+        return parse_inner_type(pfx, ty);
+    }
+    if let syn::Type::Reference(_) = ty {
+        return parse_inner_type(pfx, ty);
     }
     None
 }
 
+/// Returns (pattern, code) for a map type, like `BTreeMap<String, usize>`. Entries are given as
+/// `key: value` pairs in braces. The `expr` fragment can't be followed by `:`, so keys are matched
+/// as single token trees, like string literals, identifiers, or numbers. Values are handled by
+/// [`parse_inner_type`].
+fn parse_map_type(
+    pfx: &str,
+    key_ty: &syn::Type,
+    value_ty: &syn::Type
+) -> Option<(TokenStream, TokenStream)> {
+    let key_ident = syn::Ident::new(&format!("{pfx}_key_arg"), Span::call_site());
+    let key_arg = quote! {$#key_ident};
+    let (_, key_code) = arg_conversion(&key_arg, key_ty)?;
+    let (value_pat, value_code) = parse_inner_type(&format!("{pfx}_value"), value_ty)?;
+    let pat = quote! { { $(#key_arg:tt : #value_pat),* $(,)? } };
+    let code = quote! { [$((#key_code, #value_code)),*].into_iter().collect() };
+    Some((pat, code))
+}
+
+/// Returns (pattern, code) for a tuple type, like `(usize, &str)`. Each element is handled by
+/// [`parse_inner_type`] with the element index appended to the prefix, so the pattern of
+/// `pair: (usize, &str)` is `($pair_0_arg:literal, $pair_1_arg:expr)`.
+fn parse_tuple_type(pfx: &str, tuple: &syn::TypeTuple) -> Option<(TokenStream, TokenStream)> {
+    if tuple.elems.is_empty() { return None }
+    let (pats, codes): (Vec<_>, Vec<_>) = tuple.elems.iter().enumerate()
+        .map(|(index, elem)| parse_inner_type(&format!("{pfx}_{index}"), elem))
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    Some((quote! { (#(#pats),* $(,)?) }, quote! { (#(#codes,)*) }))
+}
+
+/// Returns the code converting the argument value to the given type, just like the code returned by
+/// [`parse_arg_type`] converts the arguments matched by the pattern. Vectors are given as arrays,
+/// tuples as parenthesized lists, and maps as `key: value` pairs in braces.
+fn arg_value_code(ty: &syn::Type, value: TokenStream) -> Option<TokenStream> {
+    let elements = |delimiter| match &without_none_groups(value.clone()).into_iter()
+        .collect::<Vec<_>>()[..] {
+        [TokenTree::Group(group)] if group.delimiter() == delimiter =>
+            Some(split_on_commas(group.stream())),
+        _ => None,
+    };
+    let element_code = |element: Vec<TokenTree>, ty| {
+        arg_value_code(ty, element.into_iter().collect())
+    };
+    match ty {
+        syn::Type::Tuple(tuple) => {
+            let elements = elements(Delimiter::Parenthesis)?;
+            if elements.len() != tuple.elems.len() { return None }
+            let elements = elements.into_iter().zip(&tuple.elems)
+                .map(|(element, ty)| element_code(element, ty))
+                .collect::<Option<Vec<_>>>()?;
+            Some(quote! { (#(#elements,)*) })
+        }
+        syn::Type::Path(type_path) => {
+            if let Some((key_ty, value_ty)) = map_entry_types(type_path) {
+                let entries = elements(Delimiter::Brace)?.into_iter()
+                    .map(|entry| match &entry[..] {
+                        [key, TokenTree::Punct(colon), value @ ..]
+                        if colon.as_char() == ':' && !value.is_empty() => {
+                            let (_, key) = arg_conversion(&key.clone().into(), key_ty)?;
+                            let value = element_code(value.to_vec(), value_ty)?;
+                            Some(quote! { (#key, #value) })
+                        }
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                return Some(quote! { [#(#entries),*].into_iter().collect() })
+            }
+            let Some(inner_ty) = vec_element_type(type_path) else {
+                return arg_conversion(&value, ty).map(|(_, code)| code)
+            };
+            let elements = elements(Delimiter::Bracket)?.into_iter()
+                .map(|element| element_code(element, inner_ty))
+                .collect::<Option<Vec<_>>>()?;
+            Some(quote! { [#(#elements),*].into_iter().collect() })
+        }
+        _ => arg_conversion(&value, ty).map(|(_, code)| code),
+    }
+}
+
+/// Returns the key and value types if the type is a `HashMap` or a `BTreeMap`.
+fn map_entry_types(type_path: &syn::TypePath) -> Option<(&syn::Type, &syn::Type)> {
+    let last_segment = type_path.path.segments.last()?;
+    if last_segment.ident != "HashMap" && last_segment.ident != "BTreeMap" { return None }
+    let syn::PathArguments::AngleBracketed(angle_bracketed) = &last_segment.arguments else {
+        return None
+    };
+    let mut types = angle_bracketed.args.iter().filter_map(|arg| match arg {
+        syn::GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    });
+    Some((types.next()?, types.next()?))
+}
+
+/// Checks whether the type is or contains a map. Maps are given in braces, which are not valid
+/// expressions, so they can't be passed as named arguments.
+fn contains_map_type(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Tuple(tuple) => tuple.elems.iter().any(contains_map_type),
+        syn::Type::Path(type_path) => map_entry_types(type_path).is_some()
+            || vec_element_type(type_path).is_some_and(contains_map_type),
+        _ => false,
+    }
+}
+
+/// Returns the element type if the type is a `Vec`.
+fn vec_element_type(type_path: &syn::TypePath) -> Option<&syn::Type> {
+    let last_segment = type_path.path.segments.last()?;
+    if last_segment.ident != "Vec" { return None }
+    let syn::PathArguments::AngleBracketed(angle_bracketed) = &last_segment.arguments else {
+        return None
+    };
+    match angle_bracketed.args.first()? {
+        syn::GenericArgument::Type(inner_ty) => Some(inner_ty),
+        _ => None,
+    }
+}
+
 #[inline(always)]
 fn parse_inner_type(pfx: &str, ty: &syn::Type) -> Option<(TokenStream, TokenStream)> {
+    let is_nested = match ty {
+        syn::Type::Tuple(_) => true,
+        syn::Type::Path(type_path) =>
+            vec_element_type(type_path).is_some() || map_entry_types(type_path).is_some(),
+        _ => false,
+    };
+    if is_nested {
+        return parse_arg_type(pfx, ty);
+    }
     let arg_str = format!("{pfx}_arg");
     let arg_ident = syn::Ident::new(&arg_str, Span::call_site());
     let arg = quote! {$#arg_ident};
+    let (fragment, code) = arg_conversion(&arg, ty)?;
+    Some((quote!{#arg:#fragment}, code))
+}
+
+/// Returns the `macro_rules!` fragment specifier matching an argument of the given type and the
+/// code converting the argument to it.
+fn arg_conversion(arg: &TokenStream, ty: &syn::Type) -> Option<(syn::Ident, TokenStream)> {
+    let fragment = |name: &str| syn::Ident::new(name, Span::call_site());
     match ty {
         syn::Type::Reference(ty_ref) => {
-            if let syn::Type::Path(inner_path) = &*ty_ref.elem {
-                if let Some(inner_seg) = inner_path.path.segments.last() {
-                    if inner_seg.ident == "str" {
-                        let pat = quote!{#arg:expr};
-                        let code = quote!{crabtime::stringify_if_needed!{#arg}};
-                        return Some((pat, code));
-                    }
-                }
+            if let syn::Type::Path(inner_path) = &*ty_ref.elem
+            && let Some(inner_seg) = inner_path.path.segments.last()
+            && inner_seg.ident == "str" {
+                let code = quote!{crabtime::stringify_if_needed!{#arg}};
+                return Some((fragment("expr"), code));
             }
         },
         syn::Type::Path(inner_type_path) => {
             if let Some(inner_seg) = inner_type_path.path.segments.last() {
                 let ident_str = inner_seg.ident.to_string();
                 if ident_str == "String" {
-                    let pat = quote!{#arg:expr};
                     let code = quote!{crabtime::stringify_if_needed!(#arg).to_string()};
-                    return Some((pat, code));
+                    return Some((fragment("expr"), code));
                 } else if matches!(ident_str.as_str(),
                     "usize" | "u8" | "u16" | "u32" | "u64" | "u128" |
-                    "isize" | "i8" | "i16" | "i32" | "i64" | "i128"
+                    "isize" | "i8" | "i16" | "i32" | "i64" | "i128" |
+                    "f32" | "f64" | "bool" | "char"
                 ) {
-                    return Some((quote!{#arg:literal}, quote!{#arg}));
+                    return Some((fragment("literal"), quote!{#arg}));
                 }
             }
         },
@@ -1083,10 +3621,21 @@ fn parse_inner_type(pfx: &str, ty: &syn::Type) -> Option<(TokenStream, TokenStre
     None
 }
 
-fn parse_args_for_pattern(arg: &syn::FnArg) -> Option<Args> {
+/// Uses the `pattern!(...)` of a single parameter as is. Patterns combined with other parameters
+/// are handled by the generic path of [`parse_args`], which joins the patterns with commas.
+fn parse_args_for_pattern(
+    args: &syn::punctuated::Punctuated<syn::FnArg, syn::token::Comma>
+) -> Option<Args> {
+    let [arg] = args.iter().collect::<Vec<_>>()[..] else { return None };
+    let str = pattern_arg(arg)?.clone();
+    Some(Args::Pattern { str, named: None, defaults: vec![] })
+}
+
+/// Returns the tokens of the `pattern!(...)` parameter.
+fn pattern_arg(arg: &syn::FnArg) -> Option<&TokenStream> {
     let syn::FnArg::Typed(pat) = arg else { return None };
     let syn::Pat::Macro(m) = &*pat.pat else { return None };
-    Some(Args::Pattern {str: m.mac.tokens.clone() })
+    Some(&m.mac.tokens)
 }
 
 fn parse_args_for_token_stream(arg: &syn::FnArg) -> Option<Args> {
@@ -1099,20 +3648,61 @@ fn parse_args_for_token_stream(arg: &syn::FnArg) -> Option<Args> {
     Some(Args::TokenStream { ident })
 }
 
-const WRONG_ARGS: &str = "Function should have zero or one argument, one of:
+/// Checks whether the type is a `TokenStream`, either bare or behind a path, like
+/// `proc_macro2::TokenStream`.
+fn is_token_stream_type(tp: &syn::Type) -> bool {
+    let syn::Type::Path(type_path) = tp else { return false };
+    type_path.path.segments.last().is_some_and(|segment| segment.ident == "TokenStream")
+}
+
+const WRONG_ARGS: &str = "Function should have no arguments or one of:
     - `pattern!(<pattern>): _`, where <pattern> is a `macro_rules!` pattern
     - `input: TokenStream`
+    - `#[input] input: &str`, for the whole input as a string
+    - `attr: TokenStream, item: TokenStream`, for attribute macros
 ";
 
+/// Wrapper in the generated prelude implementing `CodeFromOutput` for an `impl Trait` return
+/// type. Such types cannot be used in the type of a `let` binding, and blanket implementations of
+/// `CodeFromOutput` for iterators or `Display` types would conflict with the ones for `String` and
+/// the numbers, as the standard library could implement these traits for them in the future.
+fn impl_output_wrapper(tp: &syn::Type) -> Result<Option<&'static str>> {
+    let syn::Type::ImplTrait(impl_trait) = tp else { return Ok(None) };
+    for bound in &impl_trait.bounds {
+        let syn::TypeParamBound::Trait(bound) = bound else { continue };
+        let Some(segment) = bound.path.segments.last() else { continue };
+        let name = segment.ident.to_string();
+        if name.ends_with("Iterator") {
+            return Ok(Some("OutputLines"))
+        }
+        if name == "Display" {
+            return Ok(Some("OutputDisplay"))
+        }
+    }
+    let span = syn::spanned::Spanned::span(tp);
+    err!(span, "Unsupported return type, use `impl Iterator<Item = T>` or `impl Display` instead.")
+}
+
 fn prepare_input_code(
     attributes:&str,
     body: &str,
     output_tp: &str,
-    include_token_stream_impl: bool,
+    output_wrapper: Option<&str>,
+    prelude_config: &PreludeConfig,
     paths: &Paths
 ) -> String {
     let body_esc: String = body.chars().flat_map(|c| c.escape_default()).collect();
-    let prelude = gen_prelude(include_token_stream_impl, paths);
+    let prelude = gen_prelude(prelude_config, paths);
+    // The `impl Trait` type is checked by passing the result through a function returning it.
+    let result = match output_wrapper {
+        None => format!("let result: {output_tp} = {{
+                {body}
+            }};"),
+        Some(wrapper) => format!("fn check_output(output: {output_tp}) -> {output_tp} {{ output }}
+            let result = {GEN_MOD}::{wrapper}(check_output({{
+                {body}
+            }}));"),
+    };
     format!("
         {attributes}
         {prelude}
@@ -1121,130 +3711,1076 @@ fn prepare_input_code(
 
         fn main() {{
             let mut __output_buffer__ = String::new();
-            let result: {output_tp} = {{
-                {body}
-            }};
+            {result}
             __output_buffer__.push_str(&{GEN_MOD}::code_from_output(result));
-            println!(\"{{}}\", {GEN_MOD}::prefix_lines_with_output(&__output_buffer__));
+            {GEN_MOD}::print_output(&__output_buffer__);
         }}",
     )
 }
 
-fn parse_output(output: &str) -> String {
-    let mut code = String::new();
-    for line in output.split('\n') {
-        let line_trimmed = line.trim();
-        if let Some(stripped) = line_trimmed.strip_prefix(OUTPUT_PREFIX) {
-            code.push_str(stripped);
-            code.push('\n');
-        } else if let Some(stripped) = line_trimmed.strip_prefix(Level::WARNING_PREFIX) {
-            print_warning!("{}", stripped);
-        } else if let Some(stripped) = line_trimmed.strip_prefix(Level::ERROR_PREFIX) {
-            print_error!("{}", stripped);
-        } else if !line_trimmed.is_empty() {
-            println!("{line}");
-        }
-    }
-    code
+/// Breaks lines longer than `width` after the commas at the lowest delimiter depth of the line.
+/// Continuation lines are indented by four spaces more than the original line. This is a
+/// best-effort readability improvement, not a formatter. Literals and comments are tracked across
+/// lines, so only the whitespace between tokens is changed. Lines starting inside a multi-line
+/// literal or comment are kept as they are.
+fn wrap_long_lines(code: &str, width: usize) -> String {
+    let mut state = LexState::Code;
+    let lines = code.lines().map(|line| {
+        let start_state = state;
+        let commas = scan_line(line, &mut state);
+        if start_state == LexState::Code { wrap_line(line, width, &commas) } else { line.into() }
+    });
+    lines.collect::<Vec<_>>().join("\n") + "\n"
 }
 
-#[derive(Clone, Copy, Debug)]
-struct MacroOptions {
-    pub cache: bool,
-    pub content_base_name: bool,
+/// Lexical context at a line break.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LexState {
+    Code,
+    Str,
+    /// Raw string closed by a quote and the given number of hashes.
+    RawStr(usize),
+    /// Block comment of the given nesting depth.
+    Comment(usize),
 }
 
-impl Default for MacroOptions {
-    fn default() -> Self {
-        Self {
-            cache: true,
-            content_base_name: false,
+/// Returns the byte offsets after the commas in the code of the line with their delimiter depth,
+/// and updates the state to the one at the end of the line.
+fn scan_line(line: &str, state: &mut LexState) -> Vec<(usize, usize)> {
+    let mut commas = vec![];
+    let mut depth = 0_usize;
+    let mut chars = line.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let rest = &line[index + c.len_utf8()..];
+        match *state {
+            LexState::Str => match c {
+                '\\' => { chars.next(); }
+                '"' => *state = LexState::Code,
+                _ => {}
+            },
+            LexState::RawStr(hashes) => {
+                if c == '"' && rest.starts_with(&"#".repeat(hashes)) {
+                    for _ in 0..hashes { chars.next(); }
+                    *state = LexState::Code;
+                }
+            }
+            LexState::Comment(level) => {
+                if c == '*' && rest.starts_with('/') {
+                    chars.next();
+                    *state = if level > 1 { LexState::Comment(level - 1) } else { LexState::Code };
+                } else if c == '/' && rest.starts_with('*') {
+                    chars.next();
+                    *state = LexState::Comment(level + 1);
+                }
+            }
+            LexState::Code => match c {
+                '"' => *state = LexState::Str,
+                // Breaking a line comment would turn its rest into code.
+                '/' if rest.starts_with('/') => break,
+                '/' if rest.starts_with('*') => {
+                    chars.next();
+                    *state = LexState::Comment(1);
+                }
+                'r' | 'b'
+                if !line[..index].ends_with(|t: char| t.is_alphanumeric() || t == '_') => {
+                    let raw = if c == 'b' { rest.strip_prefix('r') } else { Some(rest) };
+                    let Some(raw) = raw else { continue };
+                    let hashes = raw.len() - raw.trim_start_matches('#').len();
+                    if raw[hashes..].starts_with('"') {
+                        // Skips the `r` of `br`, the hashes, and the opening quote.
+                        for _ in 0..hashes + usize::from(c == 'b') + 1 { chars.next(); }
+                        *state = LexState::RawStr(hashes);
+                    }
+                }
+                '\'' if rest.starts_with('\\') => {
+                    while let Some((_, c)) = chars.next() {
+                        if c == '\\' { chars.next(); } else if c == '\'' { break }
+                    }
+                }
+                '\'' if rest.chars().nth(1) == Some('\'') => { chars.nth(1); }
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                ',' => commas.push((index + 1, depth)),
+                _ => {}
+            },
         }
     }
+    commas
 }
 
-impl syn::parse::Parse for MacroOptions {
-    fn parse(input: syn::parse::ParseStream) -> Result<Self, syn::Error> {
-        let mut options = MacroOptions::default();
-        while !input.is_empty() {
-            let ident: syn::Ident = input.parse()?;
-            let _eq_token: syn::Token![=] = input.parse()?;
-            if ident == "cache" {
-                let bool_lit: syn::LitBool = input.parse()?;
-                options.cache = bool_lit.value;
-            } else if ident == "content_base_name" {
-                let bool_lit: syn::LitBool = input.parse()?;
-                options.content_base_name = bool_lit.value;
-            } else {
-                return Err(syn::Error::new(ident.span(), "unknown attribute"));
-            }
-            if input.peek(syn::Token![,]) {
-                let _comma: syn::Token![,] = input.parse()?;
-            }
+fn wrap_line(line: &str, width: usize, commas: &[(usize, usize)]) -> String {
+    if line.len() <= width {
+        return line.to_string();
+    }
+    let Some(min_depth) = commas.iter().map(|(_, depth)| *depth).min() else {
+        return line.to_string();
+    };
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let mut segments = vec![];
+    let mut start = 0;
+    for (end, _) in commas.iter().filter(|(_, depth)| *depth == min_depth) {
+        segments.push(line[start..*end].trim());
+        start = *end;
+    }
+    // The last segment can end inside a multi-line literal, so its end is kept.
+    segments.push(line[start..].trim_start());
+    let mut lines = vec![];
+    let mut current = indent.to_string();
+    for segment in segments.into_iter().filter(|t| !t.is_empty()) {
+        if current.trim().is_empty() {
+            current.push_str(segment);
+        } else if current.len() + 1 + segment.len() > width {
+            lines.push(std::mem::take(&mut current));
+            current = format!("{indent}    {segment}");
+        } else {
+            current.push(' ');
+            current.push_str(segment);
         }
-        Ok(options)
     }
+    lines.push(current);
+    lines.join("\n")
 }
 
-// =====================
-// === Eval Function ===
-// =====================
+/// Writes the output code to the file set by the `dump` option, and to the directory set by the
+/// [`DUMP_DIR_ENV`] variable. Files in the directory are named after the macro and the hash of its
+/// input, so outputs of different macros and invocations don't overwrite each other. Failures are
+/// reported as warnings, as they don't affect the generated code.
+fn dump_output(code: &str, options: &MacroOptions, paths: &Paths, macro_name: &str) {
+    let manifest_dir = paths.manifest_dir().unwrap_or_default();
+    let file = options.dump.as_ref().map(|path| manifest_dir.join(path));
+    let dir_file = std::env::var_os(DUMP_DIR_ENV).filter(|t| !t.is_empty()).map(|dir| {
+        PathBuf::from(dir).join(format!("{macro_name}_{:016x}.rs", paths.invocation_seed))
+    });
+    let code = format!("{}\n", code.trim());
+    for file in file.into_iter().chain(dir_file) {
+        let result = path::parent(&file)
+            .and_then(|dir| fs::create_dir_all(dir).context("Failed to create the directory."))
+            .and_then(|_| fs::write(&file, &code).context("Failed to write the file."));
+        if let Err(err) = result {
+            let message = err.message_with_cause();
+            print_warning!("Failed to dump the output to '{}': {message}", file.display());
+        }
+    }
+}
 
-#[proc_macro_attribute]
-pub fn eval_function(
-    attr: proc_macro::TokenStream,
-    item: proc_macro::TokenStream
-) -> proc_macro::TokenStream {
-    // SAFETY: Used to panic in case of error.
-    #[allow(clippy::unwrap_used)]
-    eval_function_impl(attr, item).unwrap_or_compile_error().into()
+/// If the `coverage` option is set, writes the output code to `coverage/<macro>_<hash>.rs` in the
+/// output root and returns an `include!` of it, so
+/// the spans of the generated items point to a real file. The file is named after the hash of its
+/// content, so it's rewritten only when the output changes. On stable, the code is returned as is.
+fn coverage_output(code: String, options: &MacroOptions, macro_name: &str) -> Result<String> {
+    if !options.coverage {
+        return Ok(code)
+    }
+    if !cfg!(nightly) {
+        print_warning!("The 'coverage' option requires the nightly channel and is ignored.");
+        return Ok(code)
+    }
+    if code.contains("$crate") {
+        print_warning!("The 'coverage' option is ignored, as the output code uses '$crate'.");
+        return Ok(code)
+    }
+    let content = if options.expression { format!("{{\n{}\n}}\n", code.trim()) }
+        else { format!("{}\n", code.trim()) };
+    let dir = Paths::get_output_root()?.join("coverage");
+    let file = dir.join(format!("{macro_name}_{:016x}.rs", fnv_hash(&content)));
+    if fs::read_to_string(&file).ok().as_deref() != Some(content.as_str()) {
+        fs::create_dir_all(&dir).context("Failed to create the coverage directory.")?;
+        fs::write(&file, &content).context("Failed to write the coverage file.")?;
+    }
+    let semicolon = if options.expression { "" } else { ";" };
+    Ok(format!("include!({:?}){semicolon}", file.display().to_string()))
 }
 
+/// Hashes the tokens of the code, so the hash changes only if the code changes, not its formatting
+/// or comments. The tokens are printed by [`SpanMap`], as the format of `TokenStream::to_string`
+/// may change between `proc-macro2` versions. If the code can't be tokenized, its text is hashed
+/// instead.
+fn output_hash(code: &str) -> String {
+    let hash = match code.parse::<TokenStream>() {
+        Ok(tokens) => fnv_hash(&SpanMap::new(&tokens).code),
+        Err(_) => fnv_hash(code),
+    };
+    format!("{hash:016x}")
+}
 
-fn eval_function_impl(
-    attr: proc_macro::TokenStream,
-    item: proc_macro::TokenStream
-) -> Result<TokenStream> {
-    let options = syn::parse::<MacroOptions>(attr)?;
-    let start_time = get_current_time();
-    let timer = std::time::Instant::now();
+/// Formats the code with `rustfmt`. Returns [`None`] if `rustfmt` is not installed or fails, for
+/// example, because the code is an expression, in which case the code is used unformatted. The code
+/// is parsed with the given edition, as identifiers like `gen` are keywords in newer editions.
+fn format_code(code: &str, edition: &str) -> Option<String> {
+    use std::process::Stdio;
+    let mut child = Command::new("rustfmt")
+        .args(["--edition", edition])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .inspect_err(|err| debug!("Failed to run rustfmt: {err}"))
+        .ok()?;
+    child.stdin.take()?.write_all(code.as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        debug!("rustfmt failed: {}", String::from_utf8_lossy(&output.stderr));
+        return None
+    }
+    String::from_utf8(output.stdout).ok()
+}
 
-    let input_fn_ast = syn::parse::<syn::ItemFn>(item)?;
-    let name = &input_fn_ast.sig.ident.to_string();
-    let body_ast = &input_fn_ast.block.stmts;
-    let output_tp = &input_fn_ast.sig.output;
-    let input_str = expand_output_macro(expand_quote_macro(quote!{ #(#body_ast)* })).to_string();
-    let paths = Paths::new(options, name, &input_str)?;
+/// Validates the evaluated output code and applies the output options to it.
+fn process_output(
+    output: String,
+    options: &MacroOptions,
+    cfg: &CargoConfig,
+    paths: &Paths,
+    macro_name: &str
+) -> Result<String> {
+    let mut output_code = output;
+    validate_output_code(&output_code)?;
+    validate_output_syntax(&output_code, macro_name)?;
+    if let Some(expected) = options.expect_items {
+        check_item_count(&output_code, expected)?;
+    }
+    if let Some(width) = options.max_line_width {
+        output_code = wrap_long_lines(&output_code, width);
+    }
+    if options.rustfmt {
+        // The output is compiled as part of the calling crate, so it is formatted for its edition.
+        let edition = cfg.caller_edition.as_deref().or(cfg.edition.as_deref());
+        let edition = edition.unwrap_or(DEFAULT_EDITION);
+        output_code = format_code(&output_code, edition).unwrap_or(output_code);
+    }
+    if options.clippy && !is_rust_analyzer() {
+        lint_output(&output_code, options, paths)?;
+    }
+    if cfg.no_std && !is_rust_analyzer() {
+        check_no_std_output(&output_code, options, paths)?;
+    }
+    Ok(output_code)
+}
 
-    let mut cfg = CargoConfig::default();
-    if let Some(path) = &paths.cargo_toml_path {
-        cfg.fill_from_cargo_toml(path)?;
+/// Lints the output code with `cargo clippy`, configured with the lints of the calling crate.
+/// Clippy warnings are printed, and lints denied by the configuration fail the macro. The scratch
+/// project is placed next to the generated one. It does not depend on the calling crate, so if
+/// the output refers to its items, the code does not type-check and only the lints not requiring
+/// type information are reported.
+fn lint_output(code: &str, options: &MacroOptions, paths: &Paths) -> Result {
+    let mut cfg = CargoConfig {
+        name: Some(format!("{}_clippy", paths.eval_project_name())),
+        ..Default::default()
+    };
+    if let Some(config_paths) = paths.manifest_dir().map(|dir| find_cargo_configs(&dir)) {
+        cfg.fill_from_cargo_toml(&config_paths?)?;
     }
-    let attributes = cfg.extract_inline_attributes(input_fn_ast.attrs)?;
-    let include_token_stream_impl = cfg.contains_dependency("proc-macro2");
-    let output_tp_str = match output_tp {
-        syn::ReturnType::Default => "()".to_string(),
-        syn::ReturnType::Type(_, tp) => quote!{#tp}.to_string(),
+    // The build dependencies of the calling crate are not the dependencies of the output.
+    cfg.dependencies.clear();
+    let lib = scratch_lib(code, options);
+    let project_dir = paths.output_dir.with_extension("clippy");
+    let issues = with_project_lock(&project_dir, || run_clippy(&project_dir, &cfg, &lib));
+    if paths.one_shot_output_dir {
+        fs::remove_dir_all(&project_dir).ok();
+    }
+    let (errors, warnings): (Vec<_>, Vec<_>) = issues?.into_iter()
+        .partition(|issue| matches!(issue.level, Level::Error));
+    for warning in warnings {
+        print_warning!("Clippy found an issue in the generated code.\n{}", warning.message);
+    }
+    if !errors.is_empty() {
+        let errors = errors.iter().map(|issue| issue.message.as_str()).collect::<Vec<_>>();
+        return err!("Clippy found denied lints in the generated code.\n{}", errors.join("\n"))
+    }
+    Ok(())
+}
+
+/// The `src/lib.rs` of a scratch project checking the output code. Expressions are wrapped in a
+/// function.
+fn scratch_lib(code: &str, options: &MacroOptions) -> String {
+    // The `$crate` tokens are replaced after the output is parsed, see `replace_crate_sentinel`.
+    let code = code.replace("$crate", "crate");
+    if options.expression {
+        format!("pub fn crabtime_output() {{\n    let _ = {{\n{code}\n    }};\n}}\n")
+    } else {
+        format!("{code}\n")
+    }
+}
+
+/// Runs `cargo clippy` in the project and returns the clippy lints.
+fn run_clippy(project_dir: &Path, cfg: &CargoConfig, lib: &str) -> Result<Vec<Issue>> {
+    let action = "lint the generated code with clippy";
+    let messages = run_scratch_project(project_dir, cfg, lib, "clippy", action)?;
+    Ok(clippy_diagnostics(&messages))
+}
+
+/// Checks the output code in a scratch `no_std` library with the `alloc` crate, placed next to the
+/// generated project. Like in [`lint_output`], the output can't refer to the items of the calling
+/// crate, so only the errors caused by the use of `std` are reported.
+fn check_no_std_output(code: &str, options: &MacroOptions, paths: &Paths) -> Result {
+    let cfg = CargoConfig {
+        name: Some(format!("{}_no_std", paths.eval_project_name())),
+        ..Default::default()
     };
-    let input_code = prepare_input_code(
-        &attributes,
-        &input_str,
-        &output_tp_str,
-        include_token_stream_impl,
-        &paths
+    let lib = format!("#![no_std]\nextern crate alloc;\n{}", scratch_lib(code, options));
+    let project_dir = paths.output_dir.with_extension("no_std");
+    let action = "check the generated code in a no_std crate";
+    let messages = with_project_lock(&project_dir, ||
+        run_scratch_project(&project_dir, &cfg, &lib, "check", action)
     );
-    debug!("INPUT CODE: {input_code}");
-    let mut output_dir_str = String::new();
-    let (output, was_cached) = paths.with_output_dir(|output_dir| {
-        debug!("OUTPUT_DIR: {:?}", output_dir);
-        output_dir_str = output_dir.to_string_lossy().to_string();
-        let was_cached = create_project_skeleton(output_dir, cfg, &input_code)?;
-        let output = run_cargo_project(output_dir)?;
-        Ok((output, was_cached))
-    })?;
-    let output_code = parse_output(&output);
-    let duration = format_duration(timer.elapsed());
-    let options_doc = format!("{options:#?}").replace("\n", "\n/// ");
+    if paths.one_shot_output_dir {
+        fs::remove_dir_all(&project_dir).ok();
+    }
+    let errors = no_std_errors(&messages?);
+    if !errors.is_empty() {
+        return err!("The generated code is not compatible with no_std.\n{}", errors.join("\n"))
+    }
+    Ok(())
+}
+
+/// Macros of `std` which are not available in `core` and `alloc`.
+const STD_ONLY_MACROS: &[&str] = &["print", "println", "eprint", "eprintln", "dbg"];
+
+/// Returns the rendered errors caused by the use of `std` paths or macros. Other errors, like the
+/// ones caused by items missing in the scratch project, are skipped.
+fn no_std_errors(messages: &[serde_json::Value]) -> Vec<String> {
+    let is_std_error = |text: &str| text.contains("`std`") || STD_ONLY_MACROS.iter()
+        .any(|name| text.starts_with(&format!("cannot find macro `{name}`")));
+    messages.iter()
+        .filter(|message| message["reason"] == "compiler-message")
+        .map(|message| &message["message"])
+        .filter(|message| message["level"] == "error")
+        .filter(|message| message["message"].as_str().is_some_and(is_std_error))
+        .filter_map(|message| Some(message["rendered"].as_str()?.trim_end().to_string()))
+        .collect()
+}
+
+/// Runs the Cargo subcommand in the scratch project, writing its files first, and returns the
+/// JSON messages. Files are written only if they changed, so Cargo replays the cached diagnostics
+/// of unchanged code instead of checking it again.
+fn run_scratch_project(
+    project_dir: &Path,
+    cfg: &CargoConfig,
+    lib: &str,
+    subcommand: &str,
+    action: &str,
+) -> Result<Vec<serde_json::Value>> {
+    let src_dir = project_dir.join("src");
+    fs::create_dir_all(&src_dir).context("Failed to create src directory.")?;
+    let files = [
+        (project_dir.join("Cargo.toml"), cfg.print()),
+        (src_dir.join("lib.rs"), lib.to_string()),
+    ];
+    for (file, content) in files {
+        if fs::read_to_string(&file).ok().as_deref() != Some(content.as_str()) {
+            fs::write(&file, content).context("Failed to write the scratch project.")?;
+        }
+    }
+    let host_target = &HostRustc::get()?.target;
+    // In case the project uses .cargo/config.toml, we need to explicitly revert target to native.
+    let (mut command, cargo) = cargo_command();
+    let output = command
+        .args([subcommand, "--lib", "--message-format=json", "--target", host_target])
+        .current_dir(project_dir)
+        .env("CARGO_TARGET_DIR", project_dir.join("target"))
+        .env_remove("RUSTC_WORKSPACE_WRAPPER")
+        .env_remove("CLIPPY_ARGS")
+        .output()
+        .map_err(|err| spawn_error(&cargo, err))
+        .context(|| error!("Failed to execute cargo {subcommand}"))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let messages = stdout.lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .collect::<Vec<_>>();
+    let compiled = messages.iter().any(|message| message["reason"] == "compiler-message"
+        || message["reason"] == "compiler-artifact");
+    if !output.status.success() && !compiled {
+        let details = build_failure_details(&messages, &String::from_utf8_lossy(&output.stderr));
+        return err!("Failed to {action}.\n{details}")
+    }
+    Ok(messages)
+}
+
+/// Returns the clippy lints reported in the compiler messages. Other diagnostics, like errors
+/// caused by items missing in the scratch project, are skipped.
+fn clippy_diagnostics(messages: &[serde_json::Value]) -> Vec<Issue> {
+    messages.iter()
+        .filter(|message| message["reason"] == "compiler-message")
+        .map(|message| &message["message"])
+        .filter(|message| {
+            message["code"]["code"].as_str().is_some_and(|code| code.starts_with("clippy::"))
+        })
+        .filter_map(|message| {
+            let rendered = message["rendered"].as_str()?.trim_end();
+            let is_error = message["level"].as_str()?.starts_with("error");
+            Some(if is_error { error!("{rendered}") } else { warning!("{rendered}") })
+        })
+        .collect()
+}
+
+/// Checks that the output code parses as items, statements, or an expression. Otherwise, the error
+/// shows the lines around the problem, and the full code is written to a temporary file.
+fn validate_output_syntax(code: &str, macro_name: &str) -> Result {
+    // The `$crate` tokens are replaced after the output is parsed, see `replace_crate_sentinel`.
+    let code_to_parse = code.replace("$crate", "crate");
+    let Err(error) = syn::parse_file(&code_to_parse) else { return Ok(()) };
+    // Macros can be also expanded to statements, expressions, types, and patterns.
+    let is_block = syn::parse_str::<syn::Block>(&format!("{{{code_to_parse}\n}}")).is_ok();
+    let is_type = syn::parse_str::<syn::Type>(&code_to_parse).is_ok();
+    let is_pat = syn::parse::Parser::parse_str(syn::Pat::parse_multi, &code_to_parse).is_ok();
+    if is_block || is_type || is_pat {
+        return Ok(())
+    }
+    let location = error.span().start();
+    let snippet = code_snippet(code, location.line, location.column);
+    let file_name = format!("crabtime_{macro_name}_{:016x}.rs", fnv_hash(code));
+    let file = std::env::temp_dir().join(file_name);
+    let dump = match fs::write(&file, code) {
+        Ok(()) => format!("\nThe full generated code was written to '{}'.", file.display()),
+        Err(_) => String::new(),
+    };
+    err!(
+        "The generated code is not valid Rust: {error} (line {}, column {}).\n{snippet}{dump}",
+        location.line,
+        location.column + 1
+    )
+}
+
+/// Formats the lines around the given 1-based line, with the column marked by a caret.
+fn code_snippet(code: &str, line: usize, column: usize) -> String {
+    let lines = code.lines().collect::<Vec<_>>();
+    let first = line.saturating_sub(3);
+    let last = (line + 2).min(lines.len());
+    let width = last.to_string().len();
+    let mut out = String::new();
+    for (ix, text) in lines.iter().enumerate().take(last).skip(first) {
+        out.push_str(&format!("{:>width$} | {text}\n", ix + 1));
+        if ix + 1 == line {
+            out.push_str(&format!("{:>width$} | {}^\n", "", " ".repeat(column)));
+        }
+    }
+    out
+}
+
+/// Checks that the output code consists of the expected number of top-level items.
+fn check_item_count(code: &str, expected: usize) -> Result {
+    // The `$crate` tokens are replaced after the output is parsed, see `replace_crate_sentinel`.
+    let file = syn::parse_file(&code.replace("$crate", "crate")).map_err(|e| error!(
+        "The `expect_items` option requires the generated code to consist of items, but it \
+        could not be parsed: {e}."
+    ))?;
+    let count = file.items.len();
+    if count != expected {
+        return err!("Expected the macro to generate {expected} items, but it generated {count}.")
+    }
+    Ok(())
+}
+
+/// Checks that the generated code does not contain control characters other than the whitespace
+/// allowed in Rust sources. Otherwise, tokenizing it fails with an error which does not point to
+/// the problem.
+fn validate_output_code(code: &str) -> Result {
+    let allowed = ['\t', '\u{0B}', '\u{0C}', '\r', '\u{85}'];
+    for (line_ix, line) in code.lines().enumerate() {
+        let invalid = line.chars().enumerate()
+            .find(|(_, c)| c.is_control() && !allowed.contains(c));
+        if let Some((column_ix, c)) = invalid {
+            let line_nr = line_ix + 1;
+            let column = column_ix + 1;
+            return err!(
+                "The generated code contains a control character {c:?} at line {line_nr}, column \
+                {column}:\n{}", line.escape_debug()
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Parses the output of the evaluated code line by line, as it is printed. Warnings and logs are
+/// reported immediately. Errors are collected, as they describe the failure if the evaluation
+/// fails, and are reported otherwise.
+#[derive(Default)]
+struct OutputParser {
+    /// Name of the macro, if the generated lines are displayed as they arrive.
+    verbose: Option<String>,
+    /// Sentinel delimiting the generated code. If not set, the code lines are prefixed.
+    sentinel: Option<String>,
+    /// Name of the section of the sentinel block being parsed. The default section has an empty
+    /// name.
+    sentinel_section: Option<String>,
+    code: String,
+    /// Code of the named output sections, in the order of their first lines.
+    sections: Vec<(String, String)>,
+    /// Identifies the invocation in the store of the `output_once` keys. If not set, the code of
+    /// `output_once` is always emitted.
+    once_owner: Option<String>,
+    code_lines: usize,
+    /// Files read by the evaluated code, tracked after the output is processed.
+    tracked_files: Vec<String>,
+    errors: Vec<String>,
+    invalid_utf8: Option<Issue>,
+}
+
+impl OutputParser {
+    fn push_line(&mut self, line: &[u8]) {
+        if self.invalid_utf8.is_some() {
+            return
+        }
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line,
+            Err(error) => {
+                let line_str = String::from_utf8_lossy(line);
+                let is_prefixed = line_str.trim().starts_with(OUTPUT_PREFIX)
+                    || line_str.trim().starts_with(OUTPUT_SECTION_PREFIX);
+                let is_code = self.sentinel_section.is_some()
+                    || self.sentinel.is_none() && is_prefixed;
+                let location = if is_code {
+                    format!("line {} of the generated code", self.code_lines + 1)
+                } else {
+                    "a log line".to_string()
+                };
+                let byte = error.valid_up_to() + 1;
+                self.invalid_utf8 = Some(error!(
+                    "The macro output contains an invalid UTF-8 sequence in {location} (byte \
+                    {byte}):\n{}", line_str.trim_end()
+                ));
+                return
+            }
+        };
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line_trimmed = line.trim();
+        if let Some(sentinel) = &self.sentinel {
+            // Lines between the sentinels are taken verbatim, and the prefixes are not used.
+            let marker = line_trimmed.strip_prefix(sentinel.as_str()).map(str::trim);
+            if let Some(section) = self.sentinel_section.take() {
+                if marker != Some("END") {
+                    self.push_code_line(&section, line);
+                    self.sentinel_section = Some(section);
+                }
+                return
+            } else if let Some(section) = marker.and_then(|t| t.strip_prefix("BEGIN")) {
+                self.sentinel_section = Some(section.trim().to_string());
+                return
+            }
+        }
+        let section_line = line_trimmed.strip_prefix(OUTPUT_SECTION_PREFIX)
+            .and_then(|t| t.split_once(']'));
+        let track_line = self.strip_protocol_prefix(line_trimmed, TRACK_PREFIX, "TRACK");
+        let warning_line =
+            self.strip_protocol_prefix(line_trimmed, Level::WARNING_PREFIX, "WARNING");
+        let error_line = self.strip_protocol_prefix(line_trimmed, Level::ERROR_PREFIX, "ERROR");
+        if self.sentinel.is_none()
+        && let Some(stripped) = line_trimmed.strip_prefix(OUTPUT_PREFIX) {
+            self.push_code_line("", stripped);
+        } else if self.sentinel.is_none()
+        && let Some((section, stripped)) = section_line {
+            self.push_code_line(section, stripped);
+        } else if let Some(stripped) = track_line {
+            let path = stripped.trim();
+            if Path::new(path).is_dir() {
+                // Directories can't be included. They are tracked on nightly only, so the macro is
+                // expanded again when their entries change.
+                #[cfg(nightly)]
+                proc_macro::tracked::path(path);
+            } else {
+                self.tracked_files.push(path.to_string());
+            }
+        } else if let Some(stripped) = warning_line {
+            print_warning!("{}", stripped);
+        } else if let Some(stripped) = error_line {
+            self.errors.push(stripped.trim().to_string());
+        } else if !line_trimmed.is_empty() {
+            println!("{line}");
+        }
+    }
+
+    /// Strips the prefix of a warning, error, or tracking line. In the sentinel mode, these lines
+    /// start with the sentinel followed by the marker instead.
+    fn strip_protocol_prefix<'t>(&self, line: &'t str, prefix: &str, marker: &str)
+    -> Option<&'t str> {
+        match &self.sentinel {
+            Some(sentinel) =>
+                line.strip_prefix(sentinel.as_str())?.trim_start().strip_prefix(marker),
+            None => line.strip_prefix(prefix),
+        }
+    }
+
+    fn push_code_line(&mut self, section: &str, line: &str) {
+        if let Some(name) = &self.verbose {
+            println!("[{name}] {}", line.strip_prefix(' ').unwrap_or(line));
+        }
+        let code = if section.is_empty() {
+            self.code_lines += 1;
+            &mut self.code
+        } else {
+            let index = match self.sections.iter().position(|(name, _)| name == section) {
+                Some(index) => index,
+                None => {
+                    self.sections.push((section.to_string(), String::new()));
+                    self.sections.len() - 1
+                }
+            };
+            &mut self.sections[index].1
+        };
+        code.push_str(line);
+        code.push('\n');
+    }
+
+    /// Reports the collected errors and returns the generated code. The named sections are placed
+    /// in modules of the same name before the code of the default section. The `tests` module is
+    /// compiled in tests only.
+    fn finish(self) -> Result<String> {
+        if let Some(issue) = self.invalid_utf8 {
+            return Err(issue)
+        }
+        for error in &self.errors {
+            print_error!("{}", error);
+        }
+        let has_once = self.sections.iter().any(|(name, _)| name.starts_with(ONCE_SECTION_PREFIX));
+        let once_store = self.once_owner.filter(|_| has_once).map(OnceStore::new).transpose()
+            .context("Failed to create the store of the `output_once` keys.")?;
+        let mut code = String::new();
+        for (name, section) in &self.sections {
+            if let Some(key) = name.strip_prefix(ONCE_SECTION_PREFIX) {
+                let claimed = once_store.as_ref().map_or(Ok(true), |store| store.claim(key))
+                    .context("Failed to access the store of the `output_once` keys.")?;
+                if claimed {
+                    code.push_str(section);
+                }
+                continue
+            }
+            if syn::parse_str::<syn::Ident>(name).is_err() {
+                return err!(
+                    "Output section name '{name}' is not a valid module name. Use identifiers, \
+                    like `tests`."
+                )
+            }
+            let cfg = if name == "tests" { "#[cfg(test)]\n" } else { "" };
+            code.push_str(&format!(
+                "{cfg}mod {name} {{\n#[allow(unused_imports)]\nuse super::*;\n{section}}}\n"
+            ));
+        }
+        code.push_str(&self.code);
+        Ok(code)
+    }
+}
+
+// =================
+// === OnceStore ===
+// =================
+
+/// Keys of the code emitted by `output_once`, shared by all invocations in the compilation of a
+/// crate. Each compilation runs in a separate process, so the keys are stored in a directory named
+/// after its id. Concurrent compilations of the same crate, like of its library and tests, don't
+/// share the keys, as each of them needs the code. Each process holds the lock of the `.lock` file
+/// next to its directory until it exits. Directories of finished compilations, whose lock files are
+/// not locked anymore, are removed when a new one starts, and all of them are removed by
+/// `cargo clean`.
+struct OnceStore {
+    dir: PathBuf,
+    /// Identifies the invocation, so it emits the code again when it is expanded again in the
+    /// same process, like by Rust Analyzer.
+    owner: String,
+}
+
+impl OnceStore {
+    fn new(owner: String) -> Result<Self> {
+        let crate_name = std::env::var("CARGO_CRATE_NAME").unwrap_or_default();
+        let crate_dir = Paths::get_output_root()?.join("once").join(crate_name);
+        let dir = crate_dir.join(std::process::id().to_string());
+        // Lock files of the directories of this process, held until it exits.
+        static LOCKS: std::sync::Mutex<Vec<(PathBuf, File)>> = std::sync::Mutex::new(vec![]);
+        let mut locks = LOCKS.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        if !locks.iter().any(|(locked, _)| *locked == dir) {
+            // A directory left by a finished process with the same id is removed here as well.
+            Self::remove_finished(&crate_dir);
+            fs::create_dir_all(&crate_dir)?;
+            let lock_file = File::create(dir.with_extension("lock"))?;
+            lock_file.lock()?;
+            fs::create_dir_all(&dir)?;
+            locks.push((dir.clone(), lock_file));
+        }
+        Ok(Self { dir, owner })
+    }
+
+    /// Removes the directories of compilations whose lock files are not locked anymore, as their
+    /// processes have exited. The directory is created after its lock file is locked.
+    fn remove_finished(crate_dir: &Path) {
+        let Ok(entries) = fs::read_dir(crate_dir) else { return };
+        for dir in entries.flatten().map(|entry| entry.path()).filter(|path| path.is_dir()) {
+            let lock_path = dir.with_extension("lock");
+            let lock_file = File::open(&lock_path).ok();
+            if lock_file.as_ref().is_some_and(|lock_file| lock_file.try_lock().is_err()) {
+                continue
+            }
+            fs::remove_dir_all(&dir).ok();
+            fs::remove_file(&lock_path).ok();
+        }
+    }
+
+    /// Checks whether the invocation should emit the code of the key. Only the first invocation
+    /// claiming the key does.
+    fn claim(&self, key: &str) -> Result<bool> {
+        let file = self.dir.join(format!("{:016x}", Paths::input_hash(key)));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&file) {
+            Ok(mut file) => {
+                file.write_all(self.owner.as_bytes())?;
+                Ok(true)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists =>
+                Ok(fs::read_to_string(&file)? == self.owner),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// How the macro is evaluated when the expansion engine is Rust Analyzer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum RustAnalyzerMode {
+    /// Evaluate the macro, just like the compiler does.
+    #[default]
+    Eval,
+    /// Reuse the output of the last evaluation of the same input, or evaluate the macro.
+    Cached,
+    /// Reuse the output of the last evaluation of the same input, or emit a placeholder.
+    Placeholder,
+}
+
+impl RustAnalyzerMode {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "eval" => Some(Self::Eval),
+            "cached" => Some(Self::Cached),
+            "placeholder" => Some(Self::Placeholder),
+            _ => None,
+        }
+    }
+
+    fn from_env() -> Option<Self> {
+        std::env::var(RUST_ANALYZER_MODE_ENV).ok().and_then(|name| Self::from_name(name.trim()))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MacroOptions {
+    pub cache: bool,
+    pub content_base_name: bool,
+    pub rust_analyzer: RustAnalyzerMode,
+    pub lib_target: bool,
+    /// Set by `crabtime::expression`, as the placeholder output depends on the macro position.
+    pub expression: bool,
+    /// If set, longer lines of the output code are wrapped after top-level commas.
+    pub max_line_width: Option<usize>,
+    /// Visibility of the generated macro, emitted as `#vis use name;` after the definition.
+    pub visibility: Option<String>,
+    /// Module path the generated macro is expected to be reachable at.
+    pub export: Option<String>,
+    /// If set, the output code is written to this file, relative to the crate's manifest directory.
+    pub dump: Option<String>,
+    /// If set, the output code is formatted with `rustfmt`, if it is installed.
+    pub rustfmt: bool,
+    /// If set, the number of top-level items in the output code is checked.
+    pub expect_items: Option<usize>,
+    /// If set, the output code starts with a comment containing its whitespace-insensitive hash.
+    pub hash: bool,
+    /// If set, the output code is linted by `cargo clippy` with the lints of the calling crate.
+    pub clippy: bool,
+    /// If set, the body is emitted in a test module under Rust Analyzer to enable type hints.
+    pub ra_hints: bool,
+    /// If set, the generated code is delimited by randomized sentinel lines instead of line
+    /// prefixes, so the logs of the evaluated code can't be mistaken for it.
+    pub sentinel_output: bool,
+    /// If set, the `Cargo.lock` of the generated project is stored per dependency set and reused,
+    /// so the versions of the dependencies don't drift between builds.
+    pub lock_file: bool,
+    /// If set, the output code is written to a file and included from it, so coverage tools can
+    /// map the generated items to its lines. Requires the nightly channel.
+    pub coverage: bool,
+    /// If set, the binary of a cached project is run directly if its inputs did not change. Files
+    /// and environment variables read by the body are not a part of the inputs.
+    pub reuse_binary: bool,
+    /// Set by `crabtime::function` to the `$crate` of the generated `macro_rules!`.
+    pub crate_path: Option<proc_macro2::Ident>,
+}
+
+impl Default for MacroOptions {
+    fn default() -> Self {
+        Self {
+            cache: cfg!(nightly),
+            content_base_name: false,
+            rust_analyzer: RustAnalyzerMode::from_env().unwrap_or_default(),
+            lib_target: false,
+            expression: false,
+            max_line_width: None,
+            visibility: None,
+            export: None,
+            dump: None,
+            rustfmt: false,
+            expect_items: None,
+            hash: false,
+            ra_hints: true,
+            sentinel_output: false,
+            lock_file: false,
+            coverage: false,
+            reuse_binary: false,
+            clippy: false,
+            crate_path: None,
+        }
+    }
+}
+
+impl syn::parse::Parse for MacroOptions {
+    fn parse(input: syn::parse::ParseStream) -> Result<Self, syn::Error> {
+        let mut options = MacroOptions::default();
+        while !input.is_empty() {
+            let ident: syn::Ident = input.parse()?;
+            let _eq_token: syn::Token![=] = input.parse()?;
+            if ident == "cache" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.cache = bool_lit.value;
+            } else if ident == "content_base_name" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.content_base_name = bool_lit.value;
+            } else if ident == "rust_analyzer" {
+                let mode: syn::Ident = input.parse()?;
+                options.rust_analyzer = RustAnalyzerMode::from_name(&mode.to_string()).ok_or_else(||
+                    syn::Error::new(mode.span(), "expected one of: eval, cached, placeholder")
+                )?;
+            } else if ident == "lib_target" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.lib_target = bool_lit.value;
+            } else if ident == "expression" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.expression = bool_lit.value;
+            } else if ident == "max_line_width" {
+                let int_lit: syn::LitInt = input.parse()?;
+                options.max_line_width = Some(int_lit.base10_parse()?);
+            } else if ident == "visibility" {
+                let str_lit: syn::LitStr = input.parse()?;
+                str_lit.parse::<syn::Visibility>()?;
+                options.visibility = Some(str_lit.value());
+            } else if ident == "export" {
+                let str_lit: syn::LitStr = input.parse()?;
+                str_lit.parse::<syn::Path>()?;
+                options.export = Some(str_lit.value());
+            } else if ident == "dump" {
+                let str_lit: syn::LitStr = input.parse()?;
+                options.dump = Some(str_lit.value());
+            } else if ident == "rustfmt" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.rustfmt = bool_lit.value;
+            } else if ident == "expect_items" {
+                let int_lit: syn::LitInt = input.parse()?;
+                options.expect_items = Some(int_lit.base10_parse()?);
+            } else if ident == "hash" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.hash = bool_lit.value;
+            } else if ident == "clippy" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.clippy = bool_lit.value;
+            } else if ident == "ra_hints" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.ra_hints = bool_lit.value;
+            } else if ident == "sentinel_output" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.sentinel_output = bool_lit.value;
+            } else if ident == "lock_file" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.lock_file = bool_lit.value;
+            } else if ident == "coverage" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.coverage = bool_lit.value;
+            } else if ident == "reuse_binary" {
+                let bool_lit: syn::LitBool = input.parse()?;
+                options.reuse_binary = bool_lit.value;
+            } else if ident == "crate_path" {
+                options.crate_path = Some(syn::ext::IdentExt::parse_any(input)?);
+            } else {
+                return Err(syn::Error::new(ident.span(), "unknown attribute"));
+            }
+            if input.peek(syn::Token![,]) {
+                let _comma: syn::Token![,] = input.parse()?;
+            }
+        }
+        Ok(options)
+    }
+}
+
+// =====================
+// === Eval Function ===
+// =====================
+
+#[proc_macro_attribute]
+pub fn eval_function(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> proc_macro::TokenStream {
+    // SAFETY: Used to panic in case of error.
+    #[allow(clippy::unwrap_used)]
+    eval_function_impl(attr, item).unwrap_or_compile_error().into()
+}
+
+
+fn eval_function_impl(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> Result<TokenStream> {
+    let options = syn::parse::<MacroOptions>(attr)?;
+    let start_time = get_current_time();
+    let timer = std::time::Instant::now();
+
+    let input_fn_ast = syn::parse::<syn::ItemFn>(item)?;
+    let name = &input_fn_ast.sig.ident.to_string();
+    let body_ast = &input_fn_ast.block.stmts;
+    let output_tp = &input_fn_ast.sig.output;
+    let body = expand_named_args_macro(quote!{ #(#body_ast)* })?;
+    let (body, input_spans) = expand_input_spans_macro(body);
+    let body = expand_output_macro(expand_quote_macro(expand_derive_output_macro(body)));
+    let body_span_map = SpanMap::new(&body);
+    let input_str = body_span_map.code.clone();
+    // Attributes, like the profile or dependencies, are a part of the project name on stable.
+    let attrs = &input_fn_ast.attrs;
+    let paths = Paths::new(options.clone(), name, &format!("{}{input_str}", quote!{ #(#attrs)* }))?;
+
+    let mut cfg = CargoConfig {
+        lib_target: options.lib_target,
+        lock_file: options.lock_file,
+        name: Some(paths.eval_project_name()),
+        ..Default::default()
+    };
+    if let Some(path) = &paths.cargo_toml_path {
+        cfg.fill_from_cargo_toml(path)?;
+    }
+    let attributes = cfg.extract_inline_attributes(input_fn_ast.attrs)?;
+    cfg.check_min_edition()?;
+    let timeout = cfg.eval_timeout()?;
+    if let Some(manifest_dir) = paths.manifest_dir() {
+        cfg.resolve_dependency_paths(&manifest_dir);
+    }
+    let returns_token_stream = match output_tp {
+        syn::ReturnType::Default => false,
+        syn::ReturnType::Type(_, tp) => is_token_stream_type(tp),
+    };
+    if returns_token_stream {
+        cfg.ensure_dependency(PROC_MACRO2, DEFAULT_PROC_MACRO2_VERSION);
+    }
+    let token_stream_crate = cfg.find_dependency(PROC_MACRO2).map(Dependency::crate_name);
+    if contains_ident(&body, "include_json") {
+        cfg.ensure_dependency(SERDE_JSON, DEFAULT_SERDE_JSON_VERSION);
+    }
+    let json_crate = cfg.find_dependency(SERDE_JSON).map(Dependency::crate_name);
+    if contains_ident(&body, "require_dependency") {
+        cfg.ensure_dependency(TOML, DEFAULT_TOML_VERSION);
+        cfg.ensure_dependency(SEMVER, DEFAULT_SEMVER_VERSION);
+    }
+    let manifest_crates = cfg.find_dependency(TOML).map(Dependency::crate_name)
+        .zip(cfg.find_dependency(SEMVER).map(Dependency::crate_name));
+    let output_tp_str = match output_tp {
+        syn::ReturnType::Default => "()".to_string(),
+        // A bare `TokenStream` is not in scope of the generated `main` function.
+        syn::ReturnType::Type(_, tp) => match &token_stream_crate {
+            Some(crate_name) if quote!{#tp}.to_string() == "TokenStream" =>
+                format!("{crate_name}::TokenStream"),
+            _ => quote!{#tp}.to_string(),
+        }
+    };
+    let output_wrapper = match output_tp {
+        syn::ReturnType::Default => None,
+        syn::ReturnType::Type(_, tp) => impl_output_wrapper(tp)?,
+    };
+    let prelude_config = PreludeConfig {
+        token_stream_crate: token_stream_crate.as_deref(),
+        json_crate: json_crate.as_deref(),
+        manifest_crates: manifest_crates.as_ref().map(|(t, s)| (t.as_str(), s.as_str())),
+        input_spans: &input_spans,
+        sentinel_output: options.sentinel_output,
+    };
+    let input_code = prepare_input_code(
+        &attributes,
+        &input_str,
+        &output_tp_str,
+        output_wrapper,
+        &prelude_config,
+        &paths
+    );
+    debug!("INPUT CODE: {input_code}");
+    let mut output_dir_str = String::new();
+    let cache_key = output_cache_key(&cfg, &attributes, &output_tp_str, &input_str);
+    let output_cache_file = Paths::output_cache_file(&cache_key)?;
+    let rust_analyzer_mode = is_rust_analyzer().then_some(options.rust_analyzer);
+    let cached_output = rust_analyzer_mode
+        .filter(|mode| *mode != RustAnalyzerMode::Eval)
+        .and_then(|_| fs::read_to_string(&output_cache_file).ok());
+    let (output_code, was_cached) = if let Some(output_code) = cached_output {
+        output_dir_str = output_cache_file.to_string_lossy().to_string();
+        (output_code, true)
+    } else if rust_analyzer_mode == Some(RustAnalyzerMode::Placeholder) {
+        // Diverging expression type-checks in any expression position.
+        let placeholder = if options.expression { "::core::unreachable!()" } else { "" };
+        (placeholder.to_string(), false)
+    } else {
+        let sentinel = options.sentinel_output;
+        // Invocations with the same input in the same file are not distinguished.
+        let once_owner = format!("{}:{name}:{:016x}", call_site_file(), paths.invocation_seed);
+        let (output, was_cached) = paths.with_output_dir(|output_dir| {
+            debug!("OUTPUT_DIR: {:?}", output_dir);
+            output_dir_str = output_dir.to_string_lossy().to_string();
+            let hash = CacheEntry::hash(&cfg, &input_code);
+            // Cargo would rebuild the project if sources of a local dependency changed.
+            let has_path_deps = cfg.dependencies.iter().any(Dependency::is_path);
+            let reusable = options.reuse_binary && !paths.one_shot_output_dir && !has_path_deps;
+            if reusable && let Some(entry) = CacheEntry::read(output_dir, &hash) {
+                let executable = &entry.executable;
+                let owner = once_owner.clone();
+                let output =
+                    run_executable(output_dir, executable, timeout, name, sentinel, owner)?;
+                return Ok((output, true))
+            }
+            let host_target = &HostRustc::get()?.target;
+            let toolchain = eval_toolchain(&cfg)?;
+            let was_cached = create_project_skeleton(output_dir, &cfg, &input_code)?;
+            let target_dir = Paths::target_dir()?;
+            let executable = build_cargo_project(
+                output_dir,
+                &target_dir,
+                host_target,
+                cfg.release,
+                toolchain,
+                &input_code,
+                &body_span_map
+            )?;
+            if cfg.lock_file {
+                store_lock_file(output_dir, &cfg)?;
+            }
+            let owner = once_owner.clone();
+            let output = run_executable(output_dir, &executable, timeout, name, sentinel, owner)?;
+            if reusable {
+                CacheEntry { hash, executable }.write(output_dir, &cfg)?;
+            }
+            Ok((output, was_cached))
+        })?;
+        let output_code = process_output(output.code, &options, &cfg, &paths, name)?;
+        let output_code = track_files(output_code, &output.tracked_files, &options);
+        // The compiler does not know the mode of Rust Analyzer, which is often set only in its
+        // environment, so it always stores the output for it.
+        if rust_analyzer_mode != Some(RustAnalyzerMode::Eval) {
+            let cache_dir = path::parent(&output_cache_file)?;
+            fs::create_dir_all(cache_dir).context("Failed to create output cache directory.")?;
+            let is_new_entry = !output_cache_file.exists();
+            fs::write(&output_cache_file, &output_code).context("Failed to cache macro output.")?;
+            // Rewriting an entry only refreshes its modification time, so the cache can grow only
+            // when a new entry is created.
+            if is_new_entry {
+                prune_output_cache(cache_dir, OUTPUT_CACHE_LIMIT);
+            }
+        }
+        (output_code, was_cached)
+    };
+    let output_hash = options.hash.then(|| output_hash(&output_code));
+    let output_code = match &output_hash {
+        Some(hash) => format!("{HASH_COMMENT_PREFIX} {hash}\n{}", output_code.trim_start()),
+        None => output_code,
+    };
+    dump_output(&output_code, &options, &paths, name);
+    let output_code = coverage_output(output_code, &options, name)?;
+    let duration = format_duration(timer.elapsed());
+    let options_doc = format!("{options:#?}").replace("\n", "\n/// ");
+    let hash_doc = output_hash.map(|hash| format!("/// Output Hash: {hash}")).unwrap_or_default();
     let macro_code = format!("
         /// # Compilation Stats
         /// Start: {start_time}
@@ -1252,19 +4788,71 @@ fn eval_function_impl(
         /// Cached: {was_cached}
         /// Output Dir: {output_dir_str}
         /// Macro Options: {options_doc}
+        {hash_doc}
         #[cfg(any())]
         const _: () = ();
         {output_code}
     ");
 
     debug!("BODY: {macro_code}");
-    let out: TokenStream = macro_code.parse()
+    let mut out: TokenStream = macro_code.parse()
         .map_err(|err| error!("{err:?}"))
         .context("Failed to parse generated code.")?;
+    if let Some(crate_path) = &options.crate_path {
+        out = replace_crate_sentinel(out, crate_path);
+    }
     debug!("OUTPUT: {out} ");
     Ok(out)
 }
 
+/// Removes the least recently written outputs, keeping at most `limit` of them. Outputs of the
+/// previous versions of macro bodies are never read again, so the cache would grow forever.
+fn prune_output_cache(cache_dir: &Path, limit: usize) {
+    let mut files = path::read_dir(cache_dir).into_iter()
+        .filter(|file| file.extension().is_some_and(|ext| ext == "rs"))
+        .map(|file| (fs::metadata(&file).and_then(|t| t.modified()).ok(), file))
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, file) in files.iter().skip(limit) {
+        path::remove(file);
+    }
+}
+
+/// Key of the output cache. It skips the prelude, so Rust Analyzer can reuse outputs of the
+/// compiler evaluations, and the project name, which contains the process id for one-shot projects.
+fn output_cache_key(cfg: &CargoConfig, attributes: &str, output_tp: &str, body: &str) -> String {
+    let dependencies = cfg.dependencies.iter().map(Dependency::to_config_string);
+    format!("{}\n{attributes}{output_tp}{body}", dependencies.collect::<Vec<_>>().join("\n"))
+}
+
+// ================
+// === Eval Str ===
+// ================
+
+#[proc_macro]
+pub fn eval_str(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    eval_str_impl(input.into()).unwrap_or_compile_error().into()
+}
+
+/// Evaluates code given as a string literal, like `crabtime::eval!` evaluates tokens.
+fn eval_str_impl(input: TokenStream) -> Result<TokenStream> {
+    let lit = syn::parse2::<syn::LitStr>(input)
+        .map_err(|e| error!(e.span(), "Expected a string literal with the code to evaluate."))?;
+    let body = lit.value().parse::<TokenStream>().map_err(|e|
+        error!(lit.span(), "The code passed to `eval_str!` is not valid Rust: {e}")
+    )?;
+    let item = quote! {
+        #[crabtime::eval_function(content_base_name=true)]
+        fn run() -> _ {
+            #body
+        }
+    };
+    syn::parse2::<syn::ItemFn>(item.clone()).map_err(|e|
+        error!(lit.span(), "The code passed to `eval_str!` is not valid Rust: {e}")
+    )?;
+    Ok(quote! {{ #item }})
+}
+
 // ================
 // === Function ===
 // ================
@@ -1299,6 +4887,119 @@ pub fn expression(
     function_impl(attr, item, true).unwrap_or_compile_error().into()
 }
 
+/// Defines a derive-like macro. It is a function-like macro receiving the annotated item, applied
+/// with `#[crabtime::derive_with(...)]`. It is re-exported as `crabtime::derive`, as a proc macro
+/// named `derive` would shadow the built-in attribute in this crate.
+#[proc_macro_attribute]
+pub fn derive_macro(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> proc_macro::TokenStream {
+    derive_macro_impl(attr, item).unwrap_or_compile_error().into()
+}
+
+fn derive_macro_impl(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> Result<TokenStream> {
+    let input_fn_ast = syn::parse::<syn::ItemFn>(item.clone())?;
+    let inputs = &input_fn_ast.sig.inputs;
+    let token_stream_arg = inputs.first().and_then(parse_args_for_token_stream);
+    let takes_item = inputs.len() == 1 && token_stream_arg.is_some();
+    if !takes_item {
+        let span = syn::spanned::Spanned::span(&input_fn_ast.sig);
+        return err!(span,
+            "Derive macros should have exactly one argument, `input: TokenStream`, which receives \
+            the annotated item."
+        )
+    }
+    function_impl(attr, item, false)
+}
+
+/// Applies derive macros defined with `#[crabtime::derive]` to a struct, enum, or union. The item
+/// is kept unchanged, and the output of every macro is placed after it.
+#[proc_macro_attribute]
+pub fn derive_with(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> proc_macro::TokenStream {
+    derive_with_impl(attr.into(), item.into()).unwrap_or_compile_error().into()
+}
+
+fn derive_with_impl(attr: TokenStream, item: TokenStream) -> Result<TokenStream> {
+    use syn::parse::Parser;
+    let parser = syn::punctuated::Punctuated::<syn::Path, syn::Token![,]>::parse_terminated;
+    let macros = parser.parse2(attr)?;
+    if macros.is_empty() {
+        return err!(
+            "Expected names of the derive macros, like \
+            `#[crabtime::derive_with(my_derive)]`."
+        )
+    }
+    syn::parse2::<syn::DeriveInput>(item.clone()).map_err(|e| error!(e.span(),
+        "The `derive_with` attribute can only be applied to structs, enums, and unions."
+    ))?;
+    let calls = macros.iter().map(|path| quote! { #path! { #item } });
+    Ok(quote! {
+        #item
+        #(#calls)*
+    })
+}
+
+/// Defines an attribute-like macro. It is a function-like macro receiving the attribute arguments
+/// and the annotated item, applied with `#[crabtime::apply(...)]`.
+#[proc_macro_attribute]
+pub fn attribute(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> proc_macro::TokenStream {
+    attribute_impl(attr, item).unwrap_or_compile_error().into()
+}
+
+fn attribute_impl(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> Result<TokenStream> {
+    let input_fn_ast = syn::parse::<syn::ItemFn>(item.clone())?;
+    if parse_args_for_attribute(&input_fn_ast.sig.inputs).is_none() {
+        let span = syn::spanned::Spanned::span(&input_fn_ast.sig);
+        return err!(span,
+            "Attribute macros should have exactly two arguments, `attr: TokenStream` and \
+            `item: TokenStream`, which receive the attribute arguments and the annotated item."
+        )
+    }
+    function_impl(attr, item, false)
+}
+
+/// Applies an attribute macro defined with `#[crabtime::attribute]`, like
+/// `#[crabtime::apply(my_attr(args))]`. The annotated item is replaced with the macro output.
+#[proc_macro_attribute]
+pub fn apply(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> proc_macro::TokenStream {
+    apply_impl(attr.into(), item.into()).unwrap_or_compile_error().into()
+}
+
+fn apply_impl(attr: TokenStream, item: TokenStream) -> Result<TokenStream> {
+    let parser = |input: syn::parse::ParseStream| {
+        let path = input.call(syn::Path::parse_mod_style)?;
+        let args = if input.is_empty() {
+            TokenStream::new()
+        } else {
+            let content;
+            syn::parenthesized!(content in input);
+            content.parse::<TokenStream>()?
+        };
+        Ok((path, args))
+    };
+    let (path, args) = syn::parse::Parser::parse2(parser, attr).map_err(|e| error!(e.span(),
+        "Expected an attribute macro with optional arguments, like \
+        `#[crabtime::apply(my_attr(args))]`."
+    ))?;
+    Ok(quote! { #path! { [#args] #item } })
+}
+
 fn split_attrs(attrs: Vec<syn::Attribute>) -> (Vec<syn::Attribute>, Vec<syn::Attribute>) {
     let (outer, inner): (Vec<_>, Vec<_>) = attrs.into_iter().partition(|attr| {
         matches!(attr.style, syn::AttrStyle::Outer)
@@ -1306,12 +5007,34 @@ fn split_attrs(attrs: Vec<syn::Attribute>) -> (Vec<syn::Attribute>, Vec<syn::Att
     (outer, inner)
 }
 
+/// Options passed by Crabtime to `eval_function`, which users can't set.
+const INTERNAL_OPTIONS: &[&str] = &["expression", "crate_path"];
+
+fn reject_internal_options(attr: &TokenStream) -> Result<()> {
+    let mut is_key = true;
+    for token in attr.clone() {
+        match &token {
+            TokenTree::Ident(ident)
+            if is_key && INTERNAL_OPTIONS.iter().any(|name| ident == name) =>
+                return Err(syn::Error::new(ident.span(), "unknown attribute").into()),
+            TokenTree::Punct(punct) if punct.as_char() == ',' => is_key = true,
+            _ => is_key = false,
+        }
+    }
+    Ok(())
+}
+
 fn function_impl(
     attr_in: proc_macro::TokenStream,
     item: proc_macro::TokenStream,
     extra_braces: bool,
 ) -> Result<TokenStream> {
-    let attr: TokenStream = attr_in.into();
+    let mut attr: TokenStream = attr_in.into();
+    reject_internal_options(&attr)?;
+    if extra_braces {
+        attr = quote!{ expression = true, #attr };
+    }
+    let options = syn::parse2::<MacroOptions>(attr.clone())?;
     let input_fn_ast = syn::parse::<syn::ItemFn>(item)?;
     let name = &input_fn_ast.sig.ident;
     let args_ast = &input_fn_ast.sig.inputs;
@@ -1324,13 +5047,16 @@ fn function_impl(
     let body = quote!{ #(#body_ast)* };
     let input_str = expand_expand_macro(quote!{ #(#body_ast)* });
 
-    // Check if the expansion engine is Rust Analyzer. If so, we need to generate
-    // a code which looks like a function to enable type hints.
-    let program_name = std::env::current_exe()?
-        .file_name()
-        .map_or_else(|| "unknown".into(), |s| s.to_string_lossy().into_owned());
-    let rust_analyzer_hints = if program_name.contains("rust-analyzer") {
+    let attrs_vec = input_fn_ast.attrs;
+    let (outer_attrs_vec, inner_attrs_vec) = split_attrs(attrs_vec);
+    let cfg_attrs_vec = outer_attrs_vec.iter()
+        .filter(|attr| attr.path().is_ident("cfg") || attr.path().is_ident("cfg_attr"));
+
+    // If the expansion engine is Rust Analyzer, we need to generate a code which looks like a
+    // function to enable type hints. It is gated by the same `cfg` as the macro definition.
+    let rust_analyzer_hints = if is_rust_analyzer() && options.ra_hints {
         quote! {
+            #(#cfg_attrs_vec)*
             mod __rust_analyzer_hints__ {
                 #[test]
                 #[ignore]
@@ -1343,39 +5069,104 @@ fn function_impl(
         quote! {}
     };
 
-    let attrs_vec = input_fn_ast.attrs;
-    let (outer_attrs_vec, inner_attrs_vec) = split_attrs(attrs_vec);
-
+    let macro_export = outer_attrs_vec.iter().any(|attr| attr.path().is_ident("macro_export"));
+    let exports = macro_exports(name, &options, macro_export)?;
     let outer_attrs = quote!{ #(#outer_attrs_vec)* };
     let inner_attrs = quote!{ #(#inner_attrs_vec)* };
-    let mut out = quote! {
-        {
-            #[crabtime::eval_function(#attr)]
-            fn #name() #output_tp {
-                #inner_attrs
-                #args_setup
-                #args_code
-                #input_str
+    let eval_block = |args_code: TokenStream| {
+        let out = quote! {
+            {
+                #[crabtime::eval_function(crate_path = $crate, #attr)]
+                fn #name() #output_tp {
+                    #inner_attrs
+                    #args_code
+                    #input_str
+                }
             }
+        };
+        if extra_braces { quote! { { #out } } } else { out }
+    };
+    let out = eval_block(quote! { #args_setup #args_code });
+    // The named arguments arm goes first, as `name = value` is also a valid expression argument.
+    let named_arm = match &args {
+        Args::Pattern { named: Some(named), .. } => {
+            let out = eval_block(quote! {
+                crabtime::named_args!([$($__name = $__value),*] #named);
+            });
+            quote! { ($($__name:ident = $__value:expr),+ $(,)?) => #out; }
         }
+        _ => quote! {},
     };
-    if extra_braces {
-        out = quote! {
-            { #out }
-        };
-    }
-    out = quote! {
+    let default_arms = match &args {
+        Args::Pattern { defaults, .. } => defaults.iter().map(|(pattern, code)| {
+            let out = eval_block(code.clone());
+            quote! { (#pattern) => #out; }
+        }).collect(),
+        _ => vec![],
+    };
+    let out = quote! {
         #rust_analyzer_hints
 
         #outer_attrs
         macro_rules! #name {
+            #named_arm
             (#args_pattern) => #out;
+            #(#default_arms)*
         }
+        #exports
     };
     debug!("OUT: {out}");
     Ok(out)
 }
 
+/// Generates the `use` statements for the `visibility` and `export` options. The `#[macro_export]`
+/// attribute wins, as the macro is then already visible at the crate root, and referring to
+/// macro-expanded exported macros by path is not allowed.
+fn macro_exports(
+    name: &syn::Ident,
+    options: &MacroOptions,
+    macro_export: bool,
+) -> Result<TokenStream> {
+    if options.visibility.is_none() && options.export.is_none() {
+        return Ok(quote! {})
+    }
+    if macro_export {
+        print_warning!(
+            "Macro '{name}' is annotated with `#[macro_export]`, so it is visible at the crate \
+            root. The `visibility` and `export` options are ignored."
+        );
+        return Ok(quote! {})
+    }
+    let vis = options.visibility.as_deref().unwrap_or("pub(crate)");
+    let vis = syn::parse_str::<syn::Visibility>(vis)?;
+    if matches!(vis, syn::Visibility::Public(_)) {
+        return err!(
+            "Macros can't be re-exported with `pub` visibility. Use `#[macro_export]` to export \
+            the macro from the crate, or a restricted visibility, like `pub(crate)`."
+        )
+    }
+    let export = options.export.as_deref().map(syn::parse_str::<syn::Path>).transpose()?;
+    let export_check = export.map(|path| quote! {
+        const _: () = {
+            #[allow(unused_imports)]
+            use #path::#name as _;
+        };
+    });
+    Ok(quote! {
+        #[allow(clippy::single_component_path_imports)]
+        #[allow(unused_imports)]
+        #vis use #name;
+        #export_check
+    })
+}
+
+/// Checks if the expansion engine is Rust Analyzer.
+fn is_rust_analyzer() -> bool {
+    std::env::current_exe().ok()
+        .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+        .is_some_and(|name| name.contains("rust-analyzer"))
+}
+
 fn format_duration(duration: std::time::Duration) -> String {
     let total_seconds = duration.as_secs();
     if total_seconds >= 60 {
@@ -1400,3 +5191,1204 @@ fn get_current_time() -> String {
     let seconds = total_seconds % 60;
     format!("{hours:02}:{minutes:02}:{seconds:02} ({milliseconds:03})")
 }
+
+// =============
+// === Tests ===
+// =============
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn print(code: &str) -> String {
+        let tokens: TokenStream = code.parse().unwrap();
+        print_tokens(&tokens)
+    }
+
+    #[test]
+    fn hyphenated_inline_dependencies() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![dependency(proc-macro2 = "1")]
+                #![dependency(serde_json = "1.0")]
+                #![dependency("my-crate" = "0.1")]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        let config = cfg.print();
+        assert!(config.contains("proc-macro2 = \"1\""), "{config}");
+        assert!(config.contains("serde_json = \"1.0\""), "{config}");
+        assert!(config.contains("my-crate = \"0.1\""), "{config}");
+    }
+
+    #[test]
+    fn table_inline_dependencies() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![dependency(serde = { version = "1", features = ["derive"], })]
+                #![dependency(syn = {
+                    version = "2", default-features = false, features = ["full",]
+                })]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        let config: toml::Table = toml::from_str(&cfg.print()).unwrap();
+        let dependencies = &config["dependencies"];
+        assert_eq!(dependencies["serde"]["features"][0].as_str(), Some("derive"));
+        assert_eq!(dependencies["syn"]["default-features"].as_bool(), Some(false));
+        assert_eq!(dependencies["syn"]["features"][0].as_str(), Some("full"));
+    }
+
+    #[test]
+    fn allow_inline_dependencies() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![allow_inline_dependencies]
+                #![dependency(serde = "1")]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert_eq!(cfg.extract_inline_attributes(item.attrs).ok().as_deref(), Some(""));
+        assert!(cfg.contains_dependency("serde"));
+    }
+
+    #[cfg(not(nightly))]
+    #[test]
+    fn first_inline_dependency() {
+        assert!(super::first_inline_dependency("crabtime-test-dep"));
+        assert!(!super::first_inline_dependency("crabtime-test-dep"));
+    }
+
+    #[test]
+    fn lock_file_path() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![dependency(serde = "1")]
+                #![dependency(syn = "2")]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        let path = cfg.lock_file_path().ok();
+        cfg.dependencies.reverse();
+        assert_eq!(cfg.lock_file_path().ok(), path);
+        cfg.dependencies.pop();
+        assert_ne!(cfg.lock_file_path().ok(), path);
+        let file_name = cfg.lock_file_path().ok().and_then(|t| t.file_name().map(|t| t.to_owned()));
+        assert_eq!(file_name.as_deref(), Some("bfb3d3fe0cee969a.lock".as_ref()));
+    }
+
+    #[test]
+    fn rename_lock_root() {
+        let lock = "version = 4\n\n[[package]]\nname = \"eval_1\"\nversion = \"1.0.0\"\n\
+            dependencies = [\n \"syn\",\n]\n\n[[package]]\nname = \"syn\"\n";
+        let stored = super::rename_lock_root(lock, "eval_1", EVAL_PROJECT_NAME);
+        assert!(stored.contains("name = \"eval_project\"\nversion = \"1.0.0\""), "{stored}");
+        assert_eq!(super::rename_lock_root(&stored, EVAL_PROJECT_NAME, "eval_1"), lock);
+    }
+
+    #[test]
+    fn fnv_hash() {
+        assert_eq!(super::fnv_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(super::fnv_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn release_profile() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![profile(release)]
+                #![opt_level(3)]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        assert!(cfg.release);
+        let config = cfg.print();
+        assert!(config.contains("[profile.release]\nopt-level = 3"), "{config}");
+
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![profile(fast)]
+            }
+        };
+        let error = CargoConfig::default().extract_inline_attributes(item.attrs)
+            .err().map(|e| e.message).unwrap_or_default();
+        assert!(error.contains("Unsupported profile 'fast'"), "{error}");
+    }
+
+    #[test]
+    fn eval_profile() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![opt_level(1)]
+                #![eval_profile(opt_level = 3, codegen_units = 1)]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        let config = cfg.print();
+        assert!(config.contains("[profile.dev]\nopt-level = 3\ncodegen-units = 1"), "{config}");
+        assert_eq!(config.matches("opt-level").count(), 1, "{config}");
+
+        let config = CargoConfig::default().print();
+        assert!(!config.contains("[profile."), "{config}");
+    }
+
+    #[test]
+    fn min_edition() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![min_edition(2021)]
+            }
+        };
+        let mut cfg = CargoConfig { caller_edition: Some("2018".into()), ..Default::default() };
+        assert!(cfg.extract_inline_attributes(item.attrs.clone()).is_ok());
+        let error = cfg.check_min_edition().err().map(|e| e.message).unwrap_or_default();
+        assert!(error.contains("requiring Rust edition 2021"), "{error}");
+
+        let mut cfg = CargoConfig { caller_edition: Some("2024".into()), ..Default::default() };
+        assert!(cfg.extract_inline_attributes(item.attrs.clone()).is_ok());
+        assert!(cfg.check_min_edition().is_ok());
+
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        assert!(cfg.check_min_edition().is_ok());
+
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![min_edition(2020)]
+            }
+        };
+        let error = CargoConfig::default().extract_inline_attributes(item.attrs)
+            .err().map(|e| e.message).unwrap_or_default();
+        assert!(error.contains("Unsupported edition '2020'"), "{error}");
+    }
+
+    #[test]
+    fn timeout() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("15"), Some(Duration::from_secs(15)));
+        assert_eq!(parse_duration("1h"), None);
+        assert_eq!(parse_duration("s"), None);
+
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![timeout(10s)]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        assert_eq!(cfg.eval_timeout().ok().flatten(), Some(Duration::from_secs(10)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_kills_subprocesses() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "sleep 10 & sleep 10"]);
+        let timer = std::time::Instant::now();
+        let output = output_with_timeout(command, Some(Duration::from_millis(200)), |_| {});
+        assert!(output.is_ok_and(|t| t.is_none()));
+        assert!(timer.elapsed() < Duration::from_secs(5));
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo done"]);
+        let mut lines = vec![];
+        let output = output_with_timeout(command, Some(Duration::from_secs(10)), |line| {
+            lines.push(line.to_vec());
+        });
+        assert!(output.is_ok_and(|t| t.is_some_and(|(status, _)| status.success())));
+        assert_eq!(lines, [b"done\n".to_vec()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_lines_are_streamed() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo first; sleep 1; echo second"]);
+        let timer = std::time::Instant::now();
+        let mut arrivals = vec![];
+        let output = output_with_timeout(command, None, |_| arrivals.push(timer.elapsed()));
+        assert!(output.is_ok_and(|t| t.is_some()));
+        assert_eq!(arrivals.len(), 2);
+        assert!(arrivals[0] < Duration::from_millis(900), "{arrivals:?}");
+        assert!(arrivals[1] >= Duration::from_secs(1), "{arrivals:?}");
+    }
+
+    #[test]
+    fn eval_str() {
+        let output = eval_str_impl(quote!{ "let x = 1; x + 1" }).map(|t| t.to_string());
+        assert!(output.is_ok_and(|t| t.contains("fn run () -> _ { let x = 1 ; x + 1 }")));
+        let error = |input| eval_str_impl(input).err().map(|e| e.message).unwrap_or_default();
+        assert!(error(quote!{ "let x = (1;" }).contains("is not valid Rust"));
+        assert!(error(quote!{ "let x = ;" }).contains("is not valid Rust"));
+        assert!(error(quote!{ 42 }).contains("Expected a string literal"));
+    }
+
+    #[test]
+    fn derive_with() {
+        let item = quote! { #[derive(Debug)] struct Point { x: f32 } };
+        let output = derive_with_impl(quote!{ gen_describe, a::gen_size }, item.clone())
+            .map(|t| t.to_string());
+        let expected = quote! {
+            #item
+            gen_describe! { #item }
+            a::gen_size! { #item }
+        }.to_string();
+        assert_eq!(output.ok(), Some(expected));
+        let error = |attr, item| derive_with_impl(attr, item).err().map(|e| e.message);
+        assert!(error(quote!{}, item).is_some_and(|t| t.contains("Expected names")));
+        let error = error(quote!{ gen_describe }, quote!{ fn f() {} });
+        assert!(error.is_some_and(|t| t.contains("structs, enums, and unions")));
+    }
+
+    #[test]
+    fn wrap_long_lines() {
+        let code = "    const A: [&str; 4] = [\"a, b\", ',', \"c\", f(1, 2)];\nstruct B;";
+        assert_eq!(super::wrap_long_lines(code, 200), format!("{code}\n"));
+        let wrapped = super::wrap_long_lines(code, 40);
+        assert_eq!(wrapped, [
+            "    const A: [&str; 4] = [\"a, b\", ',',",
+            "        \"c\", f(1, 2)];",
+            "struct B;\n",
+        ].join("\n"));
+        let comment = "f(aaaa, bbbb, cccc); // dddd, eeee, ffff";
+        let wrapped = "f(aaaa, bbbb,\n    cccc); // dddd, eeee, ffff\n";
+        assert_eq!(super::wrap_long_lines(comment, 15), wrapped);
+        let long = "x".repeat(50);
+        assert_eq!(super::wrap_long_lines(&long, 10), format!("{long}\n"));
+        let multi_line = "f(1, 2, \"a,\n    b, c, d\", 3, 4);";
+        assert_eq!(super::wrap_long_lines(multi_line, 8), [
+            "f(1, 2,",
+            "    \"a,",
+            "    b, c, d\", 3, 4);\n",
+        ].join("\n"));
+        let raw = "g(1, br#\"a, \"b\",\n  c\"#); /* x,\n y, z */ h(3, 4);";
+        assert_eq!(super::wrap_long_lines(raw, 8), [
+            "g(1,",
+            "    br#\"a, \"b\",",
+            "  c\"#); /* x,",
+            " y, z */ h(3, 4);\n",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn apply() {
+        let item = quote! { fn f() {} };
+        let output = apply_impl(quote!{ a::add_logging(level = "info") }, item.clone());
+        let expected = quote! { a::add_logging! { [level = "info"] fn f() {} } }.to_string();
+        assert_eq!(output.map(|t| t.to_string()).ok(), Some(expected));
+        let output = apply_impl(quote!{ add_logging }, item.clone());
+        let expected = quote! { add_logging! { [] fn f() {} } }.to_string();
+        assert_eq!(output.map(|t| t.to_string()).ok(), Some(expected));
+        assert!(apply_impl(quote!{ add_logging[level] }, item).is_err());
+
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn add_logging(attr: TokenStream, item: TokenStream) {}
+        };
+        let args = parse_args_for_attribute(&item.sig.inputs).map(|t| t.pattern().to_string());
+        assert_eq!(args, Some(quote!{ [$($attr:tt)*] $($item:tt)* }.to_string()));
+        let item: syn::ItemFn = syn::parse_quote! { fn add_logging(item: TokenStream) {} };
+        assert!(parse_args_for_attribute(&item.sig.inputs).is_none());
+    }
+
+    #[test]
+    fn relative_dependency_path() {
+        let spec = "{ path = \"../mylib\" }".to_string();
+        let mut dependency = Dependency::new("mylib".to_string(), spec, None);
+        dependency.resolve_path(Path::new("/projects/app"));
+        let expected = Path::new("/projects/app").join("../mylib").display().to_string();
+        assert_eq!(dependency.tokens_str, format!("{{ path = {expected:?} }}"));
+
+        let spec = "{ git = \"https://github.com/wdanilo/crabtime\" }".to_string();
+        let mut dependency = Dependency::new("crabtime".to_string(), spec.clone(), None);
+        dependency.resolve_path(Path::new("/projects/app"));
+        assert_eq!(dependency.tokens_str, spec);
+    }
+
+    /// Reads the config of the `member` crate of a temporary workspace. Returns the workspace root,
+    /// which is removed before returning.
+    fn fill_from_workspace(name: &str, workspace: &str, member: &str) -> (CargoConfig, PathBuf) {
+        let pid = std::process::id();
+        let root = std::env::temp_dir().join(format!("crabtime-{name}-{pid}"));
+        let member_dir = root.join("member");
+        fs::create_dir_all(&member_dir).unwrap();
+        fs::write(root.join("Cargo.toml"), workspace).unwrap();
+        fs::write(member_dir.join("Cargo.toml"), member).unwrap();
+        let paths = find_cargo_configs(&member_dir);
+        let mut cfg = CargoConfig::default();
+        let result = paths.map(|paths| cfg.fill_from_cargo_toml(&paths));
+        fs::remove_dir_all(&root).unwrap();
+        assert!(matches!(result, Ok(Ok(()))));
+        (cfg, root)
+    }
+
+    #[test]
+    fn workspace_dependencies() {
+        let (cfg, root) = fill_from_workspace("dependencies", r#"
+            [workspace]
+            members = ["member"]
+
+            [workspace.dependencies]
+            anyhow = "1.0"
+            serde = { version = "1", features = ["derive"] }
+            my-lib = { path = "my-lib" }
+        "#, r#"
+            [package]
+            name = "member"
+            edition = "2021"
+
+            [build-dependencies]
+            anyhow.workspace = true
+            serde = { workspace = true, features = ["rc"], optional = true }
+            my-lib = { workspace = true }
+        "#);
+        let spec = |name: &str| cfg.find_dependency(name).unwrap().tokens_str.clone();
+        assert_eq!(spec("anyhow"), r#"{ version = "1.0" }"#);
+        assert_eq!(
+            spec("serde"),
+            r#"{ features = ["derive", "rc"], optional = true, version = "1" }"#
+        );
+        let my_lib_path = root.join("my-lib").display().to_string();
+        assert_eq!(spec("my-lib"), format!("{{ path = {my_lib_path:?} }}"));
+    }
+
+    #[test]
+    fn default_dep_versions() {
+        let (mut cfg, _) = fill_from_workspace("default-dep-versions", r#"
+            [workspace]
+            members = ["member"]
+        "#, r#"
+            [package]
+            name = "member"
+
+            [package.metadata.crabtime.default_dep_versions]
+            proc-macro2 = { version = "=1.0.86", default-features = false }
+        "#);
+        cfg.add_default_dependency(PROC_MACRO2, DEFAULT_PROC_MACRO2_VERSION);
+        cfg.add_default_dependency(SERDE_JSON, DEFAULT_SERDE_JSON_VERSION);
+        cfg.ensure_dependency(PROC_MACRO2, DEFAULT_PROC_MACRO2_VERSION);
+        cfg.ensure_dependency(TOML, DEFAULT_TOML_VERSION);
+        let spec = |name: &str| cfg.find_dependency(name).unwrap().tokens_str.clone();
+        assert_eq!(spec(PROC_MACRO2), r#"{ default-features = false, version = "=1.0.86" }"#);
+        assert_eq!(spec(SERDE_JSON), DEFAULT_SERDE_JSON_VERSION);
+        assert_eq!(spec(TOML), DEFAULT_TOML_VERSION);
+        // The caller does not specify the edition, so Cargo compiles it with the 2015 one.
+        assert_eq!(cfg.edition.as_deref(), Some(DEFAULT_EDITION));
+        assert_eq!(cfg.caller_edition.as_deref(), Some(CARGO_DEFAULT_EDITION));
+    }
+
+    #[test]
+    fn rust_version() {
+        let (cfg, _) = fill_from_workspace("rust-version", r#"
+            [workspace]
+            members = ["member"]
+
+            [workspace.package]
+            edition = "2021"
+            rust-version = "1.74"
+        "#, r#"
+            [package]
+            name = "member"
+            edition.workspace = true
+            rust-version.workspace = true
+        "#);
+        assert_eq!(cfg.edition.as_deref(), Some("2021"));
+        assert_eq!(cfg.rust_version.as_deref(), Some("1.74"));
+        let config: toml::Table = toml::from_str(&cfg.print()).unwrap();
+        assert_eq!(config["package"]["rust-version"].as_str(), Some("1.74"));
+
+        let (cfg, _) = fill_from_workspace("no-rust-version", "[workspace]", r#"
+            [package]
+            name = "member"
+        "#);
+        assert_eq!(cfg.rust_version, None);
+        let config: toml::Table = toml::from_str(&cfg.print()).unwrap();
+        assert!(config["package"].get("rust-version").is_none());
+    }
+
+    #[test]
+    fn crate_lints() {
+        let (cfg, _) = fill_from_workspace("crate-lints", r#"
+            [workspace]
+            members = ["member"]
+
+            [workspace.lints.rust]
+            unsafe_code = "forbid"
+        "#, r#"
+            [package]
+            name = "member"
+
+            [lints.rust]
+            missing_docs = "warn"
+
+            [lints.clippy]
+            all = { level = "deny", priority = -1 }
+        "#);
+        assert_eq!(cfg.lints.rust, r#"missing_docs = "warn""#);
+        assert_eq!(cfg.lints.clippy, r#"all = { level = "deny", priority = -1 }"#);
+    }
+
+    #[test]
+    fn workspace_lints() {
+        let (cfg, _) = fill_from_workspace("workspace-lints", r#"
+            [workspace]
+            members = ["member"]
+
+            [workspace.lints.rust]
+            unsafe_code = "forbid"
+
+            [workspace.lints.clippy]
+            unwrap_used = "warn"
+        "#, r#"
+            [package]
+            name = "member"
+
+            [lints]
+            workspace = true
+        "#);
+        assert_eq!(cfg.lints.rust, r#"unsafe_code = "forbid""#);
+        assert_eq!(cfg.lints.clippy, r#"unwrap_used = "warn""#);
+        let config = cfg.print();
+        assert!(config.contains("[lints.rust]\n            unsafe_code = \"forbid\""), "{config}");
+    }
+
+    #[test]
+    fn output_cache_key() {
+        let cfg = |name: &str| {
+            let mut cfg = CargoConfig { name: Some(name.to_string()), ..Default::default() };
+            cfg.dependencies.push(Dependency::new("syn".into(), "\"2\"".into(), None));
+            cfg
+        };
+        let key = |cfg: &CargoConfig| super::output_cache_key(cfg, "", "()", "let x = 1;");
+        assert_eq!(key(&cfg("eval_1")), key(&cfg("eval_2")));
+        assert_ne!(key(&cfg("eval_1")), key(&CargoConfig::default()));
+    }
+
+    #[test]
+    fn prune_output_cache() {
+        let pid = std::process::id();
+        let cache_dir = std::env::temp_dir().join(format!("crabtime-output-cache-{pid}"));
+        fs::create_dir_all(&cache_dir).unwrap();
+        let now = std::time::SystemTime::now();
+        for (i, name) in ["old", "mid", "new"].iter().enumerate() {
+            let file = File::create(cache_dir.join(format!("{name}.rs"))).unwrap();
+            file.set_modified(now - Duration::from_secs(60 * (3 - i as u64))).unwrap();
+        }
+        super::prune_output_cache(&cache_dir, 2);
+        let mut names = path::read_dir(&cache_dir).iter()
+            .map(|t| t.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        path::remove(&cache_dir);
+        assert_eq!(names, ["mid.rs", "new.rs"]);
+    }
+
+    #[test]
+    fn reject_internal_options() {
+        let check = |attr: TokenStream| super::reject_internal_options(&attr).is_ok();
+        assert!(check(quote!{ rust_analyzer = cached, hash = true }));
+        assert!(!check(quote!{ expression = true }));
+        assert!(!check(quote!{ hash = true, crate_path = foo }));
+    }
+
+    #[test]
+    fn cache_entry() {
+        let pid = std::process::id();
+        let project_dir = std::env::temp_dir().join(format!("crabtime-cache-entry-{pid}"));
+        let executable = project_dir.join(EVAL_PROJECT_NAME);
+        fs::create_dir_all(&project_dir).unwrap();
+        fs::write(&executable, "").unwrap();
+        let mut cfg = CargoConfig::default();
+        let hash = CacheEntry::hash(&cfg, "fn main() {}");
+        let entry = CacheEntry { hash: hash.clone(), executable: executable.clone() };
+        assert!(entry.write(&project_dir, &cfg).is_ok());
+        let read_entry = CacheEntry::read(&project_dir, &hash);
+        cfg.release = true;
+        let release_hash = CacheEntry::hash(&cfg, "fn main() {}");
+        let release_entry = CacheEntry::read(&project_dir, &release_hash);
+        fs::remove_dir_all(&project_dir).unwrap();
+        assert_eq!(read_entry.map(|t| t.executable), Some(executable));
+        assert!(release_entry.is_none());
+    }
+
+    #[test]
+    fn panic_message_of_any_thread() {
+        let message = "thread '<unnamed>' panicked at src/lib.rs:1:1:\nboom";
+        assert_eq!(panic_message(&format!("   Compiling eval_project\n{message}")), Some(message));
+        let message = "thread 'main' (42) panicked at src/main.rs:1:1:\nboom";
+        assert_eq!(panic_message(message), Some(message));
+        assert_eq!(panic_message("error[E0425]: cannot find value"), None);
+        let message = "thread 'main' panicked at src/main.rs:1:1:\nfirst line\nsecond line";
+        let backtrace_note = "note: run with `RUST_BACKTRACE=1` to display a backtrace";
+        assert_eq!(panic_message(&format!("{message}\n{backtrace_note}\n")), Some(message));
+        let backtrace = "stack backtrace:\n   0: rust_begin_unwind";
+        assert_eq!(panic_message(&format!("{message}\n{backtrace}")), Some(message));
+        assert_eq!(panic_backtrace(&format!("{message}\n{backtrace}\n")), Some(backtrace));
+        assert_eq!(panic_backtrace(message), None);
+    }
+
+    #[test]
+    fn missing_tool_error() {
+        let err = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let message = spawn_error("cargo", err).message;
+        assert!(message.contains("requires a working `cargo` at build time"), "{message}");
+        let err = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let message = spawn_error("cargo", err).message;
+        assert!(!message.contains("requires"), "{message}");
+    }
+
+    #[test]
+    fn concurrent_project_access() {
+        let pid = std::process::id();
+        let root = std::env::temp_dir().join(format!("crabtime-lock-{pid}"));
+        let project_dir = root.join("project");
+        let main = "fn main() {}";
+        let threads = (0..2).map(|_| {
+            let project_dir = project_dir.clone();
+            std::thread::spawn(move || with_project_lock(&project_dir, || {
+                create_project_skeleton(&project_dir, &CargoConfig::default(), main)?;
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                let main_exists = project_dir.join("src").join("main.rs").is_file();
+                fs::remove_dir_all(&project_dir)?;
+                Ok(main_exists)
+            }).is_ok_and(|main_exists| main_exists))
+        }).collect::<Vec<_>>();
+        let results = threads.into_iter().map(|t| t.join().unwrap()).collect::<Vec<_>>();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(results, [true, true]);
+    }
+
+    #[test]
+    fn lib_target_layout() {
+        let pid = std::process::id();
+        let project_dir = std::env::temp_dir().join(format!("crabtime-lib-target-{pid}"));
+        let src_files = |lib_target: bool| {
+            let cfg = CargoConfig { lib_target, ..Default::default() };
+            create_project_skeleton(&project_dir, &cfg, "fn main() {}").ok()?;
+            let mut names = path::read_dir(&project_dir.join("src")).iter()
+                .map(|t| t.file_name().unwrap().to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            Some(names)
+        };
+        let files = [src_files(false), src_files(true), src_files(false)];
+        fs::remove_dir_all(&project_dir).unwrap();
+        let only = |name: &str| Some(vec![name.to_string()]);
+        assert_eq!(files, [only("main.rs"), only(LIB_TARGET_FILE), only("main.rs")]);
+    }
+
+    #[test]
+    fn span_map_code() {
+        let code = "fn f<'a>(x: &'a str) -> std::string::String { x.into() } let y = -1;";
+        let tokens: TokenStream = code.parse().unwrap();
+        let span_map = SpanMap::new(&tokens);
+        let reparsed: TokenStream = span_map.code.parse().unwrap();
+        assert_eq!(reparsed.to_string(), tokens.to_string());
+        assert!(span_map.code.contains("std :: string :: String"), "{}", span_map.code);
+        assert!(span_map.code.contains("&'a str"), "{}", span_map.code);
+    }
+
+    #[test]
+    fn compiler_error_mapped_to_body() {
+        let tokens: TokenStream = "let x: u32 = 1;\nlet y: u32 = \"a\";".parse().unwrap();
+        let span_map = SpanMap::new(&tokens);
+        let main = format!("fn main() {{\n    {}\n}}", span_map.code);
+        let column = span_map.code.find("\"a\"").unwrap() + 5;
+        let message = serde_json::json!({
+            "reason": "compiler-message",
+            "message": {
+                "level": "error",
+                "message": "mismatched types",
+                "spans": [{
+                    "is_primary": true,
+                    "line_start": 2,
+                    "column_start": column,
+                    "label": "expected `u32`, found `&str`"
+                }]
+            }
+        });
+        let issue = span_map.map_compiler_error(&message, &main).unwrap();
+        assert_eq!(issue.message, "mismatched types\nexpected `u32`, found `&str`");
+        let start = issue.span.unwrap().start();
+        assert_eq!((start.line, start.column), (2, 13));
+
+        let mut prelude_error = message.clone();
+        prelude_error["message"]["spans"][0]["line_start"] = 1.into();
+        assert!(span_map.map_compiler_error(&prelude_error, &main).is_none());
+    }
+
+    #[test]
+    fn invalid_utf8_in_output() {
+        let mut parser = OutputParser::default();
+        for line in [&b"log\n"[..], b"[OUTPUT] struct A;\n", b"[OUTPUT] struct \xFF;\n"] {
+            parser.push_line(line);
+        }
+        let error = parser.finish().err().map(|e| e.message).unwrap_or_default();
+        assert!(error.contains("line 2 of the generated code"), "{error}");
+    }
+
+    #[test]
+    fn sentinel_output() {
+        let sentinel = "[CRABTIME OUTPUT 1]".to_string();
+        let mut parser = OutputParser { sentinel: Some(sentinel), ..Default::default() };
+        let lines = [
+            "[OUTPUT] log\n",
+            "[ERROR] log\n",
+            "[CRABTIME OUTPUT 1] BEGIN\n",
+            "    struct A;\n",
+            "[OUTPUT] struct B;\n",
+            "[CRABTIME OUTPUT 1] END\n",
+            "[TRACK] log.txt\n",
+            "[CRABTIME OUTPUT 1] TRACK data.txt\n",
+        ];
+        for line in lines {
+            parser.push_line(line.as_bytes());
+        }
+        assert!(parser.errors.is_empty());
+        assert_eq!(parser.tracked_files, ["data.txt"]);
+        let code = parser.finish().ok().unwrap_or_default();
+        assert_eq!(code, "    struct A;\n[OUTPUT] struct B;\n");
+
+        let sentinel = "[CRABTIME OUTPUT 1]".to_string();
+        let mut parser = OutputParser { sentinel: Some(sentinel), ..Default::default() };
+        parser.push_line(b"[CRABTIME OUTPUT 1] ERROR boom\n");
+        assert_eq!(parser.errors, ["boom"]);
+    }
+
+    #[test]
+    fn output_sections() {
+        let mut parser = OutputParser::default();
+        let lines = [
+            "[OUTPUT] struct A;\n",
+            "[OUTPUT:tests] fn t() {}\n",
+            "[OUTPUT:x] struct B;\n",
+        ];
+        for line in lines {
+            parser.push_line(line.as_bytes());
+        }
+        let code = parser.finish().ok().unwrap_or_default();
+        let use_super = "#[allow(unused_imports)]\nuse super::*;";
+        assert_eq!(code, format!(
+            "#[cfg(test)]\nmod tests {{\n{use_super}\n fn t() {{}}\n}}\n\
+            mod x {{\n{use_super}\n struct B;\n}}\n struct A;\n"
+        ));
+
+        let mut parser = OutputParser::default();
+        parser.push_line(b"[OUTPUT:1x] struct C;\n");
+        let error = parser.finish().err().map(|e| e.message).unwrap_or_default();
+        assert!(error.contains("'1x' is not a valid module name"), "{error}");
+    }
+
+    #[cfg(not(nightly))]
+    #[test]
+    fn track_files() {
+        let mut parser = OutputParser::default();
+        parser.push_line(b"[TRACK] data.txt\n");
+        parser.push_line(b"[OUTPUT] struct A;\n");
+        assert_eq!(parser.tracked_files, ["data.txt"]);
+        let files = std::mem::take(&mut parser.tracked_files);
+        let code = parser.finish().ok().unwrap_or_default();
+        let options = MacroOptions::default();
+        let items = super::track_files(code, &files, &options);
+        assert!(items.ends_with("const _: &[u8] = include_bytes!(\"data.txt\");\n"), "{items}");
+        let expression = super::track_files("1 + 2".to_string(), &files, &options);
+        assert_eq!(expression, "1 + 2");
+    }
+
+    #[test]
+    fn output_once() {
+        let dir = std::env::temp_dir().join(format!("crabtime-once-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = |owner: &str| OnceStore { dir: dir.clone(), owner: owner.to_string() };
+        let claims = [
+            store("a").claim("helper").ok(),
+            store("b").claim("helper").ok(),
+            store("a").claim("helper").ok(),
+            store("b").claim("other").ok(),
+        ];
+
+        let crate_dir = dir.join("crate");
+        let lock = |name: &str| {
+            fs::create_dir_all(crate_dir.join(name)).unwrap();
+            File::create(crate_dir.join(name).with_extension("lock")).unwrap()
+        };
+        let running = lock("1");
+        running.lock().unwrap();
+        drop(lock("2"));
+        fs::create_dir_all(crate_dir.join("3")).unwrap();
+        OnceStore::remove_finished(&crate_dir);
+        let remaining = fs::read_dir(&crate_dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<std::collections::BTreeSet<_>>();
+        drop(running);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(claims, [Some(true), Some(false), Some(true), Some(true)]);
+        assert_eq!(remaining, ["1", "1.lock"].map(String::from).into());
+
+        let mut parser = OutputParser::default();
+        parser.push_line(b"[OUTPUT:once:6b6579] struct Once;\n");
+        assert_eq!(parser.finish().ok().as_deref(), Some(" struct Once;\n"));
+    }
+
+    #[test]
+    fn control_character_in_output() {
+        assert!(validate_output_code("struct A;\n\tstruct B;\r\n").is_ok());
+        let error = validate_output_code("struct A;\nstruct \u{7}B;")
+            .err().map(|e| e.message).unwrap_or_default();
+        assert!(error.contains("line 2, column 8"), "{error}");
+    }
+
+    #[test]
+    fn lifetime_in_generics() {
+        let out = print("struct Foo<'a> { x: &'a str }");
+        assert!(out.contains("<'a >"), "{out}");
+        assert!(out.contains("&'a str"), "{out}");
+    }
+
+    #[test]
+    fn lifetime_in_mut_ref() {
+        let out = print("fn f(x: &'a mut T) {}");
+        assert!(out.contains("&'a  mut  T"), "{out}");
+    }
+
+    #[test]
+    fn lifetime_in_hrtb() {
+        let out = print("for<'a> Fn(&'a str)");
+        assert!(out.contains("<'a >"), "{out}");
+        assert!(out.contains("(&'a str)"), "{out}");
+    }
+
+    #[test]
+    fn ra_hints() {
+        let options: MacroOptions = syn::parse_quote!(cache = false);
+        assert!(options.ra_hints);
+        let options: MacroOptions = syn::parse_quote!(ra_hints = false);
+        assert!(!options.ra_hints);
+    }
+
+    #[test]
+    fn coverage() {
+        let options: MacroOptions = syn::parse_quote!(cache = false);
+        assert!(!options.coverage);
+        let options: MacroOptions = syn::parse_quote!(coverage = true);
+        assert!(options.coverage);
+        let code = "struct A(u32);".to_string();
+        let output = coverage_output(code.clone(), &MacroOptions::default(), "gen").ok();
+        assert_eq!(output.as_ref(), Some(&code));
+        let code = "struct B($crate::A);".to_string();
+        let output = coverage_output(code.clone(), &options, "gen").ok();
+        assert_eq!(output.as_ref(), Some(&code));
+    }
+
+    #[test]
+    fn macro_visibility() {
+        let name: syn::Ident = syn::parse_quote!(gen);
+        let options: MacroOptions = syn::parse_quote!(visibility = "pub(super)");
+        let out = macro_exports(&name, &options, false).ok().unwrap_or_default().to_string();
+        assert!(out.contains("pub (super) use gen ;"), "{out}");
+        assert!(macro_exports(&name, &options, true).ok().is_some_and(|out| out.is_empty()));
+
+        let options: MacroOptions = syn::parse_quote!(export = "crate::macros");
+        let out = macro_exports(&name, &options, false).ok().unwrap_or_default().to_string();
+        assert!(out.contains("pub (crate) use gen ;"), "{out}");
+        assert!(out.contains("use crate :: macros :: gen as _ ;"), "{out}");
+
+        let options: MacroOptions = syn::parse_quote!(visibility = "pub");
+        assert!(macro_exports(&name, &options, false).is_err());
+    }
+
+    #[test]
+    fn named_args() {
+        let decls = quote! { count: usize; names: Vec<String>; sep: &str = "-"; };
+        let bindings = |args: TokenStream| named_args_bindings(quote! { [#args] #decls })
+            .map(|out| out.to_string());
+        assert_eq!(
+            bindings(quote! { names = [a, b], count = 2 }),
+            Ok("let count : usize = 2 ; \
+                let names : Vec < String > = \
+                [crabtime :: stringify_if_needed ! (a) . to_string () , \
+                crabtime :: stringify_if_needed ! (b) . to_string ()] \
+                . into_iter () . collect () ; \
+                let sep : & str = \"-\" ;".to_string())
+        );
+        assert_eq!(bindings(quote! { count = 2 }), Err("Missing argument `names`.".to_string()));
+        assert_eq!(
+            bindings(quote! { count = 2, names = [], size = 3 }),
+            Err("Unknown argument `size`. Expected one of: count, names, sep.".to_string())
+        );
+        assert_eq!(
+            bindings(quote! { count = 2, count = 3, names = [] }),
+            Err("Argument `count` is given more than once.".to_string())
+        );
+        assert_eq!(
+            bindings(quote! { count = 2, names = a }),
+            Err("Argument `names` should be an array, like `[a, b]`.".to_string())
+        );
+    }
+
+    #[test]
+    fn tuple_args() {
+        let ty: syn::Type = syn::parse_quote! { (usize, &str) };
+        let (pat, code) = parse_arg_type("pair", &ty).unwrap_or_default();
+        assert_eq!(pat.to_string(), "($ pair_0_arg : literal , $ pair_1_arg : expr $ (,) ?)");
+        assert_eq!(
+            code.to_string(),
+            "($ pair_0_arg , crabtime :: stringify_if_needed ! { $ pair_1_arg } ,)"
+        );
+        let bindings = named_args_bindings(quote! { [pair = 3] pair: (usize, &str); });
+        let error = "Argument `pair` should be a tuple, like `(a, b)`.";
+        assert_eq!(bindings.err().as_deref(), Some(error));
+    }
+
+    #[test]
+    fn pattern_with_typed_args() {
+        let sig: syn::Signature = syn::parse_quote! {
+            fn gen(pattern!($name:ident): _, count: usize)
+        };
+        let parsed = parse_args(&sig.inputs)
+            .map(|(args, code)| (args.pattern().to_string(), code.to_string()));
+        let pattern = "$ name : ident , $ count_arg : literal $ (,) ?";
+        let code = "let count : usize = $ count_arg ;";
+        assert_eq!(parsed, Some((pattern.to_string(), code.to_string())));
+    }
+
+    #[test]
+    fn nested_vec_args() {
+        let ty: syn::Type = syn::parse_quote! { Vec<Vec<usize>> };
+        let (pat, code) = parse_arg_type("grid", &ty).unwrap_or_default();
+        assert_eq!(pat.to_string(), "[$ ([$ ($ grid_arg : literal) , * $ (,) ?]) , * $ (,) ?]");
+        assert_eq!(
+            code.to_string(),
+            "[$ ([$ ($ grid_arg) , *] . into_iter () . collect ()) , *] . into_iter () . collect ()"
+        );
+    }
+
+    #[test]
+    fn map_args() {
+        let ty: syn::Type = syn::parse_quote! { BTreeMap<String, usize> };
+        let (pat, _) = parse_arg_type("fields", &ty).unwrap_or_default();
+        let expected = "{ $ ($ fields_key_arg : tt : $ fields_value_arg : literal) , * $ (,) ? }";
+        assert_eq!(pat.to_string(), expected);
+        let value = quote! { { a: 1, "b": 2, } };
+        let code = arg_value_code(&ty, value).map(|code| code.to_string());
+        let expected = "[(crabtime :: stringify_if_needed ! (a) . to_string () , 1) , \
+            (crabtime :: stringify_if_needed ! (\"b\") . to_string () , 2)] \
+            . into_iter () . collect ()";
+        assert_eq!(code.as_deref(), Some(expected));
+        assert!(arg_value_code(&ty, quote! { { a => 1 } }).is_none());
+        assert!(contains_map_type(&syn::parse_quote! { (usize, Vec<HashMap<String, u8>>) }));
+    }
+
+    #[test]
+    fn default_args() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f(count: usize, #[default("Item")] prefix: String, #[default([])] tags: Vec<u8>) {}
+        };
+        let Some((Args::Pattern { str, named, defaults }, code)) = parse_args(&item.sig.inputs)
+        else { unreachable!("Typed arguments are parsed to a pattern.") };
+        assert_eq!(
+            str.to_string(),
+            "$ count_arg : literal , $ prefix_arg : expr , [$ ($ tags_arg : literal) , * $ (,) ?] \
+            $ (,) ?"
+        );
+        assert!(code.to_string().contains("let prefix : String = crabtime"), "{code}");
+        let arms = defaults.iter()
+            .map(|(pattern, code)| (pattern.to_string(), code.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(arms.len(), 2);
+        assert!(arms[0].0.starts_with("$ count_arg : literal , $ prefix_arg : expr $ (,)"));
+        assert!(arms[0].1.ends_with("let tags : Vec < u8 > = [] . into_iter () . collect () ;"));
+        assert_eq!(arms[1].0, "$ count_arg : literal $ (,) ?");
+        assert!(arms[1].1.contains("let prefix : String = crabtime :: stringify_if_needed ! \
+            (\"Item\") . to_string () ;"), "{}", arms[1].1);
+        assert!(named.is_some_and(|t| t.to_string().starts_with("count : usize ; prefix")));
+    }
+
+    #[test]
+    fn output_hash() {
+        let hash = super::output_hash("struct A { x: u32 }");
+        assert_eq!(hash, super::output_hash("struct A {\n    x : u32 // Comment.\n}"));
+        assert_ne!(hash, super::output_hash("struct A { x: u64 }"));
+        // The hash is stored in dumped files, so it must not depend on the toolchain.
+        assert_eq!(hash, "38ff1bd76a81e5e1");
+    }
+
+    #[test]
+    fn crate_sentinel() {
+        let crate_path = proc_macro2::Ident::new("krate", proc_macro2::Span::call_site());
+        let input: TokenStream = "const A: $crate::Color = { $crate::Color(1) }; $x".parse()
+            .ok().unwrap_or_default();
+        let out = replace_crate_sentinel(input, &crate_path).to_string();
+        assert_eq!(out, "const A : krate :: Color = { krate :: Color (1) } ; $ x");
+    }
+
+    #[test]
+    fn expect_items() {
+        let code = "enum A { X }\nstruct B;\nimpl B { fn f() {} }\nuse $crate::C;";
+        assert!(check_item_count(code, 4).is_ok());
+        let error = check_item_count(code, 3).err().map(|e| e.message).unwrap_or_default();
+        assert!(error.contains("Expected the macro to generate 3 items, but it generated 4."));
+        assert!(check_item_count("1 + 2", 1).is_err());
+    }
+
+    #[test]
+    fn host_rustc() {
+        let version = "rustc 1.95.0-nightly (5980761 2026-02-01)\nbinary: rustc\n\
+            host: x86_64-unknown-linux-gnu\nrelease: 1.95.0-nightly\nLLVM version: 21.1.8";
+        let host = HostRustc::parse(version);
+        assert_eq!(host.as_ref().map(|t| t.target.as_str()), Some("x86_64-unknown-linux-gnu"));
+        assert_eq!(host.map(|t| t.is_nightly), Some(true));
+        let stable = HostRustc::parse("host: aarch64-apple-darwin\nrelease: 1.94.1");
+        assert_eq!(stable.map(|t| t.is_nightly), Some(false));
+        assert_eq!(HostRustc::parse("release: 1.94.1"), None);
+    }
+
+    #[test]
+    fn features_from_rustc_args() {
+        let args = ["rustc", "--cfg", "feature=\"simd\"", "--cfg=feature=\"std\"", "--cfg", "test"];
+        let features = super::features_from_rustc_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(features, ["simd", "std"]);
+    }
+
+    #[test]
+    fn compile_target() {
+        let args = ["rustc", "--crate-name", "a", "--target", "wasm32-unknown-unknown"];
+        let target = CompileTarget::from_rustc_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(target.as_deref(), Some("wasm32-unknown-unknown"));
+        let args = ["rustc", "--target=aarch64-apple-darwin"];
+        let target = CompileTarget::from_rustc_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(target.as_deref(), Some("aarch64-apple-darwin"));
+        let cfg = CompileTarget::parse_cfg("debug_assertions\ntarget_pointer_width=\"32\"\n");
+        let expected = [("debug_assertions", ""), ("target_pointer_width", "32")]
+            .map(|(key, value)| (key.to_string(), value.to_string()));
+        assert_eq!(cfg, expected);
+    }
+
+    #[test]
+    fn duplicate_singleton_attributes() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![edition(2021)]
+                #![dependency(rand = "0.8")]
+                #![edition(2024)]
+            }
+        };
+        let error = CargoConfig::default().extract_inline_attributes(item.attrs).err();
+        let message = error.as_ref().map(|e| e.message.clone()).unwrap_or_default();
+        assert_eq!(message, "The 'edition' attribute is specified more than once.");
+        assert_eq!(error.map(|e| e.related.len()), Some(1));
+
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![edition(2021)]
+                #![resolver("2")]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        assert_eq!(cfg.resolver.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn registry_index() {
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![registry_index("sparse+https://mirror.example.com/index/")]
+            }
+        };
+        let mut cfg = CargoConfig::default();
+        assert!(cfg.extract_inline_attributes(item.attrs).is_ok());
+        let config = cfg.print_cargo_config().unwrap_or_default();
+        let table = toml::from_str::<toml::Table>(&config).ok();
+        let source = |name: &str, key: &str| table.as_ref()
+            .and_then(|t| t.get("source")?.get(name)?.get(key)?.as_str().map(str::to_string));
+        assert_eq!(source("crates-io", "replace-with").as_deref(), Some("crabtime-registry"));
+        assert_eq!(
+            source("crabtime-registry", "registry").as_deref(),
+            Some("sparse+https://mirror.example.com/index/")
+        );
+        assert!(CargoConfig::default().print_cargo_config().is_none());
+
+        let item: syn::ItemFn = syn::parse_quote! {
+            fn f() {
+                #![registry_index(mirror)]
+            }
+        };
+        assert!(CargoConfig::default().extract_inline_attributes(item.attrs).is_err());
+    }
+
+    #[test]
+    fn build_failure_details() {
+        let message = |level: &str, rendered: &str| serde_json::json!({
+            "reason": "compiler-message",
+            "message": { "level": level, "rendered": rendered },
+        });
+        let messages = [
+            message("warning", "warning: unused variable\n"),
+            message("error", "error[E0308]: mismatched types\n"),
+            serde_json::json!({ "reason": "build-finished", "success": false }),
+        ];
+        let details = super::build_failure_details(&messages, "error: could not compile");
+        assert_eq!(details, "error[E0308]: mismatched types");
+
+        let stderr = "    Updating crates.io index\nerror: no matching package named `rnd`\n";
+        let details = super::build_failure_details(&messages[2..], stderr);
+        assert_eq!(details, "error: no matching package named `rnd`");
+    }
+
+    #[test]
+    fn contains_ident() {
+        let tokens = quote! { let x = { crabtime::include_json("a.json") }; };
+        assert!(super::contains_ident(&tokens, "include_json"));
+        assert!(!super::contains_ident(&tokens, "include_str"));
+        assert!(!super::contains_ident(&quote! { "include_json" }, "include_json"));
+    }
+
+    #[cfg(not(nightly))]
+    #[test]
+    fn diagnostic_rendering() {
+        assert_eq!(render(Level::Warning, " Unused value.", false), "warning: Unused value.");
+        assert_eq!(
+            render(Level::Error, "Failed.", true),
+            "\x1b[1;31merror\x1b[0m\x1b[1m:\x1b[0m Failed."
+        );
+        assert!(color_enabled(None, Some("always")));
+        assert!(!color_enabled(None, Some("auto")));
+        assert!(!color_enabled(None, Some("never")));
+        assert!(!color_enabled(None, None));
+    }
+
+    #[cfg(not(nightly))]
+    #[test]
+    fn no_color() {
+        assert!(!color_enabled(Some("1"), Some("always")));
+        assert!(!color_enabled(Some("1"), None));
+        // An empty `NO_COLOR` is treated as not set.
+        assert!(color_enabled(Some(""), Some("always")));
+    }
+
+    #[test]
+    fn token_stream_args_setup() {
+        let ident = syn::Ident::new("input", Span::call_site());
+        let setup = Args::TokenStream { ident }.setup().to_string();
+        assert!(setup.starts_with("use crabtime :: TokenStream ;"), "{setup}");
+        let prelude = gen_prelude_for_token_stream("pm2", &[]);
+        assert!(prelude.contains("pub use pm2::TokenStream;"), "{prelude}");
+    }
+
+    #[test]
+    fn impl_output_wrapper() {
+        let wrapper = |tp: &str| super::impl_output_wrapper(&syn::parse_str(tp).unwrap());
+        assert_eq!(wrapper("String").ok(), Some(None));
+        assert_eq!(wrapper("impl Iterator<Item = String>").ok(), Some(Some("OutputLines")));
+        assert_eq!(wrapper("impl DoubleEndedIterator<Item = u8>").ok(), Some(Some("OutputLines")));
+        assert_eq!(wrapper("impl std::fmt::Display + Clone").ok(), Some(Some("OutputDisplay")));
+        assert!(wrapper("impl Clone").is_err());
+    }
+
+    #[test]
+    fn format_code() {
+        // Skipped if `rustfmt` is not installed.
+        let code = "struct   A{x:u32,y : Vec<u8>}  fn f(){}";
+        if let Some(code) = super::format_code(code, DEFAULT_EDITION) {
+            assert_eq!(code, "struct A {\n    x: u32,\n    y: Vec<u8>,\n}\nfn f() {}\n");
+            // `gen` is a keyword in the 2024 edition only.
+            let code = "fn gen(){}";
+            assert_eq!(super::format_code(code, "2021").as_deref(), Some("fn gen() {}\n"));
+            assert_eq!(super::format_code(code, "2024"), None);
+        }
+        assert_eq!(super::format_code("struct {", DEFAULT_EDITION), None);
+    }
+
+    #[test]
+    fn clippy_diagnostics() {
+        let message = |level: &str, code: &str| serde_json::json!({
+            "reason": "compiler-message",
+            "message": { "level": level, "code": { "code": code }, "rendered": format!("{code}\n") }
+        });
+        let messages = [
+            message("warning", "clippy::needless_return"),
+            message("error", "clippy::ptr_arg"),
+            message("error", "E0412"),
+            serde_json::json!({ "reason": "build-finished", "success": false }),
+        ];
+        let issues = super::clippy_diagnostics(&messages).into_iter()
+            .map(|issue| (matches!(issue.level, Level::Error), issue.message))
+            .collect::<Vec<_>>();
+        assert_eq!(issues, [
+            (false, "clippy::needless_return".to_string()),
+            (true, "clippy::ptr_arg".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn run_clippy() {
+        let pid = std::process::id();
+        let project_dir = std::env::temp_dir().join(format!("crabtime-clippy-{pid}"));
+        let mut cfg = CargoConfig { name: Some("clippy_test".to_string()), ..Default::default() };
+        cfg.lints.clippy = r#"ptr_arg = "deny""#.to_string();
+        let lib = "pub fn len(v: &Vec<u8>) -> usize { v.len() }\n";
+        let issues = super::run_clippy(&project_dir, &cfg, lib);
+        fs::remove_dir_all(&project_dir).ok();
+        // Skipped if clippy is not installed.
+        if let Ok(issues) = issues {
+            assert_eq!(issues.len(), 1);
+            assert!(issues.iter().all(|issue| matches!(issue.level, Level::Error)));
+            assert!(issues.iter().all(|issue| issue.message.contains("ptr_arg")));
+        }
+    }
+
+    #[test]
+    fn no_std_check() {
+        let pid = std::process::id();
+        let project_dir = std::env::temp_dir().join(format!("crabtime-no-std-{pid}"));
+        let cfg = CargoConfig { name: Some("no_std_test".to_string()), ..Default::default() };
+        let lib = "#![no_std]\nextern crate alloc;\n\
+            pub fn f() -> alloc::vec::Vec<Missing> { alloc::vec::Vec::new() }\n\
+            pub fn g() { std::process::exit(0) }\n\
+            pub fn h() { println!(\"h\") }\n";
+        let messages = run_scratch_project(&project_dir, &cfg, lib, "check", "check");
+        fs::remove_dir_all(&project_dir).ok();
+        let errors = messages.map(|messages| no_std_errors(&messages)).unwrap_or_default();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(errors.iter().any(|error| error.contains("`std`")), "{errors:?}");
+        assert!(errors.iter().any(|error| error.contains("println")), "{errors:?}");
+    }
+
+    #[test]
+    fn format_args_with_exprs() {
+        let args = |code: &str| super::format_args_with_exprs(&print(code)).to_string();
+        assert_eq!(args("struct {{name}};"), r#"" struct  {name}; ""#);
+        assert_eq!(args("const N: usize = {{items.len()}};"),
+            r#"" const  N : usize = {}; " , items . len ()"#);
+        assert_eq!(args("fn f() { {{body}} }"), r#"" fn  f () {{ {body} }} ""#);
+        assert_eq!(args("{{value:?}}"), r#""{value:?} ""#);
+        assert_eq!(args("impl A { {{a}} x {{b}} }"), r#"" impl  A {{{a} x {b}}} ""#);
+        let nested = "mod m { {{ format!(\"{}\", 1) }} }";
+        assert_eq!(args(nested), r#"" mod  m {{ {} }} " , format ! ("{}" , 1)"#);
+    }
+
+    #[test]
+    fn output_syntax() {
+        assert!(validate_output_syntax("struct A; impl A { fn f() {} }", "gen").is_ok());
+        assert!(validate_output_syntax("let x = 1; x + 1", "gen").is_ok());
+        assert!(validate_output_syntax("1 + 2", "gen").is_ok());
+        assert!(validate_output_syntax("Vec<u32>", "gen").is_ok());
+        assert!(validate_output_syntax("Some(_) | None", "gen").is_ok());
+        assert!(validate_output_syntax("use $crate::A;", "gen").is_ok());
+        let code = "struct A;\nstruct B;\nfn f() -> { }\nstruct C;";
+        let error = validate_output_syntax(code, "gen_test").err().map(|e| e.message);
+        let error = error.unwrap_or_default();
+        assert!(error.contains("(line 3, column 11)"), "{error}");
+        let snippet = "2 | struct B;\n3 | fn f() -> { }\n  |           ^\n4 | struct C;";
+        assert!(error.contains(snippet), "{error}");
+        assert!(error.contains("The full generated code was written to"), "{error}");
+    }
+}
@@ -1,5 +1,6 @@
 use crate::error::*;
 use std::path::Path;
+use std::path::PathBuf;
 
 pub fn parent(path: &Path) -> Result<&Path> {
     path.parent().context(|| error!("Path '{}' does not have a parent.", path.display()))
@@ -13,4 +14,18 @@ pub fn find_parent<'t>(path: &'t Path, dir_name: &str) -> Result<&'t Path> {
             "Path '{}' does not have parent '{dir_name}' directory.",
             path.display()
         ))
-}
\ No newline at end of file
+}
+
+/// Paths of the directory entries. Returns an empty list if the directory cannot be read.
+pub fn read_dir(path: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(path).into_iter().flatten().flatten().map(|entry| entry.path()).collect()
+}
+
+/// Removes a file or a directory with its contents, ignoring errors.
+pub fn remove(path: &Path) {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path).ok();
+    } else {
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,16 @@
+// === Rust Stable Library Test ===
+
+pub struct Color(pub u8, pub u8, pub u8);
+
+/// Generates color constants referring to `Color` via `$crate`, so they can be used from other
+/// crates.
+#[crabtime::function]
+#[macro_export]
+fn gen_colors(names: Vec<String>) {
+    for (ix, name) in names.iter().enumerate() {
+        let value = ix * 100;
+        crabtime::output! {
+            pub const {{name}}: $crate::Color = $crate::Color({{value}}, 0, 0);
+        }
+    }
+}
@@ -14,6 +14,26 @@ fn gen_positions(components: Vec<String>) {
 }
 gen_positions!(["X", "Y", "Z", "W"]);
 
+crabtime_test_stable_lib::gen_colors!(["BLACK", "RED"]);
+
+// Unstable features switch the generated project to the nightly toolchain. It has to be installed,
+// so the test is enabled with the `nightly-toolchain` feature.
+#[cfg(feature = "nightly-toolchain")]
+#[crabtime::function]
+fn gen_unstable_feature() {
+    #![feature(never_type)]
+    let never: Option<!> = None;
+    let count = never.iter().count();
+    crabtime::output! {
+        const UNSTABLE_FEATURE_COUNT: usize = {{count}};
+    }
+}
+#[cfg(feature = "nightly-toolchain")]
+gen_unstable_feature!();
+
 fn main() {
     let _p1 = Position2::X;
+    assert_eq!(RED.0, 100);
+    #[cfg(feature = "nightly-toolchain")]
+    assert_eq!(UNSTABLE_FEATURE_COUNT, 0);
 }